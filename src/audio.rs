@@ -0,0 +1,110 @@
+mod resampler;
+#[cfg(feature = "audio")]
+mod cpal_sink;
+
+pub use self::resampler::Resampler;
+#[cfg(feature = "audio")]
+pub use self::cpal_sink::CpalSink;
+
+use crate::gameboy::AUDIO_SAMPLE_RATE;
+
+const QUEUE_CHUNK_FRAMES: usize = 256;     //How many resampled frames we hold on to before handing them to the sink
+
+/**
+ * Somewhere for resampled audio to go. Samples are stereo (left, right)
+ * frames at sample_rate
+ */
+pub trait AudioSink {
+    fn sample_rate(&self) -> u32;
+
+    fn queue(&mut self, frames: &[(f32, f32)]);
+
+    /**
+     * How many frames are still waiting to be played. None if the sink
+     * doesn't play anything in real time, so it can't be used for pacing
+     */
+    fn queued_frames(&self) -> Option<usize>;
+}
+
+/**
+ * Throws every sample away. Used when there's no audio device, like when
+ * running headless on CI
+ */
+pub struct NullSink {
+    sample_rate: u32,
+}
+
+impl NullSink {
+    pub fn new(sample_rate: u32) -> Self {
+        Self { sample_rate }
+    }
+}
+
+impl Default for NullSink {
+    fn default() -> Self {
+        Self::new(48000)
+    }
+}
+
+impl AudioSink for NullSink {
+    fn sample_rate(&self) -> u32 {
+        return self.sample_rate;
+    }
+
+    fn queue(&mut self, _frames: &[(f32, f32)]) {}
+
+    fn queued_frames(&self) -> Option<usize> {
+        return None;
+    }
+}
+
+/**
+ * Takes the samples coming out of the apu, resamples them to the rate of
+ * the sink and queues them up on it
+ */
+pub struct AudioOutput {
+    resampler: Resampler,
+    sink: Box<dyn AudioSink>,
+    pending: Vec<(f32, f32)>,
+}
+
+impl AudioOutput {
+    pub fn new(sink: Box<dyn AudioSink>) -> Self {
+        Self {
+            resampler: Resampler::new(AUDIO_SAMPLE_RATE, sink.sample_rate()),
+            sink,
+            pending: Vec::with_capacity(QUEUE_CHUNK_FRAMES * 2),
+        }
+    }
+
+    pub fn push_sample(&mut self, left: f32, right: f32) {
+        self.resampler.push(left, right, &mut self.pending);
+        if self.pending.len() >= QUEUE_CHUNK_FRAMES {
+            self.sink.queue(&self.pending);
+            self.pending.clear();
+        }
+    }
+
+    /**
+     * Running the Game Boy faster than real time. The samples get squeezed
+     * together so the audio keeps up, which raises the pitch
+     */
+    pub fn set_speed(&mut self, multiplier: u32) {
+        let input_rate = AUDIO_SAMPLE_RATE * multiplier;
+        if input_rate != self.resampler.input_rate() {
+            self.resampler = Resampler::new(input_rate, self.sink.sample_rate());
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        return self.sink.sample_rate();
+    }
+
+    /**
+     * How far ahead of the speakers the emulator is, in frames. None if the
+     * sink doesn't play in real time
+     */
+    pub fn queued_frames(&self) -> Option<usize> {
+        return self.sink.queued_frames().map(|queued| queued + self.pending.len());
+    }
+}
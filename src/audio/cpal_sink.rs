@@ -0,0 +1,91 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{FromSample, SampleFormat, SizedSample, Stream, StreamConfig};
+
+use crate::error::Error;
+use super::AudioSink;
+
+/**
+ * Plays audio through the default output device of the host. The device
+ * callback pulls frames off a queue that the emulator fills up
+ */
+pub struct CpalSink {
+    _stream: Stream,    //Audio stops once this gets dropped
+    sample_rate: u32,
+    queue: Arc<Mutex<VecDeque<(f32, f32)>>>,
+}
+
+impl CpalSink {
+    pub fn new() -> Result<Self, Error> {
+        let device = cpal::default_host()
+            .default_output_device()
+            .ok_or_else(|| Error::AudioDevice("no output device".to_string()))?;
+        let supported_config = device.default_output_config().map_err(|e| Error::AudioDevice(e.to_string()))?;
+        let sample_format = supported_config.sample_format();
+        let config = supported_config.config();
+        let sample_rate = config.sample_rate.0;
+
+        let queue = Arc::new(Mutex::new(VecDeque::new()));
+        let stream = match sample_format {
+            SampleFormat::F32 => build_stream::<f32>(&device, &config, queue.clone()),
+            SampleFormat::I16 => build_stream::<i16>(&device, &config, queue.clone()),
+            SampleFormat::U16 => build_stream::<u16>(&device, &config, queue.clone()),
+            format => return Err(Error::AudioDevice(format!("unsupported sample format {format}"))),
+        }?;
+        stream.play().map_err(|e| Error::AudioDevice(e.to_string()))?;
+
+        Ok(Self {
+            _stream: stream,
+            sample_rate,
+            queue,
+        })
+    }
+}
+
+/**
+ * Filling the device buffer from the queue. If we run dry the rest gets
+ * filled with silence
+ */
+fn build_stream<T>(device: &cpal::Device, config: &StreamConfig, queue: Arc<Mutex<VecDeque<(f32, f32)>>>) -> Result<Stream, Error>
+where
+    T: SizedSample + FromSample<f32>,
+{
+    let channels = config.channels as usize;
+    let stream = device.build_output_stream(
+        config,
+        move |data: &mut [T], _| {
+            let mut queue = queue.lock().unwrap();
+            for frame in data.chunks_mut(channels) {
+                let (left, right) = queue.pop_front().unwrap_or((0.0, 0.0));
+                for (channel, sample) in frame.iter_mut().enumerate() {
+                    let value = match (channels, channel) {
+                        (1, _) => (left + right) / 2.0,
+                        (_, 0) => left,
+                        (_, 1) => right,
+                        _ => 0.0,
+                    };
+                    *sample = T::from_sample(value);
+                }
+            }
+        },
+        |e| eprintln!("Audio stream error: {e}"),
+        None,
+    ).map_err(|e| Error::AudioDevice(e.to_string()))?;
+    return Ok(stream);
+}
+
+impl AudioSink for CpalSink {
+    fn sample_rate(&self) -> u32 {
+        return self.sample_rate;
+    }
+
+    fn queue(&mut self, frames: &[(f32, f32)]) {
+        self.queue.lock().unwrap().extend(frames);
+    }
+
+    fn queued_frames(&self) -> Option<usize> {
+        return Some(self.queue.lock().unwrap().len());
+    }
+}
@@ -0,0 +1,143 @@
+use std::f64::consts::PI;
+
+const ZERO_CROSSINGS: usize = 16;   //How many lobes of the sinc we keep on each side
+const PHASES: usize = 128;          //How finely the kernel gets split between input samples
+const PASSBAND: f64 = 0.9;          //Where the cutoff sits as a fraction of the output nyquist
+
+/**
+ * Windowed sinc resampler. Low passes the input below the output nyquist
+ * frequency while interpolating, so the harsh square waves of the Game Boy
+ * don't alias back down into the audible range
+ */
+pub struct Resampler {
+    input_rate: u32,
+    output_rate: u32,
+    taps: usize,
+    kernels: Vec<f32>,          //PHASES + 1 rows of taps coefficients
+    history: Vec<(f32, f32)>,   //The last taps samples, stored twice over so they can always be read as one slice
+    history_index: usize,
+    offset: f64,                //Where the next output sample lands between the last two input samples
+}
+
+impl Resampler {
+    pub fn new(input_rate: u32, output_rate: u32) -> Self {
+        //Cycles per input sample, halfway would be the input nyquist
+        let cutoff = 0.5 * PASSBAND * (output_rate as f64 / input_rate as f64).min(1.0);
+        let half_width = (ZERO_CROSSINGS as f64 / (2.0 * cutoff)).ceil() as usize;
+        let taps = half_width * 2;
+
+        let mut kernels = Vec::with_capacity((PHASES + 1) * taps);
+        for phase in 0..=PHASES {
+            let frac = phase as f64 / PHASES as f64;
+            let row = (0..taps).map(|tap| {
+                let x = half_width as f64 - 1.0 - tap as f64 + frac;
+                windowed_sinc(x, cutoff, half_width as f64)
+            }).collect::<Vec<f64>>();
+
+            //Normalizing each row so there's no change in volume between phases
+            let sum: f64 = row.iter().sum();
+            kernels.extend(row.iter().map(|coefficient| (coefficient / sum) as f32));
+        }
+
+        Self {
+            input_rate,
+            output_rate,
+            taps,
+            kernels,
+            history: vec![(0.0, 0.0); taps * 2],
+            history_index: 0,
+            offset: 0.0,
+        }
+    }
+
+    pub fn input_rate(&self) -> u32 {
+        return self.input_rate;
+    }
+
+    /**
+     * Feeding in one stereo sample. Any output samples that are now ready
+     * get appended to output
+     */
+    pub fn push(&mut self, left: f32, right: f32, output: &mut Vec<(f32, f32)>) {
+        self.history[self.history_index] = (left, right);
+        self.history[self.history_index + self.taps] = (left, right);
+        self.history_index = (self.history_index + 1) % self.taps;
+
+        let step = self.input_rate as f64 / self.output_rate as f64;
+        while self.offset < 1.0 {
+            output.push(self.interpolate(self.offset));
+            self.offset += step;
+        }
+        self.offset -= 1.0;
+    }
+
+    /**
+     * Convolving the history, oldest sample first, with the kernel closest to
+     * where the output sample falls
+     */
+    fn interpolate(&self, frac: f64) -> (f32, f32) {
+        let phase = (frac * PHASES as f64).round() as usize;
+        let kernel = &self.kernels[phase * self.taps..(phase + 1) * self.taps];
+        let history = &self.history[self.history_index..self.history_index + self.taps];
+
+        let mut left = 0.0;
+        let mut right = 0.0;
+        for (coefficient, sample) in kernel.iter().zip(history) {
+            left += coefficient * sample.0;
+            right += coefficient * sample.1;
+        }
+        return (left, right);
+    }
+}
+
+/**
+ * Sinc low pass at cutoff, tapered off with a Blackman window so the kernel
+ * can be cut off at half_width
+ */
+fn windowed_sinc(x: f64, cutoff: f64, half_width: f64) -> f64 {
+    let sinc = if x == 0.0 {
+        2.0 * cutoff
+    } else {
+        (2.0 * PI * cutoff * x).sin() / (PI * x)
+    };
+
+    let position = (x / half_width + 1.0) / 2.0;   //0.0 to 1.0 across the window
+    let window = 0.42 - 0.5 * (2.0 * PI * position).cos() + 0.08 * (4.0 * PI * position).cos();
+    return sinc * window.max(0.0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resample(resampler: &mut Resampler, input: impl Iterator<Item = f32>) -> Vec<f32> {
+        let mut output = vec![];
+        for sample in input {
+            resampler.push(sample, sample, &mut output);
+        }
+        return output.iter().map(|(left, _)| *left).collect();
+    }
+
+    #[test]
+    fn outputs_at_the_host_rate() {
+        let mut resampler = Resampler::new(131072, 48000);
+        let output = resample(&mut resampler, std::iter::repeat_n(0.5, 131072));
+        assert!((output.len() as i64 - 48000).abs() <= 1);
+
+        //After the kernel fills up a constant input comes out unchanged
+        assert!(output[1000..].iter().all(|sample| (sample - 0.5).abs() < 0.001));
+    }
+
+    #[test]
+    fn filters_out_frequencies_above_nyquist() {
+        let tone = |frequency: f64| (0..131072).map(move |idx| (2.0 * PI * frequency * idx as f64 / 131072.0).sin() as f32);
+        let peak = |output: Vec<f32>| output[1000..].iter().fold(0.0f32, |peak, sample| peak.max(sample.abs()));
+
+        let mut resampler = Resampler::new(131072, 48000);
+        assert!(peak(resample(&mut resampler, tone(1000.0))) > 0.95);
+
+        //Would alias down to 8kHz without the low pass
+        let mut resampler = Resampler::new(131072, 48000);
+        assert!(peak(resample(&mut resampler, tone(40000.0))) < 0.01);
+    }
+}
@@ -0,0 +1,382 @@
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use crate::disassembler::{self, Instruction};
+use crate::gameboy::{Gameboy, WatchHit};
+
+const SWITCHABLE_BANK: std::ops::RangeInclusive<u16> = 0x4000..=0x7FFF;
+const MAX_INSTRUCTION_LEN: u16 = 3;
+const RETURN_OPCODES: [u8; 6] = [0xC9, 0xD9, 0xC0, 0xC8, 0xD0, 0xD8];
+
+/**
+ * Stop right before the instruction at address runs. A bank only matters
+ * for addresses in the switchable ROM bank
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Breakpoint {
+    pub address: u16,
+    pub bank: Option<usize>,
+}
+
+impl Breakpoint {
+    fn is_hit(&self, gameboy: &Gameboy, pc: u16) -> bool {
+        if pc != self.address {
+            return false;
+        }
+        return match self.bank {
+            Some(bank) if SWITCHABLE_BANK.contains(&pc) => gameboy.rom_bank() == bank,
+            _ => true,
+        };
+    }
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.bank {
+            Some(bank) => write!(f, "{bank:02X}:{:04X}", self.address),
+            None => write!(f, "{:04X}", self.address),
+        }
+    }
+}
+
+/**
+ * Why the Game Boy stopped running
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub enum StopReason {
+    Breakpoint(Breakpoint),
+    Watchpoint(Vec<WatchHit>),      //Everything the last instruction set off
+    Interrupted,        //Somebody asked us to stop, like with Ctrl-C
+    Stepped,            //Finished what a step asked for
+}
+
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StopReason::Breakpoint(breakpoint) => write!(f, "Hit breakpoint at {breakpoint}"),
+            StopReason::Watchpoint(hits) => {
+                let hits: Vec<String> = hits.iter().map(|hit| hit.to_string()).collect();
+                write!(f, "{}", hits.join("\n"))
+            },
+            StopReason::Interrupted => write!(f, "Interrupted"),
+            StopReason::Stepped => write!(f, "Stepped"),
+        }
+    }
+}
+
+/**
+ * Runs a Game Boy while keeping an eye out for breakpoints and watchpoints.
+ * Everything gets checked between instructions, so stopping never changes
+ * how the game runs. Watchpoints live in the Gameboy itself, since memory
+ * is what has to check them
+ */
+pub struct Debugger {
+    breakpoints: Vec<Breakpoint>,
+    interrupt: Arc<AtomicBool>,
+}
+
+impl Default for Debugger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Self {
+            breakpoints: Vec::new(),
+            interrupt: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /**
+     * Setting this to true makes the Game Boy stop before its next
+     * instruction. It's safe to set from another thread or a signal handler
+     */
+    pub fn interrupt_flag(&self) -> Arc<AtomicBool> {
+        return self.interrupt.clone();
+    }
+
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) {
+        if !self.breakpoints.contains(&breakpoint) {
+            self.breakpoints.push(breakpoint);
+        }
+    }
+
+    pub fn remove_breakpoint(&mut self, breakpoint: Breakpoint) -> bool {
+        let len = self.breakpoints.len();
+        self.breakpoints.retain(|&other| other != breakpoint);
+        return self.breakpoints.len() != len;
+    }
+
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        return &self.breakpoints;
+    }
+
+    fn check(&self, gameboy: &Gameboy) -> Option<StopReason> {
+        if let Some(reason) = watch_stop(gameboy) {
+            return Some(reason);
+        }
+        if self.interrupt.swap(false, Ordering::Relaxed) {
+            return Some(StopReason::Interrupted);
+        }
+        let pc = gameboy.registers().pc;
+        return self.breakpoints.iter()
+            .find(|breakpoint| breakpoint.is_hit(gameboy, pc))
+            .map(|&breakpoint| StopReason::Breakpoint(breakpoint));
+    }
+
+    /**
+     * Running a frame like Gameboy::run_frame. Returns why we stopped if it
+     * was before the end of the frame
+     */
+    pub fn run_frame(&mut self, gameboy: &mut Gameboy) -> Option<StopReason> {
+        let mut reason = None;
+        gameboy.run_frame_until(|gameboy| {
+            reason = self.check(gameboy);
+            reason.is_some()
+        });
+        return reason;
+    }
+
+    /**
+     * Running until done says so, a breakpoint or watchpoint gets hit or we're
+     * interrupted. Frames keep getting finished along the way
+     */
+    fn run_until(&mut self, gameboy: &mut Gameboy, mut done: impl FnMut(&Gameboy) -> bool) -> StopReason {
+        //The instruction we're sitting in front of has to run before anything counts
+        let start_pc = gameboy.registers().pc;
+        let mut skip_start = gameboy.is_at_unchecked_instruction();
+        let mut reason = None;
+        while !gameboy.run_frame_until(|gameboy| {
+            if std::mem::take(&mut skip_start) && gameboy.registers().pc == start_pc {
+                return false;
+            }
+            //Watchpoints go first so the last step of something doesn't swallow them
+            reason = watch_stop(gameboy).or_else(|| match done(gameboy) {
+                true => Some(StopReason::Stepped),
+                false => self.check(gameboy),
+            });
+            reason.is_some()
+        }) {}
+        return reason.expect("only stops with a reason");
+    }
+
+    pub fn step_into(&mut self, gameboy: &mut Gameboy) -> StopReason {
+        return self.run_until(gameboy, |_| true);
+    }
+
+    /**
+     * Calls and rsts get run all the way through until they return, anything
+     * else is the same as stepping into it
+     */
+    pub fn step_over(&mut self, gameboy: &mut Gameboy) -> StopReason {
+        let registers = gameboy.registers();
+        let instruction = instruction_at(gameboy, registers.pc);
+        if !is_call(instruction.bytes[0]) {
+            return self.step_into(gameboy);
+        }
+
+        //Checking the stack too so recursive calls back through here don't count
+        let return_address = registers.pc.wrapping_add(instruction.len() as u16);
+        return self.run_until(gameboy, |gameboy| {
+            let now = gameboy.registers();
+            now.pc == return_address && now.sp >= registers.sp
+        });
+    }
+
+    /**
+     * Running until the current function returns to whatever called it
+     */
+    pub fn step_out(&mut self, gameboy: &mut Gameboy) -> StopReason {
+        let start_sp = gameboy.registers().sp;
+        let mut opcode = gameboy.read_byte(gameboy.registers().pc);
+        return self.run_until(gameboy, |gameboy| {
+            let returned = RETURN_OPCODES.contains(&opcode) && gameboy.registers().sp > start_sp;
+            opcode = gameboy.read_byte(gameboy.registers().pc);
+            returned
+        });
+    }
+}
+
+fn watch_stop(gameboy: &Gameboy) -> Option<StopReason> {
+    let hits = gameboy.take_watch_hits();
+    return (!hits.is_empty()).then_some(StopReason::Watchpoint(hits));
+}
+
+fn is_call(opcode: u8) -> bool {
+    return matches!(opcode, 0xCD | 0xC4 | 0xCC | 0xD4 | 0xDC) || opcode & 0xC7 == 0xC7;
+}
+
+/**
+ * Decoding whatever is at address in memory right now
+ */
+pub fn instruction_at(gameboy: &Gameboy, address: u16) -> Instruction {
+    let bytes: Vec<u8> = (0..MAX_INSTRUCTION_LEN).map(|i| gameboy.read_byte(address.wrapping_add(i))).collect();
+    return disassembler::decode(&bytes, address);
+}
+
+/**
+ * A few instructions before address and count after it. Going backwards is
+ * a guess, since we can't tell where instructions start. We take the earliest
+ * starting point that decodes cleanly into address
+ */
+pub fn instructions_around(gameboy: &Gameboy, address: u16, before: usize, count: usize) -> Vec<Instruction> {
+    let mut instructions = Vec::new();
+    for back in (1..=(before as u16 * MAX_INSTRUCTION_LEN)).rev() {
+        let mut at = address.wrapping_sub(back);
+        let mut decoded = Vec::new();
+        while at != address && address.wrapping_sub(at) <= back {
+            let instruction = instruction_at(gameboy, at);
+            at = at.wrapping_add(instruction.len() as u16);
+            decoded.push(instruction);
+        }
+        if at == address {
+            let skip = decoded.len().saturating_sub(before);
+            instructions = decoded.split_off(skip);
+            break;
+        }
+    }
+
+    let mut at = address;
+    for _ in 0..count {
+        let instruction = instruction_at(gameboy, at);
+        at = at.wrapping_add(instruction.len() as u16);
+        instructions.push(instruction);
+    }
+    return instructions;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game_cartridge::header::test_rom;
+    use crate::gameboy::{Watchpoint, WatchKind};
+
+    /**
+     * Runs code from 0x150, after the boot rom
+     */
+    fn gameboy_running(code: &[u8]) -> Gameboy {
+        let mut gameboy = Gameboy::new();
+        gameboy.load_rom(&test_rom(code)).unwrap();
+        return gameboy;
+    }
+
+    /**
+     * Calls a function at 0x160 from 0x150 and then spins
+     */
+    fn calling_gameboy() -> Gameboy {
+        let mut code = vec![0xCD, 0x60, 0x01, 0x00, 0x18, 0xFE];     //CALL 0x160; NOP; JR to itself
+        code.resize(0x10, 0x00);
+        code.extend([0x00, 0x00, 0xC9]);     //NOP; NOP; RET
+        return gameboy_running(&code);
+    }
+
+    #[test]
+    fn stops_at_breakpoints() {
+        let mut gameboy = calling_gameboy();
+        let mut debugger = Debugger::new();
+        let breakpoint = Breakpoint { address: 0x161, bank: None };
+        debugger.add_breakpoint(breakpoint);
+
+        assert_eq!(debugger.run_frame(&mut gameboy), Some(StopReason::Breakpoint(breakpoint)));
+        assert_eq!(gameboy.registers().pc, 0x161);
+
+        //Picking back up goes past it, and the game never comes back
+        assert!(debugger.remove_breakpoint(breakpoint));
+        assert_eq!(debugger.run_frame(&mut gameboy), None);
+    }
+
+    #[test]
+    fn stops_at_interrupt_vectors() {
+        //LD A, 1; LDH (0xFF), A; EI; JR to itself, so the only way out is VBlank
+        let mut gameboy = gameboy_running(&[0x3E, 0x01, 0xE0, 0xFF, 0xFB, 0x18, 0xFE]);
+        let mut debugger = Debugger::new();
+        let breakpoint = Breakpoint { address: 0x40, bank: None };
+        debugger.add_breakpoint(breakpoint);
+
+        assert_eq!(debugger.run_frame(&mut gameboy), Some(StopReason::Breakpoint(breakpoint)));
+        assert_eq!(gameboy.registers().pc, 0x40);
+        assert_eq!(gameboy.registers().sp, 0xFFFC);
+    }
+
+    #[test]
+    fn steps_over_into_and_out() {
+        let mut gameboy = calling_gameboy();
+        let mut debugger = Debugger::new();
+        debugger.step_into(&mut gameboy);
+        debugger.step_into(&mut gameboy);
+        assert_eq!(gameboy.registers().pc, 0x150);
+
+        assert_eq!(debugger.step_over(&mut gameboy), StopReason::Stepped);
+        assert_eq!(gameboy.registers().pc, 0x153);
+
+        let mut gameboy = calling_gameboy();
+        debugger.add_breakpoint(Breakpoint { address: 0x150, bank: None });
+        debugger.run_frame(&mut gameboy);
+        debugger.step_into(&mut gameboy);
+        assert_eq!(gameboy.registers().pc, 0x160);
+        assert_eq!(debugger.step_out(&mut gameboy), StopReason::Stepped);
+        assert_eq!(gameboy.registers().pc, 0x153);
+    }
+
+    #[test]
+    fn disassembles_around_pc() {
+        let gameboy = calling_gameboy();
+        let texts: Vec<String> = instructions_around(&gameboy, 0x153, 1, 2).into_iter().map(|instruction| instruction.text).collect();
+        assert_eq!(texts, ["call $0160", "nop", "jr $0154"]);
+    }
+
+    #[test]
+    fn stops_after_watched_accesses() {
+        //LD A, 0x81; LDH (0x40), A twice; LDH A, (0x44); JR to itself
+        let mut gameboy = gameboy_running(&[0x3E, 0x81, 0xE0, 0x40, 0xE0, 0x40, 0xF0, 0x44, 0x18, 0xFE]);
+        let mut debugger = Debugger::new();
+        let lcdc_writes = Watchpoint { start: 0xFF40, end: 0xFF40, kind: WatchKind::Write };
+        let lcdc_changes = Watchpoint { kind: WatchKind::Change, ..lcdc_writes };
+        let ly_reads = Watchpoint { start: 0xFF44, end: 0xFF45, kind: WatchKind::Read };
+        for watchpoint in [lcdc_writes, lcdc_changes, ly_reads] {
+            gameboy.add_watchpoint(watchpoint);
+        }
+
+        let hit = |watchpoint, pc, opcode, old, new| WatchHit { watchpoint, address: watchpoint.start, pc, opcode, old, new };
+        let Some(StopReason::Watchpoint(hits)) = debugger.run_frame(&mut gameboy) else { panic!("should stop at the first write") };
+        assert_eq!(hits, [hit(lcdc_writes, 0x152, 0xE0, 0x90, 0x81), hit(lcdc_changes, 0x152, 0xE0, 0x90, 0x81)]);
+        assert_eq!(gameboy.registers().pc, 0x154);
+
+        //Writing the same thing again isn't a change
+        assert_eq!(debugger.step_into(&mut gameboy), StopReason::Watchpoint(vec![hit(lcdc_writes, 0x154, 0xE0, 0x81, 0x81)]));
+
+        //Looking at memory from outside doesn't count as reading it
+        gameboy.read_byte(0xFF44);
+        let Some(StopReason::Watchpoint(hits)) = debugger.run_frame(&mut gameboy) else { panic!("should stop at the read") };
+        assert_eq!((hits.len(), hits[0].address, hits[0].pc), (1, 0xFF44, 0x156));
+
+        for watchpoint in [lcdc_writes, lcdc_changes, ly_reads] {
+            assert!(gameboy.remove_watchpoint(watchpoint));
+        }
+        assert!(gameboy.watchpoints().is_empty());
+        assert_eq!(debugger.run_frame(&mut gameboy), None);
+    }
+
+    #[test]
+    fn dma_doesnt_set_off_watchpoints() {
+        //Running from HRAM since that's all the cpu can get to during DMA. LD A, 0xC0;
+        //LDH (0x46), A to copy 0xC000 - 0xC09F into OAM; JR to itself
+        let mut gameboy = gameboy_running(&[0xC3, 0x80, 0xFF]);
+        for (offset, byte) in [0x3E, 0xC0, 0xE0, 0x46, 0x18, 0xFE].into_iter().enumerate() {
+            gameboy.write_byte(0xFF80 + offset as u16, byte);
+        }
+        let mut debugger = Debugger::new();
+        let dma_writes = Watchpoint { start: 0xFF46, end: 0xFF46, kind: WatchKind::Write };
+        gameboy.add_watchpoint(dma_writes);
+        gameboy.add_watchpoint(Watchpoint { start: 0xC000, end: 0xC09F, kind: WatchKind::Read });
+        gameboy.add_watchpoint(Watchpoint { start: 0xFE00, end: 0xFE9F, kind: WatchKind::Write });
+
+        //Only the cpu starting the transfer counts
+        let Some(StopReason::Watchpoint(hits)) = debugger.run_frame(&mut gameboy) else { panic!("should stop at the DMA write") };
+        assert_eq!((hits.len(), hits[0].watchpoint, hits[0].pc), (1, dma_writes, 0xFF82));
+        assert_eq!(debugger.run_frame(&mut gameboy), None);
+    }
+}
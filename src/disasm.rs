@@ -0,0 +1,80 @@
+use std::fs;
+
+use bintboy::disassembler;
+
+const ROM_BANK_SIZE: usize = 0x4000;
+const SWITCHABLE_BANK_START: u16 = 0x4000;
+const ROM_END: u16 = 0x7FFF;
+
+/**
+ * Entry point of the disasm subcommand. With just a bank the whole bank gets
+ * disassembled. With an address range, anything from 0x4000 up comes out of
+ * bank (1 if it isn't given), like the cpu would see with that bank switched
+ * in. Instructions never run past the end of a bank
+ */
+pub fn print_disassembly(rom_file_path: &str, bank: Option<usize>, start: Option<u16>, end: Option<u16>) {
+    let rom = match fs::read(rom_file_path) {
+        Ok(rom) => rom,
+        Err(e) => {
+            eprintln!("Error reading {rom_file_path}: {e}");
+            std::process::exit(1);
+        },
+    };
+    let num_of_banks = rom.len().div_ceil(ROM_BANK_SIZE);
+
+    let (start, end) = match (start, end) {
+        (None, None) => {
+            let bank = bank.unwrap_or(0);
+            let start = if bank == 0 { 0 } else { SWITCHABLE_BANK_START };
+            (start, start + (ROM_BANK_SIZE - 1) as u16)
+        },
+        (start, end) => (start.unwrap_or(0), end.unwrap_or(ROM_END)),
+    };
+    if start > end || end > ROM_END {
+        exit_with_error(&format!("can only disassemble ROM, from 0x0000 to 0x{ROM_END:04X}"));
+    }
+
+    let mut segments = Vec::new();
+    if start < SWITCHABLE_BANK_START {
+        segments.push((0, start, end.min(SWITCHABLE_BANK_START - 1)));
+    }
+    if end >= SWITCHABLE_BANK_START {
+        let bank = bank.unwrap_or(1);
+        if bank == 0 {
+            exit_with_error("bank 0 is only at 0x0000-0x3FFF");
+        }
+        segments.push((bank, start.max(SWITCHABLE_BANK_START), end));
+    }
+
+    for (bank, start, end) in segments {
+        if bank >= num_of_banks {
+            exit_with_error(&format!("bank {bank} doesn't exist, the ROM only has {num_of_banks}"));
+        }
+        let bank_start = bank * ROM_BANK_SIZE;
+        let bank_bytes = &rom[bank_start..rom.len().min(bank_start + ROM_BANK_SIZE)];
+        let offset = (start as usize) % ROM_BANK_SIZE;
+        if offset >= bank_bytes.len() {
+            continue;
+        }
+
+        for instruction in disassembler::disassemble(&bank_bytes[offset..], start) {
+            if instruction.address > end {
+                break;
+            }
+            println!("{bank:02X}:{instruction}");
+        }
+    }
+}
+
+fn exit_with_error(message: &str) -> ! {
+    eprintln!("Unable to disassemble: {message}");
+    std::process::exit(1);
+}
+
+/**
+ * Addresses are always hex, with or without a $ or 0x in front
+ */
+pub fn parse_address(address: &str) -> Result<u16, String> {
+    let digits = address.strip_prefix('$').or_else(|| address.strip_prefix("0x")).unwrap_or(address);
+    return u16::from_str_radix(digits, 16).map_err(|e| format!("{address}: {e}"));
+}
@@ -0,0 +1,298 @@
+use std::fmt;
+
+const R8: [&str; 8] = ["b", "c", "d", "e", "h", "l", "[hl]", "a"];
+const R16: [&str; 4] = ["bc", "de", "hl", "sp"];
+const R16_STACK: [&str; 4] = ["bc", "de", "hl", "af"];
+const R16_MEMORY: [&str; 4] = ["[bc]", "[de]", "[hl+]", "[hl-]"];
+const CONDITIONS: [&str; 4] = ["nz", "z", "nc", "c"];
+const ALU: [&str; 8] = ["add a,", "adc a,", "sub", "sbc a,", "and", "xor", "or", "cp"];
+const ACCUMULATOR_OPS: [&str; 8] = ["rlca", "rrca", "rla", "rra", "daa", "cpl", "scf", "ccf"];
+const ROTATES: [&str; 8] = ["rlc", "rrc", "rl", "rr", "sla", "sra", "swap", "srl"];
+const PREFIX_OPCODE: u8 = 0xCB;
+const OPERAND: &str = "{}";         //Where the immediate goes in a template
+
+/**
+ * What comes after the opcode, and how it gets written out
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Immediate {
+    None,
+    N8,
+    N16,
+    Relative,       //jr offsets, written as the address they jump to
+    HighPage,       //ldh addresses, which are 0xFF00 + n8
+    SignedN8,       //add sp, e8
+    SpOffset,       //ld hl, sp + e8
+    Ignored,        //stop takes a byte that doesn't do anything
+}
+
+impl Immediate {
+    fn len(self) -> usize {
+        return match self {
+            Immediate::None => 0,
+            Immediate::N16 => 2,
+            _ => 1,
+        };
+    }
+
+    /**
+     * How the immediate shows up in opcode tables when we don't know the value
+     */
+    fn placeholder(self) -> &'static str {
+        return match self {
+            Immediate::N8 => "n8",
+            Immediate::N16 => "n16",
+            Immediate::Relative | Immediate::SignedN8 => "e8",
+            Immediate::HighPage => "[$FF00+n8]",
+            Immediate::SpOffset => "sp+e8",
+            Immediate::None | Immediate::Ignored => "",
+        };
+    }
+
+    /**
+     * address is where the instruction starts, for working out jr targets
+     */
+    fn format(self, operand: &[u8], address: u16) -> String {
+        return match self {
+            Immediate::N8 => format!("${:02X}", operand[0]),
+            Immediate::N16 => format!("${:04X}", u16::from_le_bytes([operand[0], operand[1]])),
+            Immediate::Relative => format!("${:04X}", address.wrapping_add(2).wrapping_add(operand[0] as i8 as u16)),
+            Immediate::HighPage => format!("[${:04X}]", 0xFF00 | operand[0] as u16),
+            Immediate::SignedN8 => signed_hex(operand[0] as i8),
+            Immediate::SpOffset if (operand[0] as i8) < 0 => format!("sp{}", signed_hex(operand[0] as i8)),
+            Immediate::SpOffset => format!("sp+{}", signed_hex(operand[0] as i8)),
+            Immediate::None | Immediate::Ignored => String::new(),
+        };
+    }
+}
+
+fn signed_hex(value: i8) -> String {
+    if value < 0 {
+        return format!("-${:02X}", value.unsigned_abs());
+    }
+    return format!("${value:02X}");
+}
+
+/**
+ * One decoded instruction, in RGBDS syntax
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Instruction {
+    pub address: u16,
+    pub bytes: Vec<u8>,
+    pub text: String,
+}
+
+impl Instruction {
+    pub fn len(&self) -> usize {
+        return self.bytes.len();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.bytes.is_empty();
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let bytes = self.bytes.iter().map(|byte| format!("{byte:02X}")).collect::<Vec<String>>().join(" ");
+        write!(f, "{:04X}  {bytes:<8}  {}", self.address, self.text)
+    }
+}
+
+/**
+ * Working out the text of an opcode (with OPERAND where the immediate goes)
+ * from its bit fields. None for the opcodes that lock up the cpu
+ */
+fn base_template(opcode: u8) -> Option<(String, Immediate)> {
+    let x = opcode >> 6;
+    let y = ((opcode >> 3) & 0x7) as usize;
+    let z = opcode & 0x7;
+    let p = y >> 1;
+    let q = y & 0x1;
+
+    let template = match (x, z) {
+        (0, 0) => match y {
+            0 => ("nop".to_string(), Immediate::None),
+            1 => ("ld [{}], sp".to_string(), Immediate::N16),
+            2 => ("stop".to_string(), Immediate::Ignored),
+            3 => ("jr {}".to_string(), Immediate::Relative),
+            _ => (format!("jr {}, {{}}", CONDITIONS[y - 4]), Immediate::Relative),
+        },
+        (0, 1) if q == 0 => (format!("ld {}, {{}}", R16[p]), Immediate::N16),
+        (0, 1) => (format!("add hl, {}", R16[p]), Immediate::None),
+        (0, 2) if q == 0 => (format!("ld {}, a", R16_MEMORY[p]), Immediate::None),
+        (0, 2) => (format!("ld a, {}", R16_MEMORY[p]), Immediate::None),
+        (0, 3) => (format!("{} {}", if q == 0 { "inc" } else { "dec" }, R16[p]), Immediate::None),
+        (0, 4) => (format!("inc {}", R8[y]), Immediate::None),
+        (0, 5) => (format!("dec {}", R8[y]), Immediate::None),
+        (0, 6) => (format!("ld {}, {{}}", R8[y]), Immediate::N8),
+        (0, _) => (ACCUMULATOR_OPS[y].to_string(), Immediate::None),
+
+        (1, _) if opcode == 0x76 => ("halt".to_string(), Immediate::None),
+        (1, _) => (format!("ld {}, {}", R8[y], R8[z as usize]), Immediate::None),
+
+        (2, _) => (format!("{} {}", ALU[y], R8[z as usize]), Immediate::None),
+
+        (_, 0) => match y {
+            0..=3 => (format!("ret {}", CONDITIONS[y]), Immediate::None),
+            4 => ("ldh {}, a".to_string(), Immediate::HighPage),
+            5 => ("add sp, {}".to_string(), Immediate::SignedN8),
+            6 => ("ldh a, {}".to_string(), Immediate::HighPage),
+            _ => ("ld hl, {}".to_string(), Immediate::SpOffset),
+        },
+        (_, 1) if q == 0 => (format!("pop {}", R16_STACK[p]), Immediate::None),
+        (_, 1) => (["ret", "reti", "jp hl", "ld sp, hl"][p].to_string(), Immediate::None),
+        (_, 2) => match y {
+            0..=3 => (format!("jp {}, {{}}", CONDITIONS[y]), Immediate::N16),
+            4 => ("ldh [c], a".to_string(), Immediate::None),
+            5 => ("ld [{}], a".to_string(), Immediate::N16),
+            6 => ("ldh a, [c]".to_string(), Immediate::None),
+            _ => ("ld a, [{}]".to_string(), Immediate::N16),
+        },
+        (_, 3) => match y {
+            0 => ("jp {}".to_string(), Immediate::N16),
+            6 => ("di".to_string(), Immediate::None),
+            7 => ("ei".to_string(), Immediate::None),
+            _ => return None,       //0xCB gets handled before we get here
+        },
+        (_, 4) if y < 4 => (format!("call {}, {{}}", CONDITIONS[y]), Immediate::N16),
+        (_, 5) if q == 0 => (format!("push {}", R16_STACK[p]), Immediate::None),
+        (_, 5) if p == 0 => ("call {}".to_string(), Immediate::N16),
+        (_, 4) | (_, 5) => return None,
+        (_, 6) => (format!("{} {{}}", ALU[y]), Immediate::N8),
+        _ => (format!("rst ${:02X}", y * 8), Immediate::None),
+    };
+    return Some(template);
+}
+
+fn prefix_text(opcode: u8) -> String {
+    let y = (opcode >> 3) & 0x7;
+    let register = R8[(opcode & 0x7) as usize];
+    return match opcode >> 6 {
+        0 => format!("{} {register}", ROTATES[y as usize]),
+        1 => format!("bit {y}, {register}"),
+        2 => format!("res {y}, {register}"),
+        _ => format!("set {y}, {register}"),
+    };
+}
+
+/**
+ * Decoding the instruction at the start of bytes, which is at address. If
+ * bytes ends before the instruction does (like at the end of a ROM bank) or
+ * the opcode isn't a real one, we give back a single `db` instead
+ */
+pub fn decode(bytes: &[u8], address: u16) -> Instruction {
+    let opcode = bytes[0];
+    let db = || Instruction { address, bytes: vec![opcode], text: format!("db ${opcode:02X}") };
+
+    if opcode == PREFIX_OPCODE {
+        return match bytes.get(1) {
+            Some(&prefixed) => Instruction { address, bytes: vec![opcode, prefixed], text: prefix_text(prefixed) },
+            None => db(),
+        };
+    }
+
+    let Some((template, immediate)) = base_template(opcode) else { return db() };
+    let len = 1 + immediate.len();
+    if bytes.len() < len {
+        return db();
+    }
+    return Instruction {
+        address,
+        bytes: bytes[..len].to_vec(),
+        text: template.replace(OPERAND, &immediate.format(&bytes[1..len], address)),
+    };
+}
+
+/**
+ * Decoding everything in bytes, which starts at address. Nothing gets
+ * decoded past the end of bytes, so pass in one bank at a time
+ */
+pub fn disassemble(bytes: &[u8], address: u16) -> Vec<Instruction> {
+    let mut instructions = Vec::new();
+    let mut offset = 0;
+    while offset < bytes.len() {
+        let instruction = decode(&bytes[offset..], address.wrapping_add(offset as u16));
+        offset += instruction.len();
+        instructions.push(instruction);
+    }
+    return instructions;
+}
+
+/**
+ * The text for an opcode without its immediate, like `ld a, n8`. Handy for
+ * when all we know is the opcode
+ */
+pub fn opcode_name(opcode: u8, is_prefix: bool) -> String {
+    if is_prefix {
+        return prefix_text(opcode);
+    }
+    if opcode == PREFIX_OPCODE {
+        return "prefix".to_string();
+    }
+    return match base_template(opcode) {
+        Some((template, immediate)) => template.replace(OPERAND, immediate.placeholder()).trim_end().to_string(),
+        None => format!("db ${opcode:02X}"),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(bytes: &[u8], address: u16) -> String {
+        return decode(bytes, address).text;
+    }
+
+    #[test]
+    fn decodes_base_opcodes() {
+        assert_eq!(text(&[0x00], 0), "nop");
+        assert_eq!(text(&[0x3E, 0x42], 0), "ld a, $42");
+        assert_eq!(text(&[0x21, 0x34, 0x12], 0), "ld hl, $1234");
+        assert_eq!(text(&[0x08, 0x00, 0xC0], 0), "ld [$C000], sp");
+        assert_eq!(text(&[0x2A], 0), "ld a, [hl+]");
+        assert_eq!(text(&[0x32], 0), "ld [hl-], a");
+        assert_eq!(text(&[0x18, 0xFE], 0x150), "jr $0150");
+        assert_eq!(text(&[0x20, 0x05], 0x150), "jr nz, $0157");
+        assert_eq!(text(&[0x76], 0), "halt");
+        assert_eq!(text(&[0x70], 0), "ld [hl], b");
+        assert_eq!(text(&[0x88], 0), "adc a, b");
+        assert_eq!(text(&[0xAF], 0), "xor a");
+        assert_eq!(text(&[0xFE, 0x90], 0), "cp $90");
+        assert_eq!(text(&[0xE0, 0x40], 0), "ldh [$FF40], a");
+        assert_eq!(text(&[0xF2], 0), "ldh a, [c]");
+        assert_eq!(text(&[0xE8, 0xFC], 0), "add sp, -$04");
+        assert_eq!(text(&[0xF8, 0x02], 0), "ld hl, sp+$02");
+        assert_eq!(text(&[0xF5], 0), "push af");
+        assert_eq!(text(&[0xE9], 0), "jp hl");
+        assert_eq!(text(&[0xC4, 0x00, 0x40], 0), "call nz, $4000");
+        assert_eq!(text(&[0xFF], 0), "rst $38");
+        assert_eq!(decode(&[0x10, 0x00], 0).len(), 2);
+    }
+
+    #[test]
+    fn decodes_prefix_opcodes() {
+        assert_eq!(text(&[0xCB, 0x37], 0), "swap a");
+        assert_eq!(text(&[0xCB, 0x7E], 0), "bit 7, [hl]");
+        assert_eq!(text(&[0xCB, 0x87], 0), "res 0, a");
+        assert_eq!(text(&[0xCB, 0xFF], 0), "set 7, a");
+        assert_eq!(text(&[0xCB, 0x11], 0), "rl c");
+    }
+
+    #[test]
+    fn stops_at_the_end_of_a_bank() {
+        let instructions = disassemble(&[0x00, 0xC3, 0x50], 0x7FFD);
+        let texts: Vec<&str> = instructions.iter().map(|instruction| instruction.text.as_str()).collect();
+        assert_eq!(texts, ["nop", "db $C3", "ld d, b"]);
+        assert_eq!(text(&[0xD3], 0), "db $D3");
+    }
+
+    #[test]
+    fn names_opcodes_without_operands() {
+        assert_eq!(opcode_name(0x3E, false), "ld a, n8");
+        assert_eq!(opcode_name(0xE0, false), "ldh [$FF00+n8], a");
+        assert_eq!(opcode_name(0x10, false), "stop");
+        assert_eq!(opcode_name(0x40, true), "bit 0, b");
+    }
+}
@@ -0,0 +1,71 @@
+use std::{fmt, io};
+
+use crate::game_cartridge::header::{HeaderError, HEADER_END};
+
+/**
+ * Everything that can go wrong loading a ROM (or GBS file) into the
+ * emulator, or getting it hooked up to the host
+ */
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    TruncatedRom(usize),                                    //Too short for a header or not a whole number of 16KB banks
+    RomSizeMismatch { header_size: usize, file_size: usize },
+    UnsupportedMapper(u8),                                  //Cartridge type code at 0x147
+    InvalidRomSize(u8),
+    InvalidRamSize(u8),
+    CorruptSaveState,                                       //Bad magic, cut off or holds impossible values
+    UnsupportedSaveStateVersion(u16),
+    SaveStateMismatch,                                      //Made with a different ROM
+    AudioDevice(String),                                    //Couldn't open the host's audio output
+    InvalidGbs(&'static str),
+    LinkHandshake,                                          //Whatever's on the other end of the link cable isn't bintboy
+    LinkProtocol,                                           //The other end sent something that isn't a valid message
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "{e}"),
+            Error::TruncatedRom(len) if *len <= HEADER_END => write!(f, "ROM is only {len:#X} bytes long, but the header ends at {HEADER_END:#X}"),
+            Error::TruncatedRom(len) => write!(f, "ROM is {len:#X} bytes long, which isn't a multiple of 16KB"),
+            Error::RomSizeMismatch { header_size, file_size } => write!(f, "header says the ROM is {header_size:#X} bytes, but it's {file_size:#X} bytes"),
+            Error::UnsupportedMapper(code) => write!(f, "cartridge type {code:#04X} isn't supported"),
+            Error::InvalidRomSize(code) => write!(f, "invalid ROM size {code:#04X}"),
+            Error::InvalidRamSize(code) => write!(f, "invalid RAM size {code:#04X}"),
+            Error::CorruptSaveState => write!(f, "save state is corrupt"),
+            Error::UnsupportedSaveStateVersion(version) => write!(f, "save state version {version} isn't supported"),
+            Error::SaveStateMismatch => write!(f, "save state was made with a different ROM"),
+            Error::AudioDevice(message) => write!(f, "unable to open the audio device: {message}"),
+            Error::InvalidGbs(reason) => write!(f, "invalid GBS file: {reason}"),
+            Error::LinkHandshake => write!(f, "the other end of the link cable isn't a compatible bintboy"),
+            Error::LinkProtocol => write!(f, "the other end of the link cable sent a malformed message"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<HeaderError> for Error {
+    fn from(e: HeaderError) -> Self {
+        match e {
+            HeaderError::TooShort(len) => Error::TruncatedRom(len),
+            HeaderError::UnknownCartridgeType(code) => Error::UnsupportedMapper(code),
+            HeaderError::UnknownRomSize(code) => Error::InvalidRomSize(code),
+            HeaderError::UnknownRamSize(code) => Error::InvalidRamSize(code),
+        }
+    }
+}
@@ -1,12 +1,19 @@
 pub mod mbc;
+pub mod rtc;
+pub mod header;
 mod enums;
 
-use std::{fs::File, io::{Read, Seek, SeekFrom}};
+use std::{fs, io, path::Path};
 
 use self::enums::{RAMSize, ROMSize, MBC, NINTENDO_LOGO};
+use self::rtc::RtcSource;
+use self::header::CartridgeHeader;
+use crate::error::Error;
+use crate::save_state::{StateReader, StateWriter};
 
 #[derive(Debug)]
 pub struct GameCartridge {
+    pub header: Option<CartridgeHeader>,
     pub rom_banks: Vec<[u8; 0x4000]>,
     pub ram_banks: Vec<[u8; 0x2000]>,
     pub mbc: MBC,
@@ -14,11 +21,21 @@ pub struct GameCartridge {
     pub ram_size: RAMSize,
     pub bank_bit_mask: u16,
     pub ram_bank_bit_mask: u8,
+    pub has_battery: bool,      //Whether SRAM should survive the Game Boy being turned off
+    pub has_rtc: bool,          //MBC3+TIMER carts. The RTC state gets saved along with SRAM
+    sram_dirty: bool,           //SRAM was written to since it was last saved
+}
+
+impl Default for GameCartridge {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl GameCartridge {
     pub fn new() -> Self {
         Self {
+            header: None,
             rom_banks: vec![],
             ram_banks: vec![],
             mbc: MBC::RomOnly,
@@ -26,6 +43,9 @@ impl GameCartridge {
             ram_size: RAMSize::_0KiB,
             bank_bit_mask: 0,
             ram_bank_bit_mask: 0,
+            has_battery: false,
+            has_rtc: false,
+            sram_dirty: false,
         }
     }
 
@@ -62,15 +82,18 @@ impl GameCartridge {
                     self.rom_banks[0][idx as usize]
                 }
             },
-            MBC::MBC2(_) => todo!(),
+            MBC::MBC2(_) => self.rom_banks[0][idx as usize],
             MBC::MBC3(_) => self.rom_banks[0][idx as usize],
             MBC::MBC5(_) => self.rom_banks[0][idx as usize],
         };
     }
 
-    pub fn read_rom_bank_x(&self, idx: u16) -> u8 {
+    /**
+     * Which ROM bank is switched in at 0x4000 - 0x7FFF
+     */
+    pub fn switchable_rom_bank(&self) -> usize {
         return match &self.mbc {
-            MBC::RomOnly => self.rom_banks[1][idx as usize],
+            MBC::RomOnly => 1,
             MBC::MBC1(mbc1) => {
                 let mut rom_bank_num = mbc1.rom_bank_num;
 
@@ -84,21 +107,24 @@ impl GameCartridge {
                 }
 
                 rom_bank_num &= self.bank_bit_mask as u8;
-
-                self.rom_banks[rom_bank_num as usize][idx as usize]
+                rom_bank_num as usize
             },
-            MBC::MBC2(mbc2) => todo!(),
-            MBC::MBC3(mbc3) => self.rom_banks[mbc3.rom_bank_num as usize][idx as usize],
-            MBC::MBC5(mbc5) => self.rom_banks[mbc5.rom_bank_num as usize][idx as usize],
+            MBC::MBC2(mbc2) => mbc2.rom_bank_num as usize,
+            MBC::MBC3(mbc3) => mbc3.rom_bank_num as usize,
+            MBC::MBC5(mbc5) => mbc5.rom_bank_num as usize,
         };
     }
 
+    pub fn read_rom_bank_x(&self, idx: u16) -> u8 {
+        return self.rom_banks[self.switchable_rom_bank()][idx as usize];
+    }
+
     pub fn read_sram(&self, idx: u16) -> u8 {
         let mut value = 0xFF; //Default value if we can't read SRAM
 
-        if self.is_ram_enabled() && self.ram_size > RAMSize::_0KiB {
+        if self.is_ram_enabled() && self.is_sram_mapped() {
             value = match &self.mbc {
-                MBC::RomOnly => 0xFF,
+                MBC::RomOnly => self.ram_banks[0][idx as usize],
                 MBC::MBC1(mbc1) => {
                     if mbc1.banking_mode_sel == 1 {
                         let ram_bank_num = mbc1.ram_bank_num & self.ram_bank_bit_mask;
@@ -107,15 +133,12 @@ impl GameCartridge {
                         self.ram_banks[0][idx as usize]
                     }
                 },
-                MBC::MBC2(mbc2) => todo!(),
+                MBC::MBC2(mbc2) => mbc2.read_ram(idx),
                 MBC::MBC3(mbc3) => {
-                    match mbc3.ram_bank_num {
-                        0x8 => mbc3.rtc_seconds,
-                        0x9 => mbc3.rtc_minutes,
-                        0xA => mbc3.rtc_hours,
-                        0xB => mbc3.rtc_day_lower,
-                        0xC => mbc3.rtc_day_upper,
-                        ram_bank_num => self.ram_banks[ram_bank_num as usize][idx as usize],
+                    if mbc3.is_rtc_selected() {
+                        mbc3.rtc.read_register(mbc3.ram_bank_num)
+                    } else {
+                        self.ram_banks[mbc3.ram_bank_num as usize][idx as usize]
                     }
                 },
                 MBC::MBC5(mbc5) => self.ram_banks[mbc5.sram_bank_num as usize][idx as usize],
@@ -130,7 +153,7 @@ impl GameCartridge {
      * anything
      */
     pub fn write_sram(&mut self, value: u8, idx: u16) {
-        if self.is_ram_enabled() && self.ram_size > RAMSize::_0KiB {
+        if self.is_ram_enabled() && self.is_sram_mapped() {
             match &mut self.mbc {
                 MBC::RomOnly => {
                     self.ram_banks[0][idx as usize] = value;
@@ -143,29 +166,143 @@ impl GameCartridge {
                         self.ram_banks[0][idx as usize] = value;
                     } 
                 },
-                MBC::MBC2(_) => todo!(),
+                MBC::MBC2(mbc2) => mbc2.write_ram(value, idx),
                 MBC::MBC3(mbc3) => {
-                    match mbc3.ram_bank_num {
-                        0x8 => mbc3.rtc_seconds = value,
-                        0x9 => mbc3.rtc_minutes = value,
-                        0xA => mbc3.rtc_hours = value,
-                        0xB => mbc3.rtc_day_lower = value,
-                        0xC => mbc3.rtc_day_upper = value,
-                        ram_bank_num => self.ram_banks[ram_bank_num as usize][idx as usize] = value,
+                    if mbc3.is_rtc_selected() {
+                        mbc3.rtc.write_register(mbc3.ram_bank_num, value);
+                    } else {
+                        self.ram_banks[mbc3.ram_bank_num as usize][idx as usize] = value;
                     }
                 },
                 MBC::MBC5(mbc5) => self.ram_banks[mbc5.sram_bank_num as usize][idx as usize] = value,
             }
+            self.sram_dirty = true;
+        }
+    }
+
+    pub fn is_sram_dirty(&self) -> bool {
+        return self.sram_dirty;
+    }
+
+    /**
+     * Dumping all of SRAM as one flat chunk of bytes. This is the same raw
+     * format other emulators use for their .sav files. For MBC2 it's the 512
+     * bytes of built-in RAM
+     */
+    pub fn sram_bytes(&self) -> Vec<u8> {
+        return match &self.mbc {
+            MBC::MBC2(mbc2) => mbc2.ram.clone(),
+            _ => self.ram_banks.iter().flatten().copied().collect(),
+        };
+    }
+
+    /**
+     * Filling SRAM from a raw dump. If the dump is a different size than
+     * our SRAM we just copy over as much as fits
+     */
+    pub fn load_sram_bytes(&mut self, data: &[u8]) {
+        match &mut self.mbc {
+            MBC::MBC2(mbc2) => {
+                for (ram_byte, value) in mbc2.ram.iter_mut().zip(data) {
+                    *ram_byte = value & 0x0F;
+                }
+            },
+            _ => {
+                for (ram_byte, value) in self.ram_banks.iter_mut().flatten().zip(data) {
+                    *ram_byte = *value;
+                }
+            },
+        }
+        self.sram_dirty = false;
+    }
+
+    /**
+     * Loading a battery save if the cartridge has a battery and the file exists.
+     * MBC3+TIMER saves can have the RTC state tacked onto the end of SRAM
+     */
+    pub fn load_save_file(&mut self, save_path: &Path) -> io::Result<()> {
+        if !self.has_battery || !save_path.exists() {
+            return Ok(());
+        }
+
+        let data = fs::read(save_path)?;
+        let sram_len = self.sram_bytes().len().min(data.len());
+        self.load_sram_bytes(&data[..sram_len]);
+
+        if self.has_rtc {
+            if let MBC::MBC3(mbc3) = &mut self.mbc {
+                let footer = &data[sram_len..];
+                if !footer.is_empty() && !mbc3.rtc.load_save_footer(footer) {
+                    eprintln!("Ignoring RTC data in {} since it is {} bytes long", save_path.display(), footer.len());
+                }
+            }
+        }
+        return Ok(());
+    }
+
+    /**
+     * Writing SRAM out to the save file if the cartridge has a battery. We write
+     * to a temp file first so a crash halfway through can't corrupt the old save
+     */
+    pub fn write_save_file(&mut self, save_path: &Path) -> io::Result<()> {
+        if !self.has_battery {
+            return Ok(());
+        }
+
+        let mut data = self.sram_bytes();
+        if self.has_rtc {
+            if let MBC::MBC3(mbc3) = &mut self.mbc {
+                data.extend(mbc3.rtc.save_footer());
+            }
+        }
+
+        let temp_path = save_path.with_extension("sav.tmp");
+        fs::write(&temp_path, data)?;
+        fs::rename(&temp_path, save_path)?;
+        self.sram_dirty = false;
+        return Ok(());
+    }
+
+    /**
+     * Whether there is anything mapped into A000 - BFFF we can read/write to. 
+     * MBC2 reports no RAM in its header since the RAM is built into the MBC itself,
+     * and MBC3 can have its RTC registers mapped there even without RAM
+     */
+    fn is_sram_mapped(&self) -> bool {
+        return match &self.mbc {
+            MBC::MBC2(_) => true,
+            MBC::MBC3(mbc3) if mbc3.is_rtc_selected() => true,
+            _ => self.ram_size > RAMSize::_0KiB,
+        };
+    }
+
+    /**
+     * Choosing what drives the RTC if the cartridge has one
+     */
+    pub fn set_rtc_source(&mut self, source: RtcSource) {
+        if let MBC::MBC3(mbc3) = &mut self.mbc {
+            mbc3.rtc.set_source(source);
+        }
+    }
+
+    /**
+     * Mimicking one cpu clk cycle for anything on the cartridge that keeps
+     * time on its own. Right now that's just the MBC3 RTC
+     */
+    pub fn cycle(&mut self) {
+        if let MBC::MBC3(mbc3) = &mut self.mbc {
+            mbc3.rtc.cycle();
         }
     }
 
     /**
      * Will call the current MBC types ram enable register to see if were 
-     * allowed to write or read from SRAM
+     * allowed to write or read from SRAM. Without an MBC there's no register,
+     * so any RAM is always enabled
      */
     fn is_ram_enabled(&self) -> bool {
         match &self.mbc {
-            MBC::RomOnly => false,
+            MBC::RomOnly => true,
             MBC::MBC1(mbc1) => mbc1.is_ram_enabled(),
             MBC::MBC2(mbc2) => mbc2.is_ram_enabled(),
             MBC::MBC3(mbc3) => mbc3.is_ram_and_timer_enabled(),
@@ -177,11 +314,11 @@ impl GameCartridge {
      * Will handle what each mbc type does writing to the address range 
      * 0x0000 - 0x1fff
      */
-    pub fn write_0x0000_to_0x1fff(&mut self, value: u8) {
+    pub fn write_0x0000_to_0x1fff(&mut self, value: u8, address: u16) {
         match &mut self.mbc {
             MBC::RomOnly => (),
             MBC::MBC1(mbc1) => mbc1.write_ram_enable(value),
-            MBC::MBC2(mbc2) => mbc2.write_0x0000_to_0x1fff(value),
+            MBC::MBC2(mbc2) => mbc2.write_0x0000_to_0x1fff(value, address, self.bank_bit_mask),
             MBC::MBC3(mbc3) => mbc3.write_ram_and_timer_enable(value),
             MBC::MBC5(mbc5) => mbc5.write_ram_enable(value),
         }
@@ -195,7 +332,7 @@ impl GameCartridge {
         match &mut self.mbc {
             MBC::RomOnly => (),
             MBC::MBC1(mbc1) => mbc1.write_rom_bank_num(value, self.bank_bit_mask),
-            MBC::MBC2(mbc2) => mbc2.write_0x2000_to_0x3fff(value),
+            MBC::MBC2(mbc2) => mbc2.write_0x2000_to_0x3fff(value, address, self.bank_bit_mask),
             MBC::MBC3(mbc3) => mbc3.write_rom_bank_num(value, self.bank_bit_mask),
             MBC::MBC5(mbc5) => {
                 if address > 0x2FFF {
@@ -215,7 +352,7 @@ impl GameCartridge {
         match &mut self.mbc {
             MBC::RomOnly => (),
             MBC::MBC1(mbc1) => mbc1.write_ram_bank_num(value),
-            MBC::MBC2(mbc2) => mbc2.write_0x4000_to_0x5fff(value),
+            MBC::MBC2(_) => (),
             MBC::MBC3(mbc3) => mbc3.write_ram_bank_num_or_rtc_sel(value),
             MBC::MBC5(mbc5) => mbc5.write_ram_bank_num(value),
         }
@@ -229,170 +366,168 @@ impl GameCartridge {
         match &mut self.mbc {
             MBC::RomOnly => (),
             MBC::MBC1(mbc1) => mbc1.write_banking_mode_sel(value),
-            MBC::MBC2(mbc2) => mbc2.write_0x6000_to_0x7fff(value),
+            MBC::MBC2(_) => (),
             MBC::MBC3(mbc3) => mbc3.write_latch_clock_data(value),
             MBC::MBC5(_) => (),
         }
     }
 
-    fn rom_size(&self) -> ROMSize {
-        match self.rom_banks[0][0x148] {
-            0x0 => ROMSize::_32KiB,
-            0x1 => ROMSize::_64KiB,
-            0x2 => ROMSize::_128KiB,
-            0x3 => ROMSize::_256KiB,
-            0x4 => ROMSize::_512KiB,
-            0x5 => ROMSize::_1MiB,
-            0x6 => ROMSize::_2MiB,
-            0x7 => ROMSize::_4MiB,
-            0x8 => ROMSize::_8MiB,
-            _ => panic!("Error unsupported number of banks")
-        }
+    /**
+     * Takes a file path to a Game Boy rom file and loads it into the rom struct.
+     * This will separate the rom into 16KB banks.
+     */
+    pub fn load_cartridge(&mut self, file_path: &str) -> Result<(), Error> {
+        *self = Self::load_from_bytes(&fs::read(file_path)?)?;
+        return Ok(());
     }
 
-    pub fn ram_size(&self) -> RAMSize {
-        match self.rom_banks[0][0x149] {
-            0x0 => RAMSize::_0KiB,
-            0x1 => panic!("unused ram size"),
-            0x2 => RAMSize::_8KiB,
-            0x3 => RAMSize::_32KiB,
-            0x4 => RAMSize::_128KiB,
-            0x5 => RAMSize::_64KiB,
-            _ => panic!("Not of valid ram size")
+    /**
+     * Creating a cartridge out of a rom that's already in memory. The rom has
+     * to be exactly as big as its header says it is
+     */
+    pub fn load_from_bytes(rom: &[u8]) -> Result<Self, Error> {
+        let header = CartridgeHeader::parse(rom)?;
+
+        //Anything that isn't a whole number of banks got cut off somewhere
+        if !rom.len().is_multiple_of(0x4000) {
+            return Err(Error::TruncatedRom(rom.len()));
         }
-    }
 
-    fn num_of_ram_banks(&self) -> u8 {
-        match self.rom_banks[0][0x149] {
-            0x0 => 0,
-            0x1 => panic!("0x1 is a unused ram size"),
-            0x2 => 1,
-            0x3 => 4,
-            0x4 => 16,
-            0x5 => 8,
-            num => panic!("Invalid number of ram banks read from rom: {num}"),
+        let header_size = header.rom_size.num_of_banks() as usize * 0x4000;
+        if header_size != rom.len() {
+            return Err(Error::RomSizeMismatch { header_size, file_size: rom.len() });
         }
-    }
 
-    fn num_of_rom_banks(&self) -> u16 {
-        match self.rom_banks[0][0x148] {
-            0x0 => 2,
-            0x1 => 4,
-            0x2 => 8,
-            0x3 => 16,
-            0x4 => 32,
-            0x5 => 64,
-            0x6 => 128,
-            0x7 => 256,
-            0x8 => 512,
-            _ => panic!("Error unsupported number of banks")
+        //Setting the MBC controller type
+        let mbc = match select_mbc(&header, rom) {
+            Some(mbc) => mbc,
+            None => return Err(Error::UnsupportedMapper(header.cartridge_type.code)),
+        };
+
+        let max_ram_size = match mbc {
+            MBC::RomOnly => RAMSize::_8KiB,
+            MBC::MBC1(_) => RAMSize::_32KiB,
+            MBC::MBC2(_) => RAMSize::_0KiB,     //MBC2 has its ram built in, so the header has to say 0
+            MBC::MBC3(_) => RAMSize::_64KiB,
+            MBC::MBC5(_) => RAMSize::_128KiB,
+        };
+        if header.ram_size > max_ram_size {
+            return Err(Error::InvalidRamSize(header.ram_size.code()));
         }
+
+        let mut cartridge = Self::new();
+        cartridge.rom_banks = rom.chunks_exact(0x4000)
+                                 .map(|bank| bank.try_into().expect("Banks are always 16KB"))
+                                 .collect();
+        cartridge.ram_banks = vec![[0; 0x2000]; header.ram_size.num_of_banks() as usize];
+        cartridge.mbc = mbc;
+        cartridge.ram_size = header.ram_size;
+        cartridge.rom_size = header.rom_size;
+        cartridge.bank_bit_mask = header.rom_size.num_of_banks() - 1;
+        cartridge.ram_bank_bit_mask = header.ram_size.num_of_banks().saturating_sub(1);
+        cartridge.has_battery = header.cartridge_type.has_battery;
+        cartridge.has_rtc = header.cartridge_type.has_rtc;
+        cartridge.header = Some(header);
+        return Ok(cartridge);
     }
 
-    fn bank_bit_mask(&self) -> u16 {
-        match self.num_of_rom_banks() {
-            2 => 0x1,
-            4 => 0x3,
-            8 => 0x7,
-            16 => 0xF,
-            32 => 0x1F,
-            64 => 0x3F,
-            128 => 0x7F,
-            256 => 0xFF,
-            512 => 0x1FF,
-            _ => panic!("Error: Unsupported number of ROM banks")
+    /**
+     * Saving the MBC registers and SRAM. The ROM itself isn't saved, so a
+     * state can only be loaded back into the same game
+     */
+    pub fn save_state(&self, state: &mut StateWriter) {
+        self.mbc.save_state(state);
+        for ram_bank in &self.ram_banks {
+            state.write_bytes(ram_bank);
         }
     }
 
-    fn ram_bank_bit_mask(&self) -> u8 {
-        match self.num_of_ram_banks() {
-            0 => 0x0,
-            1 => 0x0,
-            4 => 0x3,
-            8 => 0x7,
-            16 => 0xF,
-            _ => panic!("Not a real num of ram banks come on man"),
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), Error> {
+        self.mbc.load_state(state, self.rom_banks.len(), self.ram_banks.len())?;
+        for ram_bank in self.ram_banks.iter_mut() {
+            state.read_into(ram_bank)?;
         }
-    }
 
-    /**
-     * Takes a file path to a Game Boy rom file and loads it into the rom struct.
-     * This will separate the rom into 16KB banks.
-     */
-    pub fn load_cartridge(&mut self, file_path: &str) {
-        let mut rom_file = File::open(file_path).expect("File not found");
-        let file_size = rom_file.seek(SeekFrom::End(0)).expect("Error finding the file size");
-        let num_of_banks = file_size / 0x4000;
+        //SRAM might not match the save file anymore
+        self.sram_dirty = self.has_battery;
+        return Ok(());
+    }
+}
 
-        //Setting the file cursor back to the beginning
-        rom_file.seek(SeekFrom::Start(0)).expect("Error resetting the file");
+/**
+ * Picking the MBC we'll emulate the cartridge with based off its header.
+ * Returns None if the mapper isn't supported
+ */
+pub fn select_mbc(header: &CartridgeHeader, rom: &[u8]) -> Option<MBC> {
+    return match MBC::from_mapper(header.cartridge_type.mapper) {
+        Some(MBC::MBC1(mut mbc1)) => {
+            mbc1.is_mbc1m_cart = is_mbc1m_cart(header, rom);
+            Some(MBC::MBC1(mbc1))
+        },
+        mbc => mbc,
+    };
+}
 
-        //Setting up how many 16KB banks the rom has
-        for _ in 0..num_of_banks {
-            self.rom_banks.push([0; 0x4000]);
+/**
+ * Tests whether the cart is a MBC1M cart. These are 1MiB multicarts that have 
+ * another copy of the Nintendo logo at the start of bank 0x10
+ */
+fn is_mbc1m_cart(header: &CartridgeHeader, rom: &[u8]) -> bool {
+    if header.rom_size == ROMSize::_1MiB {
+        if let Some(bank) = rom.get(0x10 * 0x4000..0x11 * 0x4000) {
+            return bank.windows(NINTENDO_LOGO.len()).any(|window| window == NINTENDO_LOGO);
         }
+    }
+    return false;
+}
 
-        //Loading all the game data into the rom banks
-        let mut byte_count = 0;
-        let mut bank_num = 0;
-        for byte in rom_file.bytes() {
-            self.rom_banks[bank_num][byte_count] = match byte {
-                Ok(byte_value) => byte_value,
-                Err(e) => panic!("Error reading rom on bank: {bank_num} and byte: {byte_count}\n\n {e}"),
-            };
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-            byte_count += 1;
-            if byte_count == 0x4000 {
-                byte_count = 0;
-                bank_num += 1;
-            }
-        }
+    fn rom(cartridge_type: u8, rom_size: u8, ram_size: u8, len: usize) -> Vec<u8> {
+        let mut rom = vec![0; len];
+        rom[0x147] = cartridge_type;
+        rom[0x148] = rom_size;
+        rom[0x149] = ram_size;
+        rom
+    }
 
-        //Creating the 8KB ram banks
-        for _ in 0..self.num_of_ram_banks() {
-            self.ram_banks.push([0; 0x2000]);
-        }
-        
-        self.ram_size = self.ram_size();
-        self.rom_size = self.rom_size();
-        self.bank_bit_mask = self.bank_bit_mask();
-        self.ram_bank_bit_mask = self.ram_bank_bit_mask();
+    #[test]
+    fn loads_rom_from_bytes() {
+        let cartridge = GameCartridge::load_from_bytes(&rom(0x03, 0x1, 0x3, 0x10000)).unwrap();
+        assert_eq!(cartridge.rom_banks.len(), 4);
+        assert_eq!(cartridge.ram_banks.len(), 4);
+        assert!(cartridge.has_battery);
+    }
 
-        //Setting the MBC controller type
-        self.mbc = match self.rom_banks[0][0x147] {
-            0x00 => MBC::new(0),            //ROM-ONLY
-            0x01 ..= 0x03 => {              //MBC1
-                let mut mbc1 = MBC::new(1);
-                if let MBC::MBC1(ref mut mbc1) = mbc1 {
-                    mbc1.is_mbc1m_cart = self.is_mbc1m_cart();
-                }
-                mbc1
-            },
-            0x05 ..= 0x06 => MBC::new(2),   //MBC2
-            0x0F ..= 0x13 => MBC::new(3),   //MBC3
-            0x19 ..= 0x1E => MBC::new(5),   //MBC5
-            _ => panic!("Come on man I don't got time to support this MBC type"),
-        };
+    #[test]
+    fn reports_bad_roms() {
+        assert!(matches!(GameCartridge::load_from_bytes(&[0; 0x100]), Err(Error::TruncatedRom(0x100))));
+        assert!(matches!(GameCartridge::load_from_bytes(&rom(0x00, 0x0, 0x0, 0x7000)), Err(Error::TruncatedRom(0x7000))));
+        assert!(matches!(GameCartridge::load_from_bytes(&rom(0x00, 0x1, 0x0, 0x8000)),
+                         Err(Error::RomSizeMismatch { header_size: 0x10000, file_size: 0x8000 })));
+        assert!(matches!(GameCartridge::load_from_bytes(&rom(0x20, 0x0, 0x0, 0x8000)), Err(Error::UnsupportedMapper(0x20))));
+        assert!(matches!(GameCartridge::load_from_bytes(&rom(0x0B, 0x0, 0x0, 0x8000)), Err(Error::UnsupportedMapper(0x0B))));
+        assert!(matches!(GameCartridge::load_from_bytes(&rom(0x00, 0x9, 0x0, 0x8000)), Err(Error::InvalidRomSize(0x09))));
+        assert!(matches!(GameCartridge::load_from_bytes(&rom(0x06, 0x0, 0x2, 0x8000)), Err(Error::InvalidRamSize(0x02))));
+        assert!(matches!(GameCartridge::new().load_cartridge("does/not/exist.gb"), Err(Error::Io(_))));
     }
 
-    /**
-     * Tests whether the cart is a MBC1M cart.
-     */
-    fn is_mbc1m_cart(&mut self) -> bool {
-        if self.rom_size == ROMSize::_1MiB {
-            let mut logo_idx = 0;
-            for byte in &self.rom_banks[0x10] {
-                if *byte == NINTENDO_LOGO[logo_idx] {
-                    logo_idx += 1;
-
-                    if logo_idx == NINTENDO_LOGO.len() {
-                        return true;
-                    }
-                } else {
-                    logo_idx = 0;
-                }
-            }
-        }
-        return false;
+    #[test]
+    fn rejects_states_switching_in_missing_banks() {
+        let mut cartridge = GameCartridge::load_from_bytes(&rom(0x1B, 0x1, 0x2, 0x10000)).unwrap();
+        let mut state = StateWriter::new();
+        cartridge.save_state(&mut state);
+        let state = state.into_bytes();
+        assert!(cartridge.load_state(&mut StateReader::new(&state)).is_ok());
+
+        //MBC type, RAM enable, then the 16 bit ROM bank and the RAM bank
+        let mut missing_rom_bank = state.clone();
+        missing_rom_bank[2] = 4;
+        assert!(matches!(cartridge.load_state(&mut StateReader::new(&missing_rom_bank)), Err(Error::CorruptSaveState)));
+        let mut missing_ram_bank = state.clone();
+        missing_ram_bank[4] = 1;
+        assert!(matches!(cartridge.load_state(&mut StateReader::new(&missing_ram_bank)), Err(Error::CorruptSaveState)));
     }
-}
\ No newline at end of file
+}
@@ -1,4 +1,7 @@
 use super::mbc;
+use super::header::{HeaderError, Mapper};
+use crate::error::Error;
+use crate::save_state::{StateReader, StateWriter};
 
 
 pub const NINTENDO_LOGO: [u8; 48] = [0xCE,0xED,0x66,0x66,0xCC,0x0D,0x00,0x0B,0x03,0x73,0x00,0x83,0x00,0x0C,0x00,0x0D,
@@ -30,9 +33,76 @@ impl MBC {
             x => panic!("Error creating a new MBC because we don't support MBC type {x}"),
         }
     }
+
+    /**
+     * Picking the MBC we emulate for the mapper in the cartridge header. 
+     * Returns None if we don't support it
+     */
+    pub fn from_mapper(mapper: Mapper) -> Option<Self> {
+        match mapper {
+            Mapper::RomOnly => Some(MBC::new(0)),
+            Mapper::MBC1 => Some(MBC::new(1)),
+            Mapper::MBC2 => Some(MBC::new(2)),
+            Mapper::MBC3 => Some(MBC::new(3)),
+            Mapper::MBC5 => Some(MBC::new(5)),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            MBC::RomOnly => "ROM ONLY",
+            MBC::MBC1(mbc1) if mbc1.is_mbc1m_cart => "MBC1M",
+            MBC::MBC1(_) => "MBC1",
+            MBC::MBC2(_) => "MBC2",
+            MBC::MBC3(_) => "MBC3",
+            MBC::MBC5(_) => "MBC5",
+        }
+    }
+
+    /**
+     * Saving the banking registers. The MBC type comes first so we can tell if
+     * a state was made with a different kind of cartridge
+     */
+    pub fn save_state(&self, state: &mut StateWriter) {
+        match self {
+            MBC::RomOnly => state.write_u8(0),
+            MBC::MBC1(mbc1) => {
+                state.write_u8(1);
+                mbc1.save_state(state);
+            },
+            MBC::MBC2(mbc2) => {
+                state.write_u8(2);
+                mbc2.save_state(state);
+            },
+            MBC::MBC3(mbc3) => {
+                state.write_u8(3);
+                mbc3.save_state(state);
+            },
+            MBC::MBC5(mbc5) => {
+                state.write_u8(5);
+                mbc5.save_state(state);
+            },
+        }
+    }
+
+    /**
+     * The number of ROM and RAM banks the cartridge has are needed to catch
+     * states that would switch in banks that don't exist
+     */
+    pub fn load_state(&mut self, state: &mut StateReader, rom_banks: usize, ram_banks: usize) -> Result<(), Error> {
+        return match (self, state.read_u8()?) {
+            (MBC::RomOnly, 0) => Ok(()),
+            (MBC::MBC1(mbc1), 1) => mbc1.load_state(state),
+            (MBC::MBC2(mbc2), 2) => mbc2.load_state(state, rom_banks),
+            (MBC::MBC3(mbc3), 3) => mbc3.load_state(state, rom_banks, ram_banks),
+            (MBC::MBC5(mbc5), 5) => mbc5.load_state(state, rom_banks, ram_banks),
+            _ => Err(Error::SaveStateMismatch),
+        };
+    }
 }
 
-#[derive(Debug, PartialEq, PartialOrd)]
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
 pub enum RAMSize {
     _0KiB,
     _8KiB,
@@ -41,7 +111,50 @@ pub enum RAMSize {
     _128KiB,
 }
 
-#[derive(Debug, PartialEq, PartialOrd)]
+impl RAMSize {
+    /**
+     * Converting byte 0x149 of the header. 0x1 was never used by any official
+     * cartridge so we don't accept it either
+     */
+    pub fn from_code(code: u8) -> Result<Self, HeaderError> {
+        match code {
+            0x0 => Ok(RAMSize::_0KiB),
+            0x2 => Ok(RAMSize::_8KiB),
+            0x3 => Ok(RAMSize::_32KiB),
+            0x4 => Ok(RAMSize::_128KiB),
+            0x5 => Ok(RAMSize::_64KiB),
+            _ => Err(HeaderError::UnknownRamSize(code)),
+        }
+    }
+
+    /**
+     * Turning it back into byte 0x149 of the header
+     */
+    pub fn code(&self) -> u8 {
+        match self {
+            RAMSize::_0KiB => 0x0,
+            RAMSize::_8KiB => 0x2,
+            RAMSize::_32KiB => 0x3,
+            RAMSize::_64KiB => 0x5,
+            RAMSize::_128KiB => 0x4,
+        }
+    }
+
+    /**
+     * How many 8KB ram banks this size is made up of
+     */
+    pub fn num_of_banks(&self) -> u8 {
+        match self {
+            RAMSize::_0KiB => 0,
+            RAMSize::_8KiB => 1,
+            RAMSize::_32KiB => 4,
+            RAMSize::_64KiB => 8,
+            RAMSize::_128KiB => 16,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
 pub enum ROMSize {
     _32KiB,
     _64KiB,
@@ -52,4 +165,31 @@ pub enum ROMSize {
     _2MiB,
     _4MiB,
     _8MiB,
+}
+
+impl ROMSize {
+    /**
+     * Converting byte 0x148 of the header
+     */
+    pub fn from_code(code: u8) -> Result<Self, HeaderError> {
+        match code {
+            0x0 => Ok(ROMSize::_32KiB),
+            0x1 => Ok(ROMSize::_64KiB),
+            0x2 => Ok(ROMSize::_128KiB),
+            0x3 => Ok(ROMSize::_256KiB),
+            0x4 => Ok(ROMSize::_512KiB),
+            0x5 => Ok(ROMSize::_1MiB),
+            0x6 => Ok(ROMSize::_2MiB),
+            0x7 => Ok(ROMSize::_4MiB),
+            0x8 => Ok(ROMSize::_8MiB),
+            _ => Err(HeaderError::UnknownRomSize(code)),
+        }
+    }
+
+    /**
+     * How many 16KB rom banks this size is made up of
+     */
+    pub fn num_of_banks(&self) -> u16 {
+        2 << (*self as u16)
+    }
 }
\ No newline at end of file
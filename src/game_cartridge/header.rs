@@ -0,0 +1,371 @@
+use std::fmt;
+
+use super::enums::{RAMSize, ROMSize, NINTENDO_LOGO};
+
+pub const HEADER_END: usize = 0x14F;
+const LOGO_START: usize = 0x104;
+pub const TITLE_START: usize = 0x134;
+const MANUFACTURER_CODE_START: usize = 0x13F;
+const CGB_FLAG: usize = 0x143;
+const NEW_LICENSEE_CODE_START: usize = 0x144;
+const SGB_FLAG: usize = 0x146;
+pub const CARTRIDGE_TYPE: usize = 0x147;
+pub const ROM_SIZE: usize = 0x148;
+pub const RAM_SIZE: usize = 0x149;
+const DESTINATION_CODE: usize = 0x14A;
+const OLD_LICENSEE_CODE: usize = 0x14B;
+const VERSION_NUMBER: usize = 0x14C;
+pub const HEADER_CHECKSUM: usize = 0x14D;
+const GLOBAL_CHECKSUM: usize = 0x14E;
+
+const USE_NEW_LICENSEE_CODE: u8 = 0x33;
+
+/**
+ * Everything that can go wrong when reading a cartridge header
+ */
+#[derive(Debug, PartialEq)]
+pub enum HeaderError {
+    TooShort(usize),                //The data ends before the header does
+    UnknownCartridgeType(u8),
+    UnknownRomSize(u8),
+    UnknownRamSize(u8),
+}
+
+impl fmt::Display for HeaderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HeaderError::TooShort(len) => write!(f, "only {len:#X} bytes long, but the header ends at {HEADER_END:#X}"),
+            HeaderError::UnknownCartridgeType(code) => write!(f, "unknown cartridge type {code:#04X}"),
+            HeaderError::UnknownRomSize(code) => write!(f, "unknown ROM size {code:#04X}"),
+            HeaderError::UnknownRamSize(code) => write!(f, "unknown RAM size {code:#04X}"),
+        }
+    }
+}
+
+impl std::error::Error for HeaderError {}
+
+/**
+ * Whether the game makes use of the Game Boy Color
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CgbSupport {
+    None,       //Made for the original Game Boy
+    Enhanced,   //0x80 Works on both, but has extra CGB features
+    Required,   //0xC0 Only works on the CGB
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Destination {
+    Japan,
+    Overseas,
+    Unknown(u8),
+}
+
+/**
+ * The memory bank controller chip inside the cartridge
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mapper {
+    RomOnly,
+    MBC1,
+    MBC2,
+    MMM01,
+    MBC3,
+    MBC5,
+    MBC6,
+    MBC7,
+    PocketCamera,
+    BandaiTama5,
+    HuC3,
+    HuC1,
+}
+
+impl fmt::Display for Mapper {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Mapper::RomOnly => "ROM ONLY",
+            Mapper::MBC1 => "MBC1",
+            Mapper::MBC2 => "MBC2",
+            Mapper::MMM01 => "MMM01",
+            Mapper::MBC3 => "MBC3",
+            Mapper::MBC5 => "MBC5",
+            Mapper::MBC6 => "MBC6",
+            Mapper::MBC7 => "MBC7",
+            Mapper::PocketCamera => "POCKET CAMERA",
+            Mapper::BandaiTama5 => "BANDAI TAMA5",
+            Mapper::HuC3 => "HuC3",
+            Mapper::HuC1 => "HuC1",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/**
+ * Byte 0x147 of the header. Tells us the mapper and any extra hardware on
+ * the cartridge
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CartridgeType {
+    pub code: u8,
+    pub mapper: Mapper,
+    pub has_ram: bool,
+    pub has_battery: bool,
+    pub has_rtc: bool,
+    pub has_rumble: bool,
+}
+
+impl CartridgeType {
+    pub fn from_code(code: u8) -> Result<Self, HeaderError> {
+        //(mapper, ram, battery, rtc, rumble)
+        let (mapper, has_ram, has_battery, has_rtc, has_rumble) = match code {
+            0x00 => (Mapper::RomOnly, false, false, false, false),
+            0x01 => (Mapper::MBC1, false, false, false, false),
+            0x02 => (Mapper::MBC1, true, false, false, false),
+            0x03 => (Mapper::MBC1, true, true, false, false),
+            0x05 => (Mapper::MBC2, false, false, false, false),
+            0x06 => (Mapper::MBC2, false, true, false, false),
+            0x08 => (Mapper::RomOnly, true, false, false, false),
+            0x09 => (Mapper::RomOnly, true, true, false, false),
+            0x0B => (Mapper::MMM01, false, false, false, false),
+            0x0C => (Mapper::MMM01, true, false, false, false),
+            0x0D => (Mapper::MMM01, true, true, false, false),
+            0x0F => (Mapper::MBC3, false, true, true, false),
+            0x10 => (Mapper::MBC3, true, true, true, false),
+            0x11 => (Mapper::MBC3, false, false, false, false),
+            0x12 => (Mapper::MBC3, true, false, false, false),
+            0x13 => (Mapper::MBC3, true, true, false, false),
+            0x19 => (Mapper::MBC5, false, false, false, false),
+            0x1A => (Mapper::MBC5, true, false, false, false),
+            0x1B => (Mapper::MBC5, true, true, false, false),
+            0x1C => (Mapper::MBC5, false, false, false, true),
+            0x1D => (Mapper::MBC5, true, false, false, true),
+            0x1E => (Mapper::MBC5, true, true, false, true),
+            0x20 => (Mapper::MBC6, false, false, false, false),
+            0x22 => (Mapper::MBC7, true, true, false, true),
+            0xFC => (Mapper::PocketCamera, true, true, false, false),     //128KB of SRAM for the photos
+            0xFD => (Mapper::BandaiTama5, false, false, false, false),
+            0xFE => (Mapper::HuC3, false, false, false, false),
+            0xFF => (Mapper::HuC1, true, true, false, false),
+            _ => return Err(HeaderError::UnknownCartridgeType(code)),
+        };
+
+        return Ok(Self { code, mapper, has_ram, has_battery, has_rtc, has_rumble });
+    }
+}
+
+impl fmt::Display for CartridgeType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.mapper)?;
+        if self.has_rtc {
+            write!(f, "+TIMER")?;
+        }
+        if self.has_rumble {
+            write!(f, "+RUMBLE")?;
+        }
+        if self.has_ram {
+            write!(f, "+RAM")?;
+        }
+        if self.has_battery {
+            write!(f, "+BATTERY")?;
+        }
+        return Ok(());
+    }
+}
+
+/**
+ * Everything stored in the cartridge header (0x100 - 0x14F)
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct CartridgeHeader {
+    pub title: String,
+    pub manufacturer_code: Option<String>,  //Only newer cartridges have this
+    pub cgb_support: CgbSupport,
+    pub new_licensee_code: Option<String>,  //Only used when the old licensee code is 0x33
+    pub sgb_support: bool,
+    pub cartridge_type: CartridgeType,
+    pub rom_size: ROMSize,
+    pub ram_size: RAMSize,
+    pub destination: Destination,
+    pub old_licensee_code: u8,
+    pub version: u8,
+    pub header_checksum: u8,
+    pub global_checksum: u16,
+}
+
+impl CartridgeHeader {
+    /**
+     * Parsing the header out of the start of a ROM. The slice only needs to go
+     * up to the end of the header, it doesn't need to be the whole ROM
+     */
+    pub fn parse(rom: &[u8]) -> Result<Self, HeaderError> {
+        if rom.len() <= HEADER_END {
+            return Err(HeaderError::TooShort(rom.len()));
+        }
+
+        let cgb_support = match rom[CGB_FLAG] {
+            0x80 => CgbSupport::Enhanced,
+            0xC0 => CgbSupport::Required,
+            _ => CgbSupport::None,
+        };
+
+        //On CGB carts the end of the title got repurposed for the manufacturer code and CGB flag
+        let manufacturer_code = &rom[MANUFACTURER_CODE_START..CGB_FLAG];
+        let (title_end, manufacturer_code) = if cgb_support == CgbSupport::None {
+            (CGB_FLAG + 1, None)
+        } else if manufacturer_code.iter().all(|byte| byte.is_ascii_uppercase() || byte.is_ascii_digit()) {
+            (MANUFACTURER_CODE_START, Some(ascii_string(manufacturer_code)))
+        } else {
+            (CGB_FLAG, None)
+        };
+
+        let old_licensee_code = rom[OLD_LICENSEE_CODE];
+        let new_licensee_code = match old_licensee_code {
+            USE_NEW_LICENSEE_CODE => Some(ascii_string(&rom[NEW_LICENSEE_CODE_START..SGB_FLAG])),
+            _ => None,
+        };
+
+        let destination = match rom[DESTINATION_CODE] {
+            0x00 => Destination::Japan,
+            0x01 => Destination::Overseas,
+            code => Destination::Unknown(code),
+        };
+
+        return Ok(Self {
+            title: ascii_string(&rom[TITLE_START..title_end]),
+            manufacturer_code,
+            cgb_support,
+            new_licensee_code,
+            sgb_support: rom[SGB_FLAG] == 0x03,
+            cartridge_type: CartridgeType::from_code(rom[CARTRIDGE_TYPE])?,
+            rom_size: ROMSize::from_code(rom[ROM_SIZE])?,
+            ram_size: RAMSize::from_code(rom[RAM_SIZE])?,
+            destination,
+            old_licensee_code,
+            version: rom[VERSION_NUMBER],
+            header_checksum: rom[HEADER_CHECKSUM],
+            global_checksum: u16::from_be_bytes([rom[GLOBAL_CHECKSUM], rom[GLOBAL_CHECKSUM + 1]]),
+        });
+    }
+}
+
+/**
+ * Computing what the header checksum (0x14D) should be. It's a checksum of
+ * bytes 0x134 - 0x14C and the boot rom refuses to start the game if it's wrong
+ */
+pub fn compute_header_checksum(rom: &[u8]) -> u8 {
+    return rom[TITLE_START..HEADER_CHECKSUM].iter().fold(0u8, |checksum, byte| checksum.wrapping_sub(*byte).wrapping_sub(1));
+}
+
+/**
+ * A 32KB ROM only cartridge that jumps from the entry point to code at 0x150,
+ * for tests that need the cpu to run something
+ */
+#[cfg(test)]
+pub(crate) fn test_rom(code: &[u8]) -> Vec<u8> {
+    let mut rom = vec![0; 0x8000];
+    rom[0x100..0x104].copy_from_slice(&[0x00, 0xC3, 0x50, 0x01]);     //NOP; JP 0x150
+    rom[0x150..0x150 + code.len()].copy_from_slice(code);
+    rom[HEADER_CHECKSUM] = compute_header_checksum(&rom);
+    return rom;
+}
+
+/**
+ * Computing what the global checksum (0x14E - 0x14F) should be. It's the sum
+ * of every byte in the rom except the checksum itself. Nothing actually checks it
+ */
+pub fn compute_global_checksum(rom: &[u8]) -> u16 {
+    return rom.iter()
+              .enumerate()
+              .filter(|(idx, _)| *idx != GLOBAL_CHECKSUM && *idx != GLOBAL_CHECKSUM + 1)
+              .fold(0u16, |checksum, (_, byte)| checksum.wrapping_add(*byte as u16));
+}
+
+/**
+ * Whether the Nintendo logo at 0x104 - 0x133 is intact. The boot rom locks up
+ * if it isn't
+ */
+pub fn has_valid_logo(rom: &[u8]) -> bool {
+    return rom.get(LOGO_START..LOGO_START + NINTENDO_LOGO.len()) == Some(&NINTENDO_LOGO[..]);
+}
+
+/**
+ * Turning a padded header field into a string. Stops at the first 0x00 and
+ * drops anything that isn't printable
+ */
+fn ascii_string(bytes: &[u8]) -> String {
+    return bytes.iter()
+                .take_while(|byte| **byte != 0)
+                .filter(|byte| byte.is_ascii_graphic() || **byte == b' ')
+                .map(|byte| *byte as char)
+                .collect::<String>()
+                .trim_end()
+                .to_string();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn blank_header() -> Vec<u8> {
+        let mut rom = vec![0; HEADER_END + 1];
+        rom[TITLE_START..TITLE_START + 8].copy_from_slice(b"POKEMON ");
+        rom[MANUFACTURER_CODE_START..CGB_FLAG].copy_from_slice(b"AAXE");
+        rom[CGB_FLAG] = 0x80;
+        rom[OLD_LICENSEE_CODE] = USE_NEW_LICENSEE_CODE;
+        rom[NEW_LICENSEE_CODE_START..SGB_FLAG].copy_from_slice(b"01");
+        rom[SGB_FLAG] = 0x03;
+        rom[CARTRIDGE_TYPE] = 0x10;
+        rom[ROM_SIZE] = 0x6;
+        rom[RAM_SIZE] = 0x3;
+        rom[DESTINATION_CODE] = 0x01;
+        rom[GLOBAL_CHECKSUM] = 0x12;
+        rom[GLOBAL_CHECKSUM + 1] = 0x34;
+        rom
+    }
+
+    #[test]
+    fn parses_cgb_header() {
+        let header = CartridgeHeader::parse(&blank_header()).unwrap();
+        assert_eq!(header.title, "POKEMON");
+        assert_eq!(header.manufacturer_code.as_deref(), Some("AAXE"));
+        assert_eq!(header.cgb_support, CgbSupport::Enhanced);
+        assert_eq!(header.new_licensee_code.as_deref(), Some("01"));
+        assert!(header.sgb_support);
+        assert_eq!(header.cartridge_type.to_string(), "MBC3+TIMER+RAM+BATTERY");
+        assert_eq!((header.rom_size, header.ram_size), (ROMSize::_2MiB, RAMSize::_32KiB));
+        assert_eq!(header.destination, Destination::Overseas);
+        assert_eq!(header.global_checksum, 0x1234);
+    }
+
+    #[test]
+    fn pocket_camera_keeps_its_photos() {
+        let camera = CartridgeType::from_code(0xFC).unwrap();
+        assert!(camera.has_ram && camera.has_battery);
+    }
+
+    #[test]
+    fn reports_bad_headers() {
+        assert_eq!(CartridgeHeader::parse(&[0; 0x100]), Err(HeaderError::TooShort(0x100)));
+
+        let mut rom = blank_header();
+        rom[CARTRIDGE_TYPE] = 0x04;
+        assert_eq!(CartridgeHeader::parse(&rom), Err(HeaderError::UnknownCartridgeType(0x04)));
+
+        let mut rom = blank_header();
+        rom[RAM_SIZE] = 0x1;
+        assert_eq!(CartridgeHeader::parse(&rom), Err(HeaderError::UnknownRamSize(0x01)));
+    }
+
+    #[test]
+    fn checks_checksums_and_logo() {
+        let mut rom = vec![0; HEADER_END + 1];
+        assert_eq!(compute_header_checksum(&rom), 0xE7);    //0 - 25 bytes
+        assert!(!has_valid_logo(&rom));
+
+        rom[LOGO_START..LOGO_START + NINTENDO_LOGO.len()].copy_from_slice(&NINTENDO_LOGO);
+        rom[GLOBAL_CHECKSUM] = 0xFF;
+        rom[GLOBAL_CHECKSUM + 1] = 0xFF;
+        assert!(has_valid_logo(&rom));
+        assert_eq!(compute_global_checksum(&rom), NINTENDO_LOGO.iter().map(|byte| *byte as u16).sum::<u16>());
+    }
+}
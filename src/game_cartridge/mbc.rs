@@ -1,3 +1,7 @@
+use super::rtc::Rtc;
+use crate::error::Error;
+use crate::save_state::{StateReader, StateWriter};
+
 #[derive(Debug)]
 pub struct MBC1 {
     pub ram_enable: bool,
@@ -7,6 +11,12 @@ pub struct MBC1 {
     pub is_mbc1m_cart: bool,
 }
 
+impl Default for MBC1 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl MBC1 {
     pub fn new() -> Self {
         Self {
@@ -67,38 +77,108 @@ impl MBC1 {
     pub fn is_ram_enabled(&self) -> bool {
         return self.ram_enable;
     }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.ram_enable);
+        state.write_u8(self.rom_bank_num);
+        state.write_u8(self.ram_bank_num);
+        state.write_u8(self.banking_mode_sel);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), Error> {
+        self.ram_enable = state.read_bool()?;
+        self.rom_bank_num = state.read_u8()?;
+        self.ram_bank_num = state.read_u8()?;
+        self.banking_mode_sel = state.read_u8()?;
+
+        //Anything the registers can't be written to. The bank numbers get
+        //masked to the cartridge's size when they're used
+        if self.rom_bank_num > 0x1F || self.ram_bank_num > 0x3 || self.banking_mode_sel > 1 {
+            return Err(Error::CorruptSaveState);
+        }
+        return Ok(());
+    }
 }
 
 #[derive(Debug)]
 pub struct MBC2 {
+    pub ram_enable: bool,
+    pub rom_bank_num: u8,
+    pub ram: Vec<u8>,           //Built-in 512x4 bits of RAM. Only the lower nibble of each byte is used
+}
 
+impl Default for MBC2 {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl MBC2 {
     pub fn new() -> Self {
         Self {
-
+            ram_enable: false,
+            rom_bank_num: 1,
+            ram: vec![0; 0x200],
         }
     }
 
-    pub fn write_0x0000_to_0x1fff(&mut self, value: u8) {
-        todo!()
+    pub fn write_0x0000_to_0x1fff(&mut self, value: u8, address: u16, bank_bit_mask: u16) {
+        self.write_register(value, address, bank_bit_mask);
     }
 
-    pub fn write_0x2000_to_0x3fff(&mut self, value: u8) {
-        todo!()
+    pub fn write_0x2000_to_0x3fff(&mut self, value: u8, address: u16, bank_bit_mask: u16) {
+        self.write_register(value, address, bank_bit_mask);
     }
 
-    pub fn write_0x4000_to_0x5fff(&mut self, value: u8) {
-        todo!()
+    /**
+     * MBC2 only has one register range (0x0000 - 0x3FFF). Bit 8 of the address
+     * decides what we're writing to. If it's clear then it's the ram enable 
+     * register, otherwise it's the 4 bit rom bank number. Like MBC1 a bank
+     * number of 0 gets bumped up to 1
+     */
+    fn write_register(&mut self, value: u8, address: u16, bank_bit_mask: u16) {
+        if (address & 0x100) == 0 {
+            self.ram_enable = (value & 0xF) == 0xA;
+        } else {
+            let mut rom_bank_num = value & 0xF;
+            if rom_bank_num == 0 {
+                rom_bank_num = 1;
+            }
+            self.rom_bank_num = rom_bank_num & bank_bit_mask as u8;
+        }
     }
 
-    pub fn write_0x6000_to_0x7fff(&mut self, value: u8) {
-        todo!()
+    /**
+     * The built-in RAM only has 512 addresses, so it echoes across the whole 
+     * A000 - BFFF range. The upper nibble isn't connected and always reads as 1s
+     */
+    pub fn read_ram(&self, idx: u16) -> u8 {
+        return self.ram[(idx & 0x1FF) as usize] | 0xF0;
+    }
+
+    pub fn write_ram(&mut self, value: u8, idx: u16) {
+        self.ram[(idx & 0x1FF) as usize] = value & 0x0F;
     }
 
     pub fn is_ram_enabled(&self) -> bool {
-        todo!()
+        return self.ram_enable;
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.ram_enable);
+        state.write_u8(self.rom_bank_num);
+        state.write_bytes(&self.ram);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader, rom_banks: usize) -> Result<(), Error> {
+        self.ram_enable = state.read_bool()?;
+        self.rom_bank_num = state.read_u8()?;
+        state.read_into(&mut self.ram)?;
+
+        if self.rom_bank_num as usize >= rom_banks {
+            return Err(Error::CorruptSaveState);
+        }
+        return Ok(());
     }
 }
 
@@ -106,12 +186,14 @@ impl MBC2 {
 pub struct MBC3 {
     pub ram_and_timer_enable: bool,
     pub rom_bank_num: u8,
-    pub ram_bank_num: u8,
-    pub rtc_seconds: u8,
-    pub rtc_minutes: u8,
-    pub rtc_hours: u8,
-    pub rtc_day_lower: u8,
-    pub rtc_day_upper: u8,
+    pub ram_bank_num: u8,       //0x08 - 0x0C selects one of the RTC registers instead of a ram bank
+    pub rtc: Rtc,
+}
+
+impl Default for MBC3 {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl MBC3 {
@@ -120,11 +202,7 @@ impl MBC3 {
             ram_and_timer_enable: false,
             rom_bank_num: 1,
             ram_bank_num: 0,
-            rtc_seconds: 0,
-            rtc_minutes: 0,
-            rtc_hours: 0,
-            rtc_day_lower: 0,
-            rtc_day_upper: 0,
+            rtc: Rtc::new(),
         }
     }
 
@@ -148,12 +226,36 @@ impl MBC3 {
     }
 
     pub fn write_latch_clock_data(&mut self, value: u8) {
-        ()
+        self.rtc.write_latch(value);
+    }
+
+    pub fn is_rtc_selected(&self) -> bool {
+        return (0x8..=0xC).contains(&self.ram_bank_num);
     }
 
     pub fn is_ram_and_timer_enabled(&self) -> bool {
         return self.ram_and_timer_enable;
     }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.ram_and_timer_enable);
+        state.write_u8(self.rom_bank_num);
+        state.write_u8(self.ram_bank_num);
+        self.rtc.save_state(state);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader, rom_banks: usize, ram_banks: usize) -> Result<(), Error> {
+        self.ram_and_timer_enable = state.read_bool()?;
+        self.rom_bank_num = state.read_u8()?;
+        self.ram_bank_num = state.read_u8()?;
+
+        //Bank 0 is fine without any RAM since nothing gets mapped in then
+        let is_ram_bank_valid = (self.ram_bank_num as usize) < ram_banks.max(1);
+        if self.rom_bank_num as usize >= rom_banks || !(self.is_rtc_selected() || is_ram_bank_valid) {
+            return Err(Error::CorruptSaveState);
+        }
+        return self.rtc.load_state(state);
+    }
 }
 
 #[derive(Debug)]
@@ -163,6 +265,12 @@ pub struct MBC5 {
     pub sram_bank_num: u8,
 }
 
+impl Default for MBC5 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl MBC5 {
     pub fn new() -> Self {
         Self {
@@ -203,4 +311,22 @@ impl MBC5 {
     pub fn is_ram_enabled(&self) -> bool {
         return self.ram_enable; 
     }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.ram_enable);
+        state.write_u16(self.rom_bank_num);
+        state.write_u8(self.sram_bank_num);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader, rom_banks: usize, ram_banks: usize) -> Result<(), Error> {
+        self.ram_enable = state.read_bool()?;
+        self.rom_bank_num = state.read_u16()?;
+        self.sram_bank_num = state.read_u8()?;
+
+        //Bank 0 is fine without any RAM since nothing gets mapped in then
+        if self.rom_bank_num as usize >= rom_banks || self.sram_bank_num as usize >= ram_banks.max(1) {
+            return Err(Error::CorruptSaveState);
+        }
+        return Ok(());
+    }
 }
\ No newline at end of file
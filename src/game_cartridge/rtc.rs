@@ -0,0 +1,471 @@
+use std::fmt::Debug;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::error::Error;
+use crate::save_state::{StateReader, StateWriter};
+
+const CLK_TICKS_PER_SECOND: u32 = 4194304;
+const SECONDS_PER_DAY: u64 = 86400;
+const MAX_DAYS: u64 = 512;          //The day counter is only 9 bits
+
+const SECONDS_MASK: u8 = 0x3F;
+const MINUTES_MASK: u8 = 0x3F;
+const HOURS_MASK: u8 = 0x1F;
+const DAY_UPPER_MASK: u8 = 0xC1;    //Bit 0 -> day bit 8, Bit 6 -> halt, Bit 7 -> day carry
+const HALT_BIT: u8 = 0x40;
+const DAY_CARRY_BIT: u8 = 0x80;
+
+pub const SAVE_FOOTER_LEN: usize = 48;          //10 registers as u32s + a u64 unix timestamp
+pub const SHORT_SAVE_FOOTER_LEN: usize = 44;    //Older VBA style footer that only has a u32 timestamp
+
+/**
+ * Anything that can tell the RTC what time it currently is. This is mostly here
+ * so tests can drive the clock without depending on the wall clock
+ */
+pub trait RtcClock: Debug {
+    fn unix_time(&self) -> u64;     //Seconds since the unix epoch
+}
+
+#[derive(Debug)]
+pub struct SystemClock;
+
+impl RtcClock for SystemClock {
+    fn unix_time(&self) -> u64 {
+        return SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or(0);
+    }
+}
+
+/**
+ * What makes the RTC tick forward
+ */
+#[derive(Debug)]
+pub enum RtcSource {
+    Cycles,                         //Counting cpu clk ticks. 4194304 of them make up one second
+    HostTime(Box<dyn RtcClock>),    //Catching up to the host clock whenever the game touches the RTC
+}
+
+/**
+ * The raw values of the 5 RTC registers (0x08 - 0x0C)
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct RtcRegisters {
+    pub seconds: u8,
+    pub minutes: u8,
+    pub hours: u8,
+    pub day_lower: u8,
+    pub day_upper: u8,
+}
+
+impl RtcRegisters {
+    fn days(&self) -> u64 {
+        return (((self.day_upper & 0x1) as u64) << 8) | self.day_lower as u64;
+    }
+
+    fn set_days(&mut self, days: u64) {
+        self.day_lower = days as u8;
+        self.day_upper = (self.day_upper & !0x1) | ((days >> 8) & 0x1) as u8;
+    }
+
+    fn is_halted(&self) -> bool {
+        return (self.day_upper & HALT_BIT) != 0;
+    }
+
+    /**
+     * Registers can be written with values that are out of range (ex. 61 seconds).
+     * When that happens they just count up until they wrap around their bit width
+     */
+    fn is_in_range(&self) -> bool {
+        return self.seconds < 60 && self.minutes < 60 && self.hours < 24;
+    }
+}
+
+/**
+ * The MBC3 real time clock. The game only ever reads the latched copy of the
+ * registers, which gets refreshed by writing 0x00 then 0x01 to 0x6000 - 0x7FFF
+ */
+#[derive(Debug)]
+pub struct Rtc {
+    pub live: RtcRegisters,
+    pub latched: RtcRegisters,
+    source: RtcSource,
+    clk_ticks: u32,             //Sub-second ticks when driven by cycles
+    last_sync: u64,             //Unix time we last caught up to when driven by host time
+    prev_latch_write: u8,
+}
+
+impl Default for Rtc {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Rtc {
+    pub fn new() -> Self {
+        Self::with_source(RtcSource::HostTime(Box::new(SystemClock)))
+    }
+
+    pub fn with_source(source: RtcSource) -> Self {
+        let last_sync = match &source {
+            RtcSource::Cycles => 0,
+            RtcSource::HostTime(clock) => clock.unix_time(),
+        };
+
+        Self {
+            live: RtcRegisters::default(),
+            latched: RtcRegisters::default(),
+            source,
+            clk_ticks: 0,
+            last_sync,
+            prev_latch_write: 0xFF,
+        }
+    }
+
+    /**
+     * Swapping out what drives the clock. The register values are kept as is
+     */
+    pub fn set_source(&mut self, source: RtcSource) {
+        if let RtcSource::HostTime(clock) = &source {
+            self.last_sync = clock.unix_time();
+        }
+        self.source = source;
+    }
+
+    /**
+     * Mimicking one cpu clk cycle. Only does something when the RTC is driven
+     * by emulated cycles
+     */
+    pub fn cycle(&mut self) {
+        if let RtcSource::Cycles = self.source {
+            if !self.live.is_halted() {
+                self.clk_ticks += 1;
+                if self.clk_ticks == CLK_TICKS_PER_SECOND {
+                    self.clk_ticks = 0;
+                    self.tick_second();
+                }
+            }
+        }
+    }
+
+    /**
+     * What time the host thinks it is. When we're driven by cycles we still
+     * need this to timestamp save files
+     */
+    fn host_time(&self) -> u64 {
+        return match &self.source {
+            RtcSource::Cycles => SystemClock.unix_time(),
+            RtcSource::HostTime(clock) => clock.unix_time(),
+        };
+    }
+
+    /**
+     * Catching the live registers up to the host clock. Time spent halted
+     * doesn't count
+     */
+    pub fn sync(&mut self) {
+        if let RtcSource::HostTime(clock) = &self.source {
+            let now = clock.unix_time();
+            if now > self.last_sync && !self.live.is_halted() {
+                self.advance_seconds(now - self.last_sync);
+            }
+            self.last_sync = now;
+        }
+    }
+
+    /**
+     * Moving the live registers forward by some number of seconds, carrying
+     * into minutes, hours and days. Overflowing the 9 bit day counter sets the
+     * day carry flag, which stays set until the game clears it
+     */
+    pub fn advance_seconds(&mut self, mut seconds: u64) {
+        //Out of range values have to be ticked one by one until they wrap
+        while seconds > 0 && !self.live.is_in_range() {
+            self.tick_second();
+            seconds -= 1;
+        }
+
+        if seconds == 0 {
+            return;
+        }
+
+        let total = self.live.seconds as u64
+                    + self.live.minutes as u64 * 60
+                    + self.live.hours as u64 * 3600
+                    + self.live.days() * SECONDS_PER_DAY
+                    + seconds;
+
+        let days = total / SECONDS_PER_DAY;
+        if days >= MAX_DAYS {
+            self.live.day_upper |= DAY_CARRY_BIT;
+        }
+
+        self.live.seconds = (total % 60) as u8;
+        self.live.minutes = ((total / 60) % 60) as u8;
+        self.live.hours = ((total / 3600) % 24) as u8;
+        self.live.set_days(days % MAX_DAYS);
+    }
+
+    fn tick_second(&mut self) {
+        self.live.seconds = (self.live.seconds + 1) & SECONDS_MASK;
+        if self.live.seconds != 60 {
+            return;
+        }
+        self.live.seconds = 0;
+
+        self.live.minutes = (self.live.minutes + 1) & MINUTES_MASK;
+        if self.live.minutes != 60 {
+            return;
+        }
+        self.live.minutes = 0;
+
+        self.live.hours = (self.live.hours + 1) & HOURS_MASK;
+        if self.live.hours != 24 {
+            return;
+        }
+        self.live.hours = 0;
+
+        let days = self.live.days() + 1;
+        if days == MAX_DAYS {
+            self.live.day_upper |= DAY_CARRY_BIT;
+        }
+        self.live.set_days(days % MAX_DAYS);
+    }
+
+    /**
+     * Writing 0x00 and then 0x01 copies the live registers into the latched
+     * ones that the game can read
+     */
+    pub fn write_latch(&mut self, value: u8) {
+        if self.prev_latch_write == 0x00 && value == 0x01 {
+            self.sync();
+            self.latched = self.live;
+        }
+        self.prev_latch_write = value;
+    }
+
+    /**
+     * Reading one of the latched RTC registers. reg_sel is the value written
+     * to 0x4000 - 0x5FFF (0x08 - 0x0C)
+     */
+    pub fn read_register(&self, reg_sel: u8) -> u8 {
+        match reg_sel {
+            0x8 => self.latched.seconds,
+            0x9 => self.latched.minutes,
+            0xA => self.latched.hours,
+            0xB => self.latched.day_lower,
+            0xC => self.latched.day_upper,
+            _ => 0xFF,
+        }
+    }
+
+    /**
+     * Writing to one of the live RTC registers. Only the bits each register
+     * actually has are kept. Writing the seconds also resets the sub-second counter
+     */
+    pub fn write_register(&mut self, reg_sel: u8, value: u8) {
+        self.sync();
+        match reg_sel {
+            0x8 => {
+                self.live.seconds = value & SECONDS_MASK;
+                self.clk_ticks = 0;
+            },
+            0x9 => self.live.minutes = value & MINUTES_MASK,
+            0xA => self.live.hours = value & HOURS_MASK,
+            0xB => self.live.day_lower = value,
+            0xC => self.live.day_upper = value & DAY_UPPER_MASK,
+            _ => (),
+        }
+    }
+
+    /**
+     * Building the footer that gets appended to the save file. This is the 
+     * layout VBA and BGB use: the live registers, then the latched registers,
+     * each stored as a little endian u32, followed by a u64 unix timestamp
+     */
+    pub fn save_footer(&mut self) -> Vec<u8> {
+        self.sync();
+
+        let mut footer = Vec::with_capacity(SAVE_FOOTER_LEN);
+        for registers in [self.live, self.latched] {
+            for value in [registers.seconds, registers.minutes, registers.hours, registers.day_lower, registers.day_upper] {
+                footer.extend_from_slice(&(value as u32).to_le_bytes());
+            }
+        }
+        footer.extend_from_slice(&self.host_time().to_le_bytes());
+        return footer;
+    }
+
+    /**
+     * Restoring the registers from a save file footer and fast forwarding by
+     * however much real time passed since it was written. Returns false if the
+     * footer isn't one we understand
+     */
+    pub fn load_save_footer(&mut self, footer: &[u8]) -> bool {
+        let timestamp = match footer.len() {
+            SAVE_FOOTER_LEN => u64::from_le_bytes(footer[40..48].try_into().unwrap()),
+            SHORT_SAVE_FOOTER_LEN => u32::from_le_bytes(footer[40..44].try_into().unwrap()) as u64,
+            _ => return false,
+        };
+
+        let register = |idx: usize| footer[idx * 4];
+        self.live = RtcRegisters {
+            seconds: register(0) & SECONDS_MASK,
+            minutes: register(1) & MINUTES_MASK,
+            hours: register(2) & HOURS_MASK,
+            day_lower: register(3),
+            day_upper: register(4) & DAY_UPPER_MASK,
+        };
+        self.latched = RtcRegisters {
+            seconds: register(5) & SECONDS_MASK,
+            minutes: register(6) & MINUTES_MASK,
+            hours: register(7) & HOURS_MASK,
+            day_lower: register(8),
+            day_upper: register(9) & DAY_UPPER_MASK,
+        };
+
+        let now = self.host_time();
+        if now > timestamp && !self.live.is_halted() {
+            self.advance_seconds(now - timestamp);
+        }
+        self.last_sync = now;
+        return true;
+    }
+
+    /**
+     * Unlike the save file footer this doesn't sync first. When driven by host
+     * time it'll catch up from last_sync once the state is loaded
+     */
+    pub fn save_state(&self, state: &mut StateWriter) {
+        for registers in [self.live, self.latched] {
+            for value in [registers.seconds, registers.minutes, registers.hours, registers.day_lower, registers.day_upper] {
+                state.write_u8(value);
+            }
+        }
+        state.write_u32(self.clk_ticks);
+        state.write_u64(self.last_sync);
+        state.write_u8(self.prev_latch_write);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), Error> {
+        for registers in [&mut self.live, &mut self.latched] {
+            registers.seconds = state.read_u8()? & SECONDS_MASK;
+            registers.minutes = state.read_u8()? & MINUTES_MASK;
+            registers.hours = state.read_u8()? & HOURS_MASK;
+            registers.day_lower = state.read_u8()?;
+            registers.day_upper = state.read_u8()? & DAY_UPPER_MASK;
+        }
+        self.clk_ticks = state.read_u32()?;
+        self.last_sync = state.read_u64()?;
+        self.prev_latch_write = state.read_u8()?;
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, rc::Rc};
+
+    use super::*;
+
+    #[derive(Debug)]
+    struct FakeClock(Rc<Cell<u64>>);
+
+    impl RtcClock for FakeClock {
+        fn unix_time(&self) -> u64 {
+            self.0.get()
+        }
+    }
+
+    fn rtc_with_fake_clock() -> (Rtc, Rc<Cell<u64>>) {
+        let time = Rc::new(Cell::new(1_000_000));
+        let rtc = Rtc::with_source(RtcSource::HostTime(Box::new(FakeClock(time.clone()))));
+        (rtc, time)
+    }
+
+    fn latch(rtc: &mut Rtc) {
+        rtc.write_latch(0x00);
+        rtc.write_latch(0x01);
+    }
+
+    #[test]
+    fn reads_only_change_after_latching() {
+        let (mut rtc, time) = rtc_with_fake_clock();
+        time.set(time.get() + 3661);
+        assert_eq!(rtc.read_register(0x8), 0);
+
+        latch(&mut rtc);
+        assert_eq!((rtc.read_register(0xA), rtc.read_register(0x9), rtc.read_register(0x8)), (1, 1, 1));
+
+        //Writing 0x01 again without a 0x00 first doesn't latch
+        time.set(time.get() + 5);
+        rtc.write_latch(0x01);
+        assert_eq!(rtc.read_register(0x8), 1);
+    }
+
+    #[test]
+    fn halt_stops_the_clock() {
+        let (mut rtc, time) = rtc_with_fake_clock();
+        rtc.write_register(0xC, HALT_BIT);
+        time.set(time.get() + 100);
+        latch(&mut rtc);
+        assert_eq!(rtc.read_register(0x8), 0);
+
+        rtc.write_register(0xC, 0);
+        time.set(time.get() + 7);
+        latch(&mut rtc);
+        assert_eq!(rtc.read_register(0x8), 7);
+    }
+
+    #[test]
+    fn day_overflow_sets_sticky_carry() {
+        let (mut rtc, time) = rtc_with_fake_clock();
+        rtc.write_register(0xB, 0xFF);
+        rtc.write_register(0xC, 0x01);
+        rtc.write_register(0xA, 23);
+        rtc.write_register(0x9, 59);
+        rtc.write_register(0x8, 59);
+        time.set(time.get() + 1);
+        latch(&mut rtc);
+        assert_eq!(rtc.read_register(0xB), 0);
+        assert_eq!(rtc.read_register(0xC), DAY_CARRY_BIT);
+
+        time.set(time.get() + SECONDS_PER_DAY);
+        latch(&mut rtc);
+        assert_eq!(rtc.read_register(0xB), 1);
+        assert_eq!(rtc.read_register(0xC), DAY_CARRY_BIT);
+    }
+
+    #[test]
+    fn register_writes_are_masked() {
+        let (mut rtc, _) = rtc_with_fake_clock();
+        rtc.write_register(0x8, 0xFF);
+        rtc.write_register(0x9, 0xFF);
+        rtc.write_register(0xA, 0xFF);
+        rtc.write_register(0xC, 0xFF);
+        assert_eq!(rtc.live, RtcRegisters { seconds: 0x3F, minutes: 0x3F, hours: 0x1F, day_lower: 0, day_upper: 0xC1 });
+    }
+
+    #[test]
+    fn save_footer_round_trip_fast_forwards() {
+        let (mut rtc, time) = rtc_with_fake_clock();
+        rtc.write_register(0x9, 10);
+        latch(&mut rtc);
+        let footer = rtc.save_footer();
+        assert_eq!(footer.len(), SAVE_FOOTER_LEN);
+
+        let (mut restored, restored_time) = rtc_with_fake_clock();
+        restored_time.set(time.get() + 90);
+        assert!(restored.load_save_footer(&footer));
+        assert_eq!(restored.latched, rtc.latched);
+        assert_eq!((restored.live.minutes, restored.live.seconds), (11, 30));
+    }
+
+    #[test]
+    fn out_of_range_seconds_wrap_without_carry() {
+        let mut rtc = Rtc::with_source(RtcSource::Cycles);
+        rtc.write_register(0x8, 63);
+        for _ in 0..CLK_TICKS_PER_SECOND {
+            rtc.cycle();
+        }
+        assert_eq!((rtc.live.seconds, rtc.live.minutes), (0, 0));
+    }
+}
@@ -1,4 +1,5 @@
 mod cpu;
+mod apu;
 mod memory;
 mod ppu;
 mod timer;
@@ -9,154 +10,662 @@ mod interrupt_handler;
 mod opcodes;
 mod binary_utils;
 mod constants;
+mod inspector;
+mod watchpoint;
 
-use minifb::{Key, Scale, ScaleMode, Window, WindowOptions};
+use std::path::PathBuf;
 
 use crate::game_cartridge::GameCartridge;
+use crate::game_cartridge::rtc::RtcSource;
 use crate::gameboy::cpu::{Cpu, cpu_state};
 use crate::gameboy::memory::Memory;
+use crate::gameboy::watchpoint::Watchpoints;
 use crate::TestStatus;
+use crate::error::Error;
+use crate::save_state::{StateReader, StateWriter};
+use crate::rewind::{RewindBuffer, RewindConfig};
 
-const WIDTH: usize = 160;
-const HEIGHT: usize = 144;
+pub use self::joypad::Buttons;
+pub use self::serial_transfer::{SerialCallback, SerialSink, LinkCable, LinkMessage};
+pub use self::apu::SoundChannel;
+pub use self::inspector::{DebugState, Registers, PpuState, ApuState, ChannelState, EnvelopeState};
+pub use self::watchpoint::{Watchpoint, WatchKind, WatchHit};
+
+pub const WIDTH: usize = 160;
+pub const HEIGHT: usize = 144;
+pub const CYCLES_PER_FRAME: u32 = 70224;       //154 scanlines * 456 clk cycles
+pub const CLK_CYCLES_PER_SECOND: u32 = 4194304; //Which works out to about 59.73 frames a second
+pub const AUDIO_SAMPLE_RATE: u32 = 131072;     //One sample every 32 clk cycles
+const SAVE_INTERVAL_FRAMES: u32 = 300;     //Roughly every 5 seconds
+const BLARGG_SILENCE_TIMEOUT_CLK_CYCLES: u64 = 5 * CLK_CYCLES_PER_SECOND as u64;
+const BLARGG_SRAM_RESULT: u16 = 0xA000;
+const BLARGG_SRAM_SIGNATURE_START: u16 = 0xA001;
+const BLARGG_SRAM_SIGNATURE: [u8; 3] = [0xDE, 0xB0, 0x61];
+const BLARGG_SRAM_TEXT: u16 = 0xA004;
+const BLARGG_SRAM_RUNNING: u8 = 0x80;
+const SAVE_STATE_MAGIC: &[u8; 8] = b"BINTBOYS";
+const SAVE_STATE_VERSION: u16 = 5;
+
+/**
+ * Gets handed every stereo (left, right) sample the Game Boy outputs at
+ * AUDIO_SAMPLE_RATE. Samples are between -1.0 and 1.0
+ */
+pub type AudioCallback = Box<dyn FnMut(f32, f32)>;
+
+/**
+ * Gets handed what each channel added to every sample, in the order pulse 1,
+ * pulse 2, wave, noise. Adding them all up gives the sample the AudioCallback
+ * gets
+ */
+pub type ChannelAudioCallback = Box<dyn FnMut([(f32, f32); 4])>;
+
+/**
+ * Gets handed a line in gameboy-doctor's format, like
+ * A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02
+ * right before every instruction runs
+ */
+pub type TraceCallback = Box<dyn FnMut(&str)>;
 
 pub struct Gameboy {
     cpu: Cpu,
     memory: Memory,
+    save_path: Option<PathBuf>,     //Where battery backed SRAM gets saved to
+    frame_buffer: Vec<u32>,         //0RGB pixels, WIDTH * HEIGHT of them
+    buffer_index: usize,            //Where the ppu will put its next pixel
+    pub(crate) frame_completed: bool,   //The ppu just pushed the last pixel of a frame
+    cpu_cycle_pending: bool,            //run_frame_until stopped before the cpu's part of a clk cycle
+    frames_since_save: u32,
+    buttons: Buttons,
+    audio_callback: Option<AudioCallback>,
+    channel_audio_callback: Option<ChannelAudioCallback>,
+    trace_callback: Option<TraceCallback>,
+    rewind: Option<RewindBuffer>,   //Only there if rewinding was turned on
+    link_cable: Option<Box<dyn LinkCable>>,
+}
+
+impl Default for Gameboy {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Gameboy {
     pub fn new() -> Self {
+        let mut memory = Memory::new();
+        memory.ppu.activate_ppu();
+
         Gameboy { 
             cpu: Cpu::new(), 
-            memory: Memory::new()
+            memory,
+            save_path: None,
+            frame_buffer: vec![0; WIDTH * HEIGHT],
+            buffer_index: 0,
+            frame_completed: false,
+            cpu_cycle_pending: false,
+            frames_since_save: 0,
+            buttons: Buttons::default(),
+            audio_callback: None,
+            channel_audio_callback: None,
+            trace_callback: None,
+            rewind: None,
+            link_cable: None,
         }
     }
 
     /**
      * Loading the game cartridge from the file path specified. As well loading
-     * the gameboys 2 rom banks with the inital values.
+     * the gameboys 2 rom banks with the inital values. If the cartridge has a
+     * battery we also load <rom>.sav if there is one
+     */
+    pub fn initialize(&mut self, rom_file_path: &str) -> Result<(), Error> {
+        self.initialize_without_save(rom_file_path)?;
+
+        if self.memory.game_cartridge.has_battery {
+            let save_path = PathBuf::from(rom_file_path).with_extension("sav");
+            if let Err(e) = self.memory.game_cartridge.load_save_file(&save_path) {
+                eprintln!("Unable to load save file {}: {e}", save_path.display());
+            }
+            self.save_path = Some(save_path);
+        }
+        return Ok(());
+    }
+
+    /**
+     * Same as initialize, but SRAM never gets loaded from or written to a
+     * save file. The test roms run this way so they don't leave .sav files
+     * lying around next to them
      */
-    pub fn initialize(&mut self, rom_file_path: &str) {
+    pub fn initialize_without_save(&mut self, rom_file_path: &str) -> Result<(), Error> {
         let mut game_cartridge = GameCartridge::new();
-        game_cartridge.load_cartridge(rom_file_path);
+        game_cartridge.load_cartridge(rom_file_path)?;
 
         self.memory.game_cartridge = game_cartridge;
+        self.save_path = None;
+        return Ok(());
     }
 
     /**
-     * This is the starting point for the Game Boy. You just need to give it a
-     * rom file for it to run
+     * Loading a cartridge that's already in memory, like one we built
+     * ourselves. There's no save file for these, even if it has a battery
      */
-    pub fn run(&mut self) {
-        let mut buffer = vec![0u32; WIDTH * WIDTH];
-        let mut buffer_index: usize = 0;
-        let buff_max = WIDTH * HEIGHT;
-        let mut window = Self::initialize_window();
-        self.memory.ppu.activate_ppu();
+    pub fn load_rom(&mut self, rom: &[u8]) -> Result<(), Error> {
+        self.memory.game_cartridge = GameCartridge::load_from_bytes(rom)?;
+        self.save_path = None;
+        return Ok(());
+    }
 
-        let mut toggle_2x_speed = false;
-        let mut counter = 0;
-        
-        
-        while window.is_open() && !window.is_key_down(Key::Escape) {
-            if window.is_key_down(Key::F) {
-                if counter == 0 {
-                    toggle_2x_speed = !toggle_2x_speed;
-                    counter = 500000;
-                    println!("Toggle2x is: {}", toggle_2x_speed);
-                }
-                counter -= 1;
+    /**
+     * Writing battery backed SRAM out to the save file. Does nothing if the 
+     * cartridge doesn't have a battery
+     */
+    pub fn flush_save(&mut self) {
+        if let Some(save_path) = &self.save_path {
+            if let Err(e) = self.memory.game_cartridge.write_save_file(save_path) {
+                eprintln!("Unable to write save file {}: {e}", save_path.display());
             }
+        }
+    }
 
-            if toggle_2x_speed {
-                window.limit_update_rate(Some(std::time::Duration::from_micros(8333)));
-            } else {
-                window.limit_update_rate(Some(std::time::Duration::from_micros(16666)));
+    /**
+     * Makes the cartridge RTC (if there is one) count emulated cycles instead
+     * of following the host clock
+     */
+    pub fn use_emulated_rtc(&mut self) {
+        self.memory.game_cartridge.set_rtc_source(RtcSource::Cycles);
+    }
+
+    /**
+     * Runs the Game Boy until the cpu finishes its current instruction. If an
+     * interrupt gets serviced first, this will also run the first instruction
+     * of the handler
+     */
+    pub fn step_instruction(&mut self) {
+        loop {
+            let cpu_ran = self.cycle();
+            if cpu_ran && self.cpu.is_at_instruction_boundary() {
+                return;
             }
+        }
+    }
 
+    /**
+     * Runs the Game Boy until the ppu has drawn a whole frame. If the lcd is
+     * off we stop after a frame's worth of cycles instead
+     */
+    pub fn run_frame(&mut self) {
+        self.frame_completed = false;
+        let mut clk_cycles = 0;
+        while !self.frame_completed {
+            self.cycle();
 
-            self.memory.timer_cycle();
-            self.memory.dma_cycle();
-            self.memory.joypad_cycle(&window);
-            if self.memory.ppu.is_active() {
-                self.memory.gpu_cycle(&mut buffer, &mut buffer_index);
+            clk_cycles += 1;
+            if clk_cycles >= CYCLES_PER_FRAME && !self.memory.ppu.is_active() {
+                break;
             }
+        }
+        self.finish_frame();
+    }
+
+    pub(crate) fn is_lcd_on(&self) -> bool {
+        return self.memory.ppu.is_active();
+    }
+
+    /**
+     * Same as run_frame, but should_stop gets checked every time the cpu is
+     * about to start a new instruction. If it says to stop, we do so right
+     * away and return true. Calling this again picks the frame back up
+     */
+    pub fn run_frame_until(&mut self, mut should_stop: impl FnMut(&Gameboy) -> bool) -> bool {
+        //Picking back up where we stopped, which may have been the end of the frame
+        if self.cpu_cycle_pending {
+            self.cycle();
+        } else {
+            self.frame_completed = false;
+        }
 
-            if buffer_index == buff_max {
-                buffer_index = 0;
-                window.update_with_buffer(&buffer, WIDTH, HEIGHT).unwrap();
+        let mut clk_cycles = 0;
+        while !self.frame_completed {
+            //Stopping right before the cpu starts an instruction, so nothing
+            //has happened to it yet
+            match self.cycle_before_cpu() {
+                Some(true) if should_stop(self) => {
+                    self.cpu_cycle_pending = true;
+                    return true;
+                },
+                Some(_) => self.cpu.cycle(&mut self.memory),
+                None => (),
             }
 
-            //Only try to service an interrupt if you finished an instruction
-            match self.cpu.cpu_state {
-                cpu_state::CpuState::Fetch => self.memory.interrupt_cycle(&mut self.cpu.pc, &mut self.cpu.sp),
-                _ => (),
+            clk_cycles += 1;
+            if clk_cycles >= CYCLES_PER_FRAME && !self.memory.ppu.is_active() {
+                break;
             }
+        }
+        self.finish_frame();
+        return false;
+    }
+
+    /**
+     * Sitting right in front of the instruction at PC without run_frame_until
+     * having stopped there, like before anything has run
+     */
+    pub fn is_at_unchecked_instruction(&self) -> bool {
+        return !self.cpu_cycle_pending && self.is_about_to_fetch();
+    }
 
-            if !self.memory.interrupt_handler.handling_isr {
-                self.cpu.cycle(&mut self.memory);
+    /**
+     * At an instruction boundary and not halted
+     */
+    fn is_about_to_fetch(&self) -> bool {
+        return self.cpu.is_at_instruction_boundary() && matches!(self.cpu.cpu_state, cpu_state::CpuState::Fetch);
+    }
+
+    /**
+     * Everything that happens once a frame, after the frame is done
+     */
+    pub(crate) fn finish_frame(&mut self) {
+        if self.rewind.as_mut().is_some_and(|rewind| rewind.frame_completed()) {
+            let state = self.save_state();
+            self.rewind.as_mut().unwrap().push(state);
+        }
+
+        //Periodically saving so we don't lose everything if we crash
+        self.frames_since_save += 1;
+        if self.frames_since_save >= SAVE_INTERVAL_FRAMES {
+            self.frames_since_save = 0;
+            if self.memory.game_cartridge.is_sram_dirty() {
+                self.flush_save();
             }
         }
     }
 
-    fn initialize_window() -> Window {
-        let mut window = Window::new(
-            "Noise Test - Press ESC to exit",
-            WIDTH,
-            HEIGHT,
-            WindowOptions {
-                resize: false,
-                title: true,
-                scale: Scale::X4,
-                scale_mode: ScaleMode::Stretch,
-                ..WindowOptions::default()
-            },
-        )
-            .expect("Unable to create the window");
+    /**
+     * Turning on rewinding. From here on run_frame takes a snapshot every
+     * config.interval frames
+     */
+    pub fn enable_rewind(&mut self, config: RewindConfig) {
+        self.rewind = Some(RewindBuffer::new(config));
+    }
 
-        window.limit_update_rate(Some(std::time::Duration::from_micros(16666)));
+    /**
+     * Going back to the most recent snapshot. Calling this again keeps going
+     * further back. Returns false once there's nothing left to rewind to
+     */
+    pub fn rewind_frame(&mut self) -> bool {
+        let state = match self.rewind.as_mut().and_then(|rewind| rewind.pop()) {
+            Some(state) => state,
+            None => return false,
+        };
+        return self.load_state(&state).is_ok();
+    }
 
-        return window;
+    /**
+     * The last frame the ppu drew. Pixels are 0RGB and go row by row
+     */
+    pub fn frame_buffer(&self) -> &[u32] {
+        return &self.frame_buffer;
     }
-    
 
+    /**
+     * Setting which buttons are held down. They stay held until this gets
+     * called again
+     */
+    pub fn set_buttons(&mut self, buttons: Buttons) {
+        self.buttons = buttons;
+    }
 
-    
     /**
-     * This is the starting point for the Game Boy. You just need to give it a
-     * rom file for it to run
+     * Gets called with every byte the game sends out over the serial port
      */
-    #[allow(unused)]
-    pub fn test_run(&mut self) -> TestStatus {
-        let mut buffer = vec![0u32; WIDTH * HEIGHT];
-        let mut buffer_index: usize = 0;
-        let buff_max = WIDTH * HEIGHT;
-        //let mut window = Self::test_initialize_window();
-        self.memory.ppu.activate_ppu();
-        
-        loop {
-            //let new_size = window.get_size();
+    pub fn set_serial_callback(&mut self, on_transfer: Option<SerialCallback>) {
+        self.memory.set_serial_callback(on_transfer);
+    }
 
-            self.memory.timer_cycle();
-            self.memory.dma_cycle();
-            //self.memory.joypad_cycle(&window);
-            if self.memory.ppu.is_active() {
-                self.memory.gpu_cycle(&mut buffer, &mut buffer_index);
-            }
+    /**
+     * Plugging in (or pulling out with None) a link cable to another Game Boy
+     */
+    pub fn set_link_cable(&mut self, link_cable: Option<Box<dyn LinkCable>>) {
+        self.memory.set_linked(link_cable.is_some());
+        self.link_cable = link_cable;
+    }
+
+    /**
+     * Gets called with every audio sample the apu mixes, AUDIO_SAMPLE_RATE
+     * times a second of emulated time
+     */
+    pub fn set_audio_callback(&mut self, on_sample: Option<AudioCallback>) {
+        self.audio_callback = on_sample;
+    }
+
+    /**
+     * Gets called with each channel's part of every audio sample
+     */
+    pub fn set_channel_audio_callback(&mut self, on_sample: Option<ChannelAudioCallback>) {
+        self.channel_audio_callback = on_sample;
+    }
+
+    /**
+     * Gets called with a trace line before every instruction. LY reads as 0x90
+     * the whole time there's a callback, since that's what gameboy-doctor's
+     * logs were made with
+     */
+    pub fn set_trace_callback(&mut self, on_instruction: Option<TraceCallback>) {
+        self.memory.set_ly_stubbed(on_instruction.is_some());
+        self.trace_callback = on_instruction;
+    }
+
+    /**
+     * The registers and the 4 bytes at PC, the way gameboy-doctor writes them
+     */
+    pub fn trace_line(&self) -> String {
+        let registers = self.registers();
+        let pc_memory: Vec<String> = (0..4).map(|i| format!("{:02X}", self.memory.peek_byte(registers.pc.wrapping_add(i)))).collect();
+        return format!(
+            "A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} SP:{:04X} PC:{:04X} PCMEM:{}",
+            registers.a, registers.f, registers.b, registers.c, registers.d, registers.e, registers.h, registers.l,
+            registers.sp, registers.pc, pc_memory.join(","),
+        );
+    }
+
+    /**
+     * Muting a channel only changes what comes out of the apu, the game
+     * can't tell
+     */
+    pub fn set_channel_muted(&mut self, channel: SoundChannel, muted: bool) {
+        self.memory.apu.set_muted(channel, muted);
+    }
+
+    pub fn is_channel_muted(&self, channel: SoundChannel) -> bool {
+        return self.memory.apu.is_muted(channel);
+    }
+
+    /**
+     * Only letting channel through, or everything that isn't muted if None
+     */
+    pub fn set_solo_channel(&mut self, channel: Option<SoundChannel>) {
+        self.memory.apu.set_solo(channel);
+    }
+
+    pub fn solo_channel(&self) -> Option<SoundChannel> {
+        return self.memory.apu.solo();
+    }
+
+    pub fn registers(&self) -> Registers {
+        return Registers::capture(&self.cpu, self.memory.interrupt_handler.ime_flag);
+    }
+
+    /**
+     * Changing the cpu's registers. This only makes sense between
+     * instructions, like when a debugger has stopped the game
+     */
+    pub fn set_registers(&mut self, registers: Registers) {
+        registers.apply(&mut self.cpu);
+        self.memory.interrupt_handler.ime_flag = registers.ime;
+    }
+
+    /**
+     * Which ROM bank is switched in at 0x4000 - 0x7FFF
+     */
+    pub fn rom_bank(&self) -> usize {
+        return self.memory.game_cartridge.switchable_rom_bank();
+    }
+
+    /**
+     * Reading out of a ROM bank whether or not it's switched in. None if the
+     * cartridge doesn't have that bank
+     */
+    pub fn read_rom_bank(&self, bank: usize, address: u16) -> Option<u8> {
+        return self.memory.game_cartridge.rom_banks.get(bank).map(|rom_bank| rom_bank[(address & 0x3FFF) as usize]);
+    }
+
+    /**
+     * What the cpu would get reading address right now. Reading this way
+     * doesn't take any time or change anything
+     */
+    pub fn read_byte(&self, address: u16) -> u8 {
+        return self.memory.peek_byte(address);
+    }
+
+    /**
+     * Writing address like the cpu would, so writes to the MBC switch banks.
+     * This doesn't take any time or set off watchpoints
+     */
+    pub fn write_byte(&mut self, address: u16, data: u8) {
+        self.memory.poke_byte(address, data);
+    }
+
+    /**
+     * Watching for the cpu touching memory. Hits pile up until they're taken
+     * with take_watch_hits. Nothing gets checked while no watchpoints are set
+     */
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        let pc = self.cpu.pc;
+        let opcode = self.memory.peek_byte(pc);
+        let watchpoints = self.memory.watchpoints.get_or_insert_with(|| Box::new(Watchpoints::new()));
+        watchpoints.add(watchpoint);
+        watchpoints.start_instruction(pc, opcode);
+    }
+
+    pub fn remove_watchpoint(&mut self, watchpoint: Watchpoint) -> bool {
+        let Some(watchpoints) = &mut self.memory.watchpoints else { return false };
+        let removed = watchpoints.remove(watchpoint);
+        if watchpoints.is_empty() {
+            self.memory.watchpoints = None;
+        }
+        return removed;
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        return match &self.memory.watchpoints {
+            Some(watchpoints) => watchpoints.watchpoints(),
+            None => &[],
+        };
+    }
+
+    /**
+     * Every watchpoint hit since the last time this got called
+     */
+    pub fn take_watch_hits(&self) -> Vec<WatchHit> {
+        return match &self.memory.watchpoints {
+            Some(watchpoints) => watchpoints.take_hits(),
+            None => Vec::new(),
+        };
+    }
+
+    /**
+     * What the ppu and apu are up to right now
+     */
+    pub fn debug_state(&self) -> DebugState {
+        return DebugState {
+            ppu: PpuState::capture(&self.memory.ppu),
+            apu: ApuState::capture(&self.memory.apu),
+        };
+    }
+
+    /**
+     * Snapshotting the whole machine. States start with a magic number and a 
+     * version, followed by the checksums of the ROM they were made with
+     */
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut state = StateWriter::new();
+        state.write_bytes(SAVE_STATE_MAGIC);
+        state.write_u16(SAVE_STATE_VERSION);
+        let (header_checksum, global_checksum) = self.rom_checksums();
+        state.write_u8(header_checksum);
+        state.write_u16(global_checksum);
+        self.save_machine_state(&mut state);
+        return state.into_bytes();
+    }
+
+    /**
+     * Restoring a snapshot from save_state. If the state turns out to be bad
+     * the Game Boy is left exactly how it was
+     */
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), Error> {
+        let mut state = StateReader::new(data);
+        if state.read_bytes(SAVE_STATE_MAGIC.len())? != SAVE_STATE_MAGIC {
+            return Err(Error::CorruptSaveState);
+        }
+
+        let version = state.read_u16()?;
+        if version != SAVE_STATE_VERSION {
+            return Err(Error::UnsupportedSaveStateVersion(version));
+        }
+
+        if (state.read_u8()?, state.read_u16()?) != self.rom_checksums() {
+            return Err(Error::SaveStateMismatch);
+        }
+
+        let mut backup = StateWriter::new();
+        self.save_machine_state(&mut backup);
+        if let Err(e) = self.load_machine_state(&mut state) {
+            self.load_machine_state(&mut StateReader::new(&backup.into_bytes()))
+                .expect("Unable to restore the Game Boy after a bad save state");
+            return Err(e);
+        }
+        return Ok(());
+    }
+
+    fn save_machine_state(&self, state: &mut StateWriter) {
+        self.cpu.save_state(state);
+        self.memory.save_state(state);
+        for pixel in &self.frame_buffer {
+            state.write_u32(*pixel);
+        }
+        state.write_u32(self.buffer_index as u32);
+        state.write_bool(self.frame_completed);
+        state.write_bool(self.cpu_cycle_pending);
+    }
+
+    fn load_machine_state(&mut self, state: &mut StateReader) -> Result<(), Error> {
+        self.cpu.load_state(state)?;
+        self.memory.load_state(state)?;
+        for pixel in self.frame_buffer.iter_mut() {
+            *pixel = state.read_u32()?;
+        }
+        self.buffer_index = state.read_u32()? as usize;
+        if self.buffer_index >= WIDTH * HEIGHT {
+            return Err(Error::CorruptSaveState);
+        }
+        self.frame_completed = state.read_bool()?;
+        self.cpu_cycle_pending = state.read_bool()?;
+
+        if !state.is_finished() {
+            return Err(Error::CorruptSaveState);
+        }
+        return Ok(());
+    }
+
+    fn rom_checksums(&self) -> (u8, u16) {
+        return match &self.memory.game_cartridge.header {
+            Some(header) => (header.header_checksum, header.global_checksum),
+            None => (0, 0),
+        };
+    }
+
+    /**
+     * Carrying out one clk cycle of the whole Game Boy. Returns whether the
+     * cpu got to run this cycle
+     */
+    pub(crate) fn cycle(&mut self) -> bool {
+        if self.cpu_cycle_pending {
+            self.cpu_cycle_pending = false;
+            self.cpu.cycle(&mut self.memory);
+            return true;
+        }
+
+        let cpu_runs = self.cycle_before_cpu().is_some();
+        if cpu_runs {
+            self.cpu.cycle(&mut self.memory);
+        }
+        return cpu_runs;
+    }
+
+    /**
+     * Everything in a clk cycle up until the cpu's turn. Returns None if the
+     * cpu doesn't get a turn, otherwise whether it's about to start an
+     * instruction
+     */
+    fn cycle_before_cpu(&mut self) -> Option<bool> {
+        self.memory.serial_cycle();     //Goes off DIV from before this cycle's tick
+        self.memory.timer_cycle();
+        if let Some(link_cable) = &mut self.link_cable {
+            self.memory.link_cycle(link_cable.as_mut());
+        }
+        self.memory.cartridge_cycle();
+        self.memory.dma_cycle();
+        self.memory.joypad_cycle(&self.buttons);
+        if self.memory.ppu.is_active() {
+            self.memory.gpu_cycle(&mut self.frame_buffer, &mut self.buffer_index);
+        }
 
-            if buffer_index == buff_max {
-                buffer_index = 0;
-                //window.update_with_buffer(&buffer, new_size.0, new_size.1).unwrap();
+        if self.buffer_index == WIDTH * HEIGHT {
+            self.buffer_index = 0;
+            self.frame_completed = true;
+        }
+
+        if let Some(sample) = self.memory.apu_cycle() {
+            if let Some(on_sample) = &mut self.audio_callback {
+                on_sample(sample.left, sample.right);
             }
-            //Only try to service an interrupt if you finished an instruction
-            match self.cpu.cpu_state {
-                cpu_state::CpuState::Fetch => self.memory.interrupt_cycle(&mut self.cpu.pc, &mut self.cpu.sp),
-                _ => (),
+            if let Some(on_sample) = &mut self.channel_audio_callback {
+                on_sample(sample.channels);
             }
-            if !self.memory.interrupt_handler.handling_isr {
-                self.cpu.cycle(&mut self.memory);
+        }
+
+        //So pushes from jumping to an interrupt count against the instruction it interrupts
+        if self.memory.watchpoints.is_some() && self.is_about_to_fetch() {
+            self.stamp_watchpoints();
+        }
+
+        //Only try to service an interrupt if you finished an instruction
+        let was_jumping_to_interrupt = self.memory.interrupt_handler.handling_isr;
+        match self.cpu.cpu_state {
+            cpu_state::CpuState::Fetch => self.memory.interrupt_cycle(&mut self.cpu.pc, &mut self.cpu.sp),
+            _ => (),
+        }
+
+        if self.memory.interrupt_handler.handling_isr {
+            return None;
+        }
+
+        //The jump to an interrupt can finish partway into the cpu's fetch, which
+        //then carries on from the handler
+        let starting_instruction = self.is_about_to_fetch() || was_jumping_to_interrupt;
+        if starting_instruction && (self.trace_callback.is_some() || self.memory.watchpoints.is_some()) {
+            self.start_instruction();
+        }
+        return Some(starting_instruction);
+    }
+
+    /**
+     * The cpu is about to fetch the instruction at PC, so it gets traced and
+     * anything it sets off gets blamed on it
+     */
+    fn start_instruction(&mut self) {
+        if self.trace_callback.is_some() {
+            let line = self.trace_line();
+            if let Some(on_instruction) = &mut self.trace_callback {
+                on_instruction(&line);
             }
+        }
+        self.stamp_watchpoints();
+    }
+
+    fn stamp_watchpoints(&mut self) {
+        let opcode = self.memory.peek_byte(self.cpu.pc);
+        if let Some(watchpoints) = &mut self.memory.watchpoints {
+            watchpoints.start_instruction(self.cpu.pc, opcode);
+        }
+    }
+
+    /**
+     * Runs a test rom until it reports whether it passed or failed. Test roms
+     * signal this by running LD B, B with the registers set to a magic value
+     */
+    pub fn test_run(&mut self) -> TestStatus {
+        loop {
+            self.cycle();
 
             if self.cpu.current_opcode == 0x40 {
                 if self.cpu.b == 66 && self.cpu.c == 66 && self.cpu.d == 66 
@@ -170,29 +679,306 @@ impl Gameboy {
                 }
             }
         }
+    }
+
+    /**
+     * Runs a blargg test rom until it prints Passed or Failed over the serial
+     * port, or reports its result in cartridge SRAM. If it hasn't done either
+     * after timeout_clk_cycles we call it a failure. They print their name or
+     * sign SRAM right away, so if neither has happened after a few seconds
+     * it's stuck and we give up early. Returns everything it printed along
+     * with the result
+     */
+    pub fn blargg_test_run(&mut self, timeout_clk_cycles: u64) -> (TestStatus, String) {
+        let sink = SerialSink::new();
+        self.set_serial_callback(Some(sink.callback()));
+
+        let mut clk_cycles = 0;
+        while clk_cycles < timeout_clk_cycles {
+            self.cycle();
+            clk_cycles += 1;
+
+            //Nothing new can show up between bytes, so there's no point checking every cycle
+            if clk_cycles.is_multiple_of(CYCLES_PER_FRAME as u64) {
+                if sink.contains("Passed") {
+                    return (TestStatus::Pass, sink.text());
+                }
+                if sink.contains("Failed") {
+                    return (TestStatus::Failed, sink.text());
+                }
+                if let Some(result) = self.blargg_sram_result() {
+                    return result;
+                }
+                if clk_cycles >= BLARGG_SILENCE_TIMEOUT_CLK_CYCLES && sink.text().is_empty() && !self.has_blargg_sram_signature() {
+                    return (TestStatus::Failed, sink.text());
+                }
+            }
+        }
+        return (TestStatus::Failed, sink.text());
+    }
+
+    /**
+     * Some blargg roms, like the memory timing ones, report in cartridge SRAM
+     * instead of over serial. Once they've signed 0xA001 - 0xA003, 0xA000
+     * holds 0x80 while they're running and then their result code, 0 being a
+     * pass. Their text starts at 0xA004 and ends with a 0
+     */
+    fn blargg_sram_result(&self) -> Option<(TestStatus, String)> {
+        let result_code = self.memory.peek_byte(BLARGG_SRAM_RESULT);
+        if !self.has_blargg_sram_signature() || result_code == BLARGG_SRAM_RUNNING {
+            return None;
+        }
+
+        let text = (BLARGG_SRAM_TEXT..=0xBFFF).map(|address| self.memory.peek_byte(address))
+                                             .take_while(|byte| *byte != 0)
+                                             .map(|byte| byte as char)
+                                             .collect();
+        let status = if result_code == 0 { TestStatus::Pass } else { TestStatus::Failed };
+        return Some((status, text));
+    }
+
+    fn has_blargg_sram_signature(&self) -> bool {
+        return (0..3).all(|i| self.memory.peek_byte(BLARGG_SRAM_SIGNATURE_START + i) == BLARGG_SRAM_SIGNATURE[i as usize]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gbs::{GbsFile, tests::test_gbs};
+    use crate::game_cartridge::header::test_rom;
+
+    #[test]
+    fn save_state_round_trip() {
+        let mut gameboy = Gameboy::new();
+        gameboy.initialize("test_roms/games/Tetris(World)(Rev1).gb").unwrap();
+        for _ in 0..60 {
+            gameboy.run_frame();
+        }
+
+        //Making sure we're somewhere in the middle of an instruction
+        while !matches!(gameboy.cpu.cpu_state, cpu_state::CpuState::Execute { .. }) {
+            gameboy.cycle();
+        }
+        gameboy.cycle();
+
+        let state = gameboy.save_state();
+        for _ in 0..30 {
+            gameboy.run_frame();
+        }
+        let expected_frame = gameboy.frame_buffer().to_vec();
+
+        gameboy.load_state(&state).unwrap();
+        assert_eq!(gameboy.save_state(), state);
+        for _ in 0..30 {
+            gameboy.run_frame();
+        }
+        assert_eq!(gameboy.frame_buffer(), expected_frame);
+    }
+
+    #[test]
+    fn traces_like_gameboy_doctor() {
+        use std::{cell::RefCell, rc::Rc};
+
+        let mut gameboy = Gameboy::new();
+        gameboy.load_rom(&test_rom(&[0xF0, 0x44, 0x47, 0x76])).unwrap();     //LDH A, (0x44); LD B, A; HALT
+
+        let lines = Rc::new(RefCell::new(vec![]));
+        let trace_lines = lines.clone();
+        gameboy.set_trace_callback(Some(Box::new(move |line| trace_lines.borrow_mut().push(line.to_string()))));
+        gameboy.run_frame();
+
+        //Halting with interrupts off and nothing pending stops the trace at the HALT
+        let lines = lines.borrow();
+        assert_eq!(lines.len(), 5);
+        assert_eq!(lines[0], "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,50,01");
+        assert!(lines[4].starts_with("A:90 F:B0 B:90 "));
+        assert!(lines[4].ends_with("PC:0153 PCMEM:76,00,00,00"));
+    }
+
+    #[test]
+    fn traces_the_first_instruction_of_interrupt_handlers() {
+        use std::{cell::RefCell, rc::Rc};
+
+        let mut rom = test_rom(&[0x3E, 0x01, 0xE0, 0xFF, 0xFB, 0x18, 0xFE]);     //LD A, 1; LDH (0xFF), A; EI; JR to itself
+        rom[0x40] = 0x76;       //HALT
+        let mut gameboy = Gameboy::new();
+        gameboy.load_rom(&rom).unwrap();
+
+        let lines = Rc::new(RefCell::new(vec![]));
+        let trace_lines = lines.clone();
+        gameboy.set_trace_callback(Some(Box::new(move |line| trace_lines.borrow_mut().push(line.to_string()))));
+        for _ in 0..3 {
+            gameboy.run_frame();
+        }
+
+        //The VBlank handler's first instruction comes right after the loop it interrupted
+        let lines = lines.borrow();
+        let handler = lines.iter().position(|line| line.contains("PC:0040")).unwrap();
+        assert!(lines[handler - 1].ends_with("PC:0155 PCMEM:18,FE,00,00"));
+        assert!(lines[handler].ends_with("SP:FFFC PC:0040 PCMEM:76,00,00,00"));
+    }
+
+    #[test]
+    fn saves_states_while_stopped_before_an_instruction() {
+        let mut rom = test_rom(&[0x3E, 0x01, 0xE0, 0xFF, 0xFB, 0x18, 0xFE]);     //LD A, 1; LDH (0xFF), A; EI; JR to itself
+        rom[0x40] = 0x3C;       //INC A
+        let mut gameboy = Gameboy::new();
+        gameboy.load_rom(&rom).unwrap();
+        let start = gameboy.save_state();
+
+        //Stopped with the cpu's part of a clk cycle still to go
+        while !gameboy.run_frame_until(|gameboy| gameboy.registers().pc == 0x40) {}
+        let stopped = gameboy.save_state();
+        gameboy.run_frame();
+        let finished = gameboy.save_state();
+
+        gameboy.load_state(&start).unwrap();
+        assert!(gameboy.is_at_unchecked_instruction());
+        gameboy.load_state(&stopped).unwrap();
+        assert!(!gameboy.is_at_unchecked_instruction());
+        gameboy.run_frame();
+        assert_eq!(gameboy.save_state(), finished);
+    }
+
+    #[test]
+    fn rewinds_to_earlier_frames() {
+        let mut gameboy = Gameboy::new();
+        gameboy.initialize("test_roms/games/Tetris(World)(Rev1).gb").unwrap();
+        gameboy.enable_rewind(RewindConfig { interval: 1, ..RewindConfig::default() });
+        for _ in 0..20 {
+            gameboy.run_frame();
+        }
+        let state = gameboy.save_state();
+
+        for _ in 0..5 {
+            gameboy.run_frame();
+        }
+        for _ in 0..6 {
+            assert!(gameboy.rewind_frame());
+        }
+        assert_eq!(gameboy.save_state(), state);
+    }
+
+    #[test]
+    fn channels_add_up_to_the_mix() {
+        use std::{cell::RefCell, rc::Rc};
 
-        return TestStatus::Pass;
+        let mut gameboy = Gameboy::new();
+        gameboy.initialize("test_roms/games/Tetris(World)(Rev1).gb").unwrap();
+        let mix = Rc::new(RefCell::new(vec![]));
+        let channels = Rc::new(RefCell::new(vec![]));
+        let mix_callback = mix.clone();
+        let channels_callback = channels.clone();
+        gameboy.set_audio_callback(Some(Box::new(move |left, right| mix_callback.borrow_mut().push((left, right)))));
+        gameboy.set_channel_audio_callback(Some(Box::new(move |samples| channels_callback.borrow_mut().push(samples))));
+        for _ in 0..30 {
+            gameboy.run_frame();
+        }
+
+        let mix = mix.borrow();
+        let channels = channels.borrow();
+        assert_eq!(mix.len(), channels.len());
+        assert!(mix.iter().any(|(left, right)| left.abs() > 0.01 || right.abs() > 0.01));
+        for ((left, right), samples) in mix.iter().zip(channels.iter()) {
+            assert!((left - samples.iter().map(|sample| sample.0).sum::<f32>()).abs() < 0.0001);
+            assert!((right - samples.iter().map(|sample| sample.1).sum::<f32>()).abs() < 0.0001);
+        }
     }
 
-    #[allow(unused)]
-    fn test_initialize_window() -> Window {
-        let mut window = Window::new(
-            "Noise Test - Press ESC to exit",
-            WIDTH,
-            HEIGHT,
-            WindowOptions {
-                resize: false,
-                title: true,
-                scale: Scale::X1,
-                scale_mode: ScaleMode::Stretch,
-                ..WindowOptions::default()
-            },
-        )
-            .expect("Unable to create the window");
+    #[test]
+    fn rejects_bad_save_states() {
+        let mut gameboy = Gameboy::new();
+        gameboy.initialize("test_roms/games/Tetris(World)(Rev1).gb").unwrap();
+        let state = gameboy.save_state();
 
-        window.limit_update_rate(Some(std::time::Duration::from_micros(16600)));
+        assert!(matches!(gameboy.load_state(&state[..state.len() - 1]), Err(Error::CorruptSaveState)));
+        assert!(matches!(gameboy.load_state(b"NOTSTATE"), Err(Error::CorruptSaveState)));
 
-        return window;
+        let mut newer_version = state.clone();
+        newer_version[8] = 0xFF;
+        assert!(matches!(gameboy.load_state(&newer_version), Err(Error::UnsupportedSaveStateVersion(0x00FF))));
+
+        let mut other_gameboy = Gameboy::new();
+        other_gameboy.initialize("test_roms/acceptance/bits/mem_oam.gb").unwrap();
+        assert!(matches!(other_gameboy.load_state(&state), Err(Error::SaveStateMismatch)));
+
+        assert_eq!(gameboy.save_state(), state);
     }
 
-}
\ No newline at end of file
+    /**
+     * A GBS whose play routine counts how many times it got called in 0xC000
+     */
+    fn counting_gbs(timer_control: u8) -> GbsFile {
+        let mut code = vec![0xC9];                              //init: RET
+        code.resize(0x10, 0x00);
+        code.extend([0x21, 0x00, 0xC0, 0x34, 0xC9]);            //play: LD HL, 0xC000; INC (HL); RET
+        return GbsFile::parse(&test_gbs(&code, timer_control)).unwrap();
+    }
+
+    #[test]
+    fn gbs_play_gets_called_every_vblank() {
+        let mut gameboy = Gameboy::new();
+        gameboy.load_rom(&counting_gbs(0).build_rom(0)).unwrap();
+        for _ in 0..10 {
+            gameboy.run_frame();
+        }
+        assert!((9..=10).contains(&gameboy.memory.read_byte(0xC000)));
+    }
+
+    #[test]
+    fn gbs_play_gets_called_at_the_timer_rate() {
+        //262144 Hz / 256 = 1024 calls a second, a little over 17 a frame
+        let mut gameboy = Gameboy::new();
+        gameboy.load_rom(&counting_gbs(0x05).build_rom(0)).unwrap();
+        gameboy.run_frame();
+        let calls_before = gameboy.memory.read_byte(0xC000);
+        for _ in 0..10 {
+            gameboy.run_frame();
+        }
+        let calls = gameboy.memory.read_byte(0xC000) - calls_before;
+        assert!((170..=172).contains(&calls), "{calls}");
+    }
+
+    #[test]
+    fn gbs_can_use_cartridge_ram() {
+        let mut code = vec![0x3E, 0x42, 0xEA, 0x00, 0xA0, 0xC9];     //init: LD A, 0x42; LD (0xA000), A; RET
+        code.resize(0x10, 0x00);
+        code.extend([0xFA, 0x00, 0xA0, 0xEA, 0x00, 0xC0, 0xC9]);    //play: LD A, (0xA000); LD (0xC000), A; RET
+        let small_gbs = GbsFile::parse(&test_gbs(&code, 0)).unwrap();
+        code.resize(0x9000, 0x00);
+        let banked_gbs = GbsFile::parse(&test_gbs(&code, 0)).unwrap();
+
+        for gbs in [small_gbs, banked_gbs] {
+            let mut gameboy = Gameboy::new();
+            gameboy.load_rom(&gbs.build_rom(0)).unwrap();
+            gameboy.run_frame();
+            gameboy.run_frame();
+            assert_eq!(gameboy.memory.read_byte(0xC000), 0x42);
+        }
+    }
+
+    #[test]
+    fn blargg_results_come_over_serial() {
+        let mut gameboy = Gameboy::new();
+        gameboy.initialize("test_roms/individual/06-ld-r-r.gb").unwrap();
+        let (status, output) = gameboy.blargg_test_run(10 * CLK_CYCLES_PER_SECOND as u64);
+        assert!(matches!(status, TestStatus::Pass));
+        assert!(output.starts_with("06-ld r,r"));
+
+        //Running out of time counts as failing
+        let mut gameboy = Gameboy::new();
+        gameboy.initialize("test_roms/individual/06-ld-r-r.gb").unwrap();
+        let (status, _) = gameboy.blargg_test_run(CYCLES_PER_FRAME as u64);
+        assert!(matches!(status, TestStatus::Failed));
+    }
+
+    #[test]
+    fn blargg_results_come_from_sram() {
+        let mut gameboy = Gameboy::new();
+        gameboy.initialize_without_save("test_roms/timing_tests/01-read_timing.gb").unwrap();
+        let (status, output) = gameboy.blargg_test_run(10 * CLK_CYCLES_PER_SECOND as u64);
+        assert!(matches!(status, TestStatus::Pass));
+        assert!(output.contains("Passed"), "{output}");
+    }
+}
@@ -0,0 +1,532 @@
+mod units;
+mod pulse_channel;
+mod wave_channel;
+mod noise_channel;
+
+use self::pulse_channel::PulseChannel;
+use self::wave_channel::WaveChannel;
+use self::noise_channel::NoiseChannel;
+use crate::gameboy::constants::*;
+use crate::gameboy::inspector::ChannelState;
+use crate::error::Error;
+use crate::save_state::{StateReader, StateWriter};
+
+const SAMPLE_CLK_CYCLES: u8 = 32;       //One sample every 32 clk cycles
+
+//Bits that always read back as 1 for FF10-FF2F. Write only and unused registers read back as 0xFF
+const READ_MASKS: [u8; 0x20] = [
+    0x80, 0x3F, 0x00, 0xFF, 0xBF,           //NR10-NR14
+    0xFF, 0x3F, 0x00, 0xFF, 0xBF,           //NR20-NR24
+    0x7F, 0xFF, 0x9F, 0xFF, 0xBF,           //NR30-NR34
+    0xFF, 0xFF, 0x00, 0x00, 0xBF,           //NR40-NR44
+    0x00, 0x00, 0x70,                       //NR50-NR52
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+];
+
+//How much of the DC offset the high pass filter lets through per sample. This is 0.999958^32
+const HIGH_PASS_CHARGE_FACTOR: f32 = 0.998_657;
+
+/**
+ * One sample of the apu's output. Along with the final mix we keep what each
+ * channel added to it, in the order pulse 1, pulse 2, wave, noise
+ */
+pub struct AudioSample {
+    pub left: f32,
+    pub right: f32,
+    pub channels: [(f32, f32); 4],
+}
+
+/**
+ * The 4 sound channels, for muting and soloing them
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SoundChannel {
+    Pulse1,
+    Pulse2,
+    Wave,
+    Noise,
+}
+
+impl SoundChannel {
+    pub const ALL: [SoundChannel; 4] = [SoundChannel::Pulse1, SoundChannel::Pulse2, SoundChannel::Wave, SoundChannel::Noise];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            SoundChannel::Pulse1 => "Pulse 1",
+            SoundChannel::Pulse2 => "Pulse 2",
+            SoundChannel::Wave => "Wave",
+            SoundChannel::Noise => "Noise",
+        }
+    }
+}
+
+/**
+ * Audio Processing Unit. Owns the 4 sound channels (FF10-FF26) and wave RAM
+ * (FF30-FF3F), and mixes them down to a stereo sample every 32 clk cycles
+ */
+pub struct Apu {
+    pulse_1: PulseChannel,
+    pulse_2: PulseChannel,
+    wave: WaveChannel,
+    noise: NoiseChannel,
+    powered_on: bool,           //Bit 7 of NR52
+    nr50: u8,                   //Master volume for each side
+    nr51: u8,                   //Which channels go to which side
+    frame_sequencer_step: u8,   //The next step the frame sequencer will carry out
+    prev_div_bit: bool,
+    channel_totals: [u32; 4],   //Each channel's output added up over the current sample
+    sample_clk_cycles: u8,
+    capacitors: [[f32; 2]; 4],  //Charge of the high pass filter on each side of each channel
+    muted: [bool; 4],           //Muted by us, not the game. These don't go in save states
+    solo: Option<SoundChannel>,
+}
+
+impl Apu {
+    pub fn new() -> Self {
+        let mut apu = Self {
+            pulse_1: PulseChannel::new(true),
+            pulse_2: PulseChannel::new(false),
+            wave: WaveChannel::new(),
+            noise: NoiseChannel::new(),
+            powered_on: false,
+            nr50: 0,
+            nr51: 0,
+            frame_sequencer_step: 0,
+            prev_div_bit: false,
+            channel_totals: [0; 4],
+            sample_clk_cycles: 0,
+            capacitors: [[0.0; 2]; 4],
+            muted: [false; 4],
+            solo: None,
+        };
+
+        //Leaving everything how the boot rom does. Channel 1 is still on from the startup sound
+        apu.write_register(NR52_REG, 0x80);
+        apu.write_register(NR11_REG, 0x80);
+        apu.write_register(NR12_REG, 0xF3);
+        apu.write_register(NR50_REG, 0x77);
+        apu.write_register(NR51_REG, 0xF3);
+        apu.pulse_1.enabled = true;
+        apu.capacitors[0] = [-0.25; 2];     //Its DAC has been on long enough for the high pass filter to settle
+        return apu;
+    }
+
+    /**
+     * Carrying out one clk cycle. The frame sequencer steps whenever bit 4 of
+     * DIV goes from 1 to 0, which is 512 times a second. Returns a (left, right)
+     * sample every SAMPLE_CLK_CYCLES clk cycles
+     */
+    pub fn cycle(&mut self, div_bit: bool) -> Option<AudioSample> {
+        if self.powered_on {
+            if self.prev_div_bit && !div_bit {
+                self.step_frame_sequencer();
+            }
+
+            self.pulse_1.cycle();
+            self.pulse_2.cycle();
+            self.wave.cycle();
+            self.noise.cycle();
+        }
+        self.prev_div_bit = div_bit;
+
+        self.channel_totals[0] += self.pulse_1.output() as u32;
+        self.channel_totals[1] += self.pulse_2.output() as u32;
+        self.channel_totals[2] += self.wave.output() as u32;
+        self.channel_totals[3] += self.noise.output() as u32;
+
+        self.sample_clk_cycles += 1;
+        if self.sample_clk_cycles < SAMPLE_CLK_CYCLES {
+            return None;
+        }
+        self.sample_clk_cycles = 0;
+        return Some(self.mix());
+    }
+
+    /**
+     * Lengths get clocked on every other step, the sweep on every 4th and the
+     * envelopes on the last one
+     */
+    fn step_frame_sequencer(&mut self) {
+        match self.frame_sequencer_step {
+            0 | 4 => self.clock_lengths(),
+            2 | 6 => {
+                self.clock_lengths();
+                self.pulse_1.clock_sweep();
+            },
+            7 => {
+                self.pulse_1.clock_envelope();
+                self.pulse_2.clock_envelope();
+                self.noise.clock_envelope();
+            },
+            _ => (),
+        }
+        self.frame_sequencer_step = (self.frame_sequencer_step + 1) % 8;
+    }
+
+    fn clock_lengths(&mut self) {
+        self.pulse_1.clock_length();
+        self.pulse_2.clock_length();
+        self.wave.clock_length();
+        self.noise.clock_length();
+    }
+
+    /**
+     * Each DAC turns the averaged 0-15 output of its channel into -1.0 to 1.0.
+     * NR51 picks which side each channel goes to and NR50 sets the volume of
+     * each side. A DAC that's off outputs nothing at all
+     */
+    fn mix(&mut self) -> AudioSample {
+        let dacs_enabled = [self.pulse_1.dac_enabled(), self.pulse_2.dac_enabled(), self.wave.dac_enabled(), self.noise.dac_enabled()];
+        let left_volume = (((self.nr50 >> 4) & 0x7) + 1) as f32 / 8.0 / 4.0;
+        let right_volume = ((self.nr50 & 0x7) + 1) as f32 / 8.0 / 4.0;
+
+        let mut sample = AudioSample { left: 0.0, right: 0.0, channels: [(0.0, 0.0); 4] };
+        for (channel, dac_enabled) in dacs_enabled.into_iter().enumerate() {
+            let mut dac_output = 0.0;
+            if dac_enabled && self.powered_on {
+                dac_output = (self.channel_totals[channel] as f32 / SAMPLE_CLK_CYCLES as f32) / 7.5 - 1.0;
+            }
+            self.channel_totals[channel] = 0;
+
+            let left = if self.nr51 & (0x10 << channel) != 0 { dac_output * left_volume } else { 0.0 };
+            let right = if self.nr51 & (0x01 << channel) != 0 { dac_output * right_volume } else { 0.0 };
+            let mut filtered = (self.high_pass(channel, 0, left), self.high_pass(channel, 1, right));
+
+            //The filter keeps running so there's no pop when the channel comes back
+            if self.is_silenced(SoundChannel::ALL[channel]) {
+                filtered = (0.0, 0.0);
+            }
+
+            sample.channels[channel] = filtered;
+            sample.left += filtered.0;
+            sample.right += filtered.1;
+        }
+        return sample;
+    }
+
+    /**
+     * The capacitor on each output slowly soaks up any DC offset, so a DAC
+     * that's on but silent settles back to 0. The filter is linear, so running
+     * it per channel and adding them up is the same as running it on the mix
+     */
+    fn high_pass(&mut self, channel: usize, side: usize, input: f32) -> f32 {
+        let capacitor = &mut self.capacitors[channel][side];
+        let output = input - *capacitor;
+        *capacitor = input - output * HIGH_PASS_CHARGE_FACTOR;
+        return output;
+    }
+
+    pub fn set_muted(&mut self, channel: SoundChannel, muted: bool) {
+        self.muted[channel as usize] = muted;
+    }
+
+    pub fn is_muted(&self, channel: SoundChannel) -> bool {
+        return self.muted[channel as usize];
+    }
+
+    /**
+     * While a channel is soloed every other channel is silent, whether it's
+     * muted or not
+     */
+    pub fn set_solo(&mut self, channel: Option<SoundChannel>) {
+        self.solo = channel;
+    }
+
+    pub fn solo(&self) -> Option<SoundChannel> {
+        return self.solo;
+    }
+
+    fn is_silenced(&self, channel: SoundChannel) -> bool {
+        return match self.solo {
+            Some(solo) => solo != channel,
+            None => self.muted[channel as usize],
+        };
+    }
+
+    pub fn powered_on(&self) -> bool {
+        return self.powered_on;
+    }
+
+    pub fn nr50(&self) -> u8 {
+        return self.nr50;
+    }
+
+    pub fn nr51(&self) -> u8 {
+        return self.nr51;
+    }
+
+    pub fn channel_state(&self, channel: SoundChannel) -> ChannelState {
+        let mut state = match channel {
+            SoundChannel::Pulse1 => self.pulse_1.state(channel),
+            SoundChannel::Pulse2 => self.pulse_2.state(channel),
+            SoundChannel::Wave => self.wave.state(),
+            SoundChannel::Noise => self.noise.state(),
+        };
+        state.muted = self.is_silenced(channel);
+        return state;
+    }
+
+    pub fn read_register(&self, address: u16) -> u8 {
+        if let WAVE_RAM_START ..= WAVE_RAM_END = address {
+            return self.wave.read_wave_ram(address - WAVE_RAM_START);
+        }
+
+        let value = match address {
+            NR10_REG ..= NR14_REG => self.pulse_1.read_register(address - NR10_REG),
+            NR21_REG ..= NR24_REG => self.pulse_2.read_register(address - (NR21_REG - 1)),
+            NR30_REG ..= NR34_REG => self.wave.read_register(address - NR30_REG),
+            NR41_REG ..= NR44_REG => self.noise.read_register(address - (NR41_REG - 1)),
+            NR50_REG => self.nr50,
+            NR51_REG => self.nr51,
+            NR52_REG => {
+                ((self.powered_on as u8) << 7) | (self.noise.enabled as u8) << 3 | (self.wave.enabled as u8) << 2
+                    | (self.pulse_2.enabled as u8) << 1 | self.pulse_1.enabled as u8
+            },
+            _ => 0,     //Unused
+        };
+        return value | READ_MASKS[(address - NR10_REG) as usize];
+    }
+
+    pub fn write_register(&mut self, address: u16, value: u8) {
+        if let WAVE_RAM_START ..= WAVE_RAM_END = address {
+            self.wave.write_wave_ram(address - WAVE_RAM_START, value);
+            return;
+        }
+
+        if address == NR52_REG {
+            self.write_nr52(value);
+            return;
+        }
+
+        //While the apu is off the only thing you can write on the DMG are the lengths
+        if !self.powered_on {
+            match address {
+                NR11_REG => self.pulse_1.length.load(value & 0x3F),
+                NR21_REG => self.pulse_2.length.load(value & 0x3F),
+                NR31_REG => self.wave.length.load(value),
+                NR41_REG => self.noise.length.load(value & 0x3F),
+                _ => (),
+            }
+            return;
+        }
+
+        let next_step_clocks_length = self.frame_sequencer_step.is_multiple_of(2);
+        match address {
+            NR10_REG ..= NR14_REG => self.pulse_1.write_register(address - NR10_REG, value, next_step_clocks_length),
+            NR21_REG ..= NR24_REG => self.pulse_2.write_register(address - (NR21_REG - 1), value, next_step_clocks_length),
+            NR30_REG ..= NR34_REG => self.wave.write_register(address - NR30_REG, value, next_step_clocks_length),
+            NR41_REG ..= NR44_REG => self.noise.write_register(address - (NR41_REG - 1), value, next_step_clocks_length),
+            NR50_REG => self.nr50 = value,
+            NR51_REG => self.nr51 = value,
+            _ => (),    //Unused
+        }
+    }
+
+    /**
+     * Turning the apu off clears every register and keeps it from being
+     * written to. Turning it back on restarts the frame sequencer
+     */
+    fn write_nr52(&mut self, value: u8) {
+        let power = value & 0x80 != 0;
+        if self.powered_on && !power {
+            self.pulse_1.power_off();
+            self.pulse_2.power_off();
+            self.wave.power_off();
+            self.noise.power_off();
+            self.nr50 = 0;
+            self.nr51 = 0;
+        } else if !self.powered_on && power {
+            self.frame_sequencer_step = 0;
+            self.pulse_1.reset_duty_position();
+            self.pulse_2.reset_duty_position();
+            self.wave.reset_sample_buffer();
+        }
+        self.powered_on = power;
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        self.pulse_1.save_state(state);
+        self.pulse_2.save_state(state);
+        self.wave.save_state(state);
+        self.noise.save_state(state);
+        state.write_bool(self.powered_on);
+        state.write_u8(self.nr50);
+        state.write_u8(self.nr51);
+        state.write_u8(self.frame_sequencer_step);
+        state.write_bool(self.prev_div_bit);
+        for total in self.channel_totals {
+            state.write_u32(total);
+        }
+        state.write_u8(self.sample_clk_cycles);
+        for capacitor in self.capacitors.as_flattened() {
+            state.write_u32(capacitor.to_bits());
+        }
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), Error> {
+        self.pulse_1.load_state(state)?;
+        self.pulse_2.load_state(state)?;
+        self.wave.load_state(state)?;
+        self.noise.load_state(state)?;
+        self.powered_on = state.read_bool()?;
+        self.nr50 = state.read_u8()?;
+        self.nr51 = state.read_u8()?;
+        self.frame_sequencer_step = state.read_u8()?;
+        self.prev_div_bit = state.read_bool()?;
+        for total in self.channel_totals.iter_mut() {
+            *total = state.read_u32()?;
+        }
+        self.sample_clk_cycles = state.read_u8()?;
+        for capacitor in self.capacitors.as_flattened_mut() {
+            *capacitor = f32::from_bits(state.read_u32()?);
+        }
+        if self.frame_sequencer_step > 7 || self.sample_clk_cycles >= SAMPLE_CLK_CYCLES {
+            return Err(Error::CorruptSaveState);
+        }
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /**
+     * Steps the frame sequencer once by toggling the DIV bit
+     */
+    fn step_frame_sequencer(apu: &mut Apu) {
+        apu.cycle(true);
+        apu.cycle(false);
+    }
+
+    #[test]
+    fn masks_unused_register_bits() {
+        let mut apu = Apu::new();
+        apu.write_register(NR52_REG, 0x00);
+        for address in NR10_REG..NR52_REG {
+            assert_eq!(apu.read_register(address), READ_MASKS[(address - NR10_REG) as usize], "{address:#06X}");
+        }
+        assert_eq!(apu.read_register(NR52_REG), 0x70);
+
+        apu.write_register(NR52_REG, 0x80);
+        for address in NR10_REG..NR52_REG {
+            apu.write_register(address, 0xFF);
+        }
+        assert_eq!(apu.read_register(NR10_REG), 0xFF);
+        assert_eq!(apu.read_register(NR34_REG - 2), 0xFF);
+        assert_eq!(apu.read_register(0xFF27), 0xFF);
+    }
+
+    #[test]
+    fn power_off_clears_registers_but_not_wave_ram() {
+        let mut apu = Apu::new();
+        apu.write_register(WAVE_RAM_START, 0x12);
+        apu.write_register(NR50_REG, 0x55);
+        apu.write_register(NR52_REG, 0x00);
+
+        apu.write_register(NR50_REG, 0x77);
+        assert_eq!(apu.read_register(NR50_REG), 0x00);
+        assert_eq!(apu.read_register(NR52_REG), 0x70);
+        assert_eq!(apu.read_register(WAVE_RAM_START), 0x12);
+    }
+
+    #[test]
+    fn length_turns_channel_off() {
+        let mut apu = Apu::new();
+        apu.write_register(NR24_REG - 2, 0xF0);     //NR22, DAC on
+        apu.write_register(NR21_REG, 0x3E);         //2 clocks of length left
+        apu.write_register(NR24_REG, 0xC0);         //Trigger with length enabled
+        assert_eq!(apu.read_register(NR52_REG) & 0x2, 0x2);
+
+        step_frame_sequencer(&mut apu);
+        assert_eq!(apu.read_register(NR52_REG) & 0x2, 0x2);
+        step_frame_sequencer(&mut apu);
+        step_frame_sequencer(&mut apu);
+        assert_eq!(apu.read_register(NR52_REG) & 0x2, 0x0);
+    }
+
+    #[test]
+    fn sweep_overflow_turns_channel_off() {
+        let mut apu = Apu::new();
+        apu.write_register(NR10_REG, 0x11);         //Period 1, adding, shift 1
+        apu.write_register(NR12_REG, 0xF0);
+        apu.write_register(NR14_REG - 1, 0x00);     //NR13
+        apu.write_register(NR14_REG, 0x85);         //Frequency 0x500 sweeps to 0x780, which then fails the overflow check
+        assert_eq!(apu.read_register(NR52_REG) & 0x1, 0x1);
+
+        for _ in 0..3 {
+            step_frame_sequencer(&mut apu);
+        }
+        assert_eq!(apu.read_register(NR52_REG) & 0x1, 0x0);
+    }
+
+    #[test]
+    fn plays_square_wave() {
+        let mut apu = Apu::new();
+        apu.write_register(NR12_REG, 0x00);         //Turning off channel 1's DAC
+        apu.capacitors = [[0.0; 2]; 4];             //and forgetting it was ever on
+        apu.write_register(NR21_REG, 0x80);         //50% duty
+        apu.write_register(NR24_REG - 2, 0xF0);     //NR22
+        apu.write_register(NR24_REG - 1, 0xF0);     //NR23
+        apu.write_register(NR24_REG, 0x87);         //Frequency 0x7F0, so 64 clk cycles between duty steps
+
+        let samples = (0..4096).filter_map(|_| apu.cycle(false)).collect::<Vec<AudioSample>>();
+        assert_eq!(samples.len(), 4096 / SAMPLE_CLK_CYCLES as usize);
+        assert!(samples.iter().any(|sample| sample.left > 0.1));
+        assert!(samples.iter().any(|sample| sample.left < -0.1));
+        assert!(samples.iter().all(|sample| sample.left.abs() <= 1.0 && sample.right.abs() <= 1.0));
+
+        //Only channel 2 is making any sound
+        for sample in &samples {
+            assert_eq!(sample.channels[1], (sample.left, sample.right));
+            assert_eq!(sample.channels[0], (0.0, 0.0));
+        }
+    }
+
+    #[test]
+    fn mute_and_solo_silence_channels() {
+        let mut apu = Apu::new();
+        apu.write_register(NR12_REG, 0x00);
+        apu.capacitors = [[0.0; 2]; 4];
+        apu.write_register(NR21_REG, 0x80);
+        apu.write_register(NR24_REG - 2, 0xF0);     //NR22
+        apu.write_register(NR24_REG - 1, 0xF0);     //NR23
+        apu.write_register(NR24_REG, 0x87);
+        let is_silent = |apu: &mut Apu| (0..4096).filter_map(|_| apu.cycle(false)).all(|sample| sample.left == 0.0 && sample.right == 0.0);
+
+        apu.set_muted(SoundChannel::Pulse2, true);
+        assert!(is_silent(&mut apu));
+        assert!(apu.channel_state(SoundChannel::Pulse2).muted);
+
+        //Soloing wins over muting
+        apu.set_solo(Some(SoundChannel::Pulse2));
+        assert!(!is_silent(&mut apu));
+        assert!(apu.channel_state(SoundChannel::Pulse1).muted);
+
+        apu.set_solo(Some(SoundChannel::Noise));
+        assert!(is_silent(&mut apu));
+
+        apu.set_solo(None);
+        apu.set_muted(SoundChannel::Pulse2, false);
+        assert!(!is_silent(&mut apu));
+    }
+
+    #[test]
+    fn channel_state_follows_registers() {
+        let mut apu = Apu::new();
+        apu.write_register(NR21_REG, 0xC0 | 0x3F);  //75% duty, 1 length clock left
+        apu.write_register(NR24_REG - 2, 0xA3);     //NR22: volume 10, decreasing every 3 steps
+        apu.write_register(NR24_REG - 1, 0x00);     //NR23
+        apu.write_register(NR24_REG, 0xC4);         //Frequency 0x400, length enabled
+
+        let state = apu.channel_state(SoundChannel::Pulse2);
+        assert!(state.enabled && state.dac_enabled && state.length_enabled);
+        assert_eq!(state.frequency, 0x400);
+        assert_eq!(state.frequency_hz, 128.0);
+        assert_eq!(state.volume, 10);
+        assert_eq!(state.duty, Some(3));
+        let envelope = state.envelope.unwrap();
+        assert_eq!((envelope.initial_volume, envelope.increasing, envelope.period), (10, false, 3));
+        assert!(state.to_string().starts_with("Pulse 2 on   DAC on       128.0 Hz (400)  vol 10  env 10-3"));
+    }
+}
@@ -0,0 +1,168 @@
+use crate::error::Error;
+use crate::save_state::{StateReader, StateWriter};
+use crate::gameboy::CLK_CYCLES_PER_SECOND;
+use crate::gameboy::inspector::ChannelState;
+use super::SoundChannel;
+use super::units::{Envelope, LengthCounter};
+
+/**
+ * Channel 4 (NR41-NR44). Outputs the inverted low bit of a linear feedback
+ * shift register, which is either 15 or 7 bits wide
+ */
+pub struct NoiseChannel {
+    pub enabled: bool,
+    polynomial: u8,     //What was last written to NR43
+    freq_timer: u32,
+    lfsr: u16,
+    pub length: LengthCounter,
+    envelope: Envelope,
+}
+
+impl NoiseChannel {
+    pub fn new() -> Self {
+        Self {
+            enabled: false,
+            polynomial: 0,
+            freq_timer: 0,
+            lfsr: 0x7FFF,
+            length: LengthCounter::new(64),
+            envelope: Envelope::new(),
+        }
+    }
+
+    /**
+     * Turning the apu off clears every register. On the DMG the length
+     * counter is left alone
+     */
+    pub fn power_off(&mut self) {
+        let mut length = self.length;
+        length.enabled = false;
+        *self = Self { length, ..Self::new() };
+    }
+
+    /**
+     * NR40 doesn't exist, so registers go from 1 to 4 to line up with the
+     * other channels
+     */
+    pub fn read_register(&self, register: u16) -> u8 {
+        match register {
+            1 => 0,
+            2 => self.envelope.read_register(),
+            3 => self.polynomial,
+            4 => (self.length.enabled as u8) << 6,
+            _ => panic!("The noise channel only has 4 registers"),
+        }
+    }
+
+    pub fn write_register(&mut self, register: u16, value: u8, next_step_clocks_length: bool) {
+        match register {
+            1 => self.length.load(value & 0x3F),
+            2 => {
+                self.envelope.write_register(value);
+                if !self.envelope.dac_enabled() {
+                    self.enabled = false;
+                }
+            },
+            3 => self.polynomial = value,
+            4 => {
+                let trigger = value & 0x80 != 0;
+                if self.length.write_control(value & 0x40 != 0, trigger, next_step_clocks_length) {
+                    self.enabled = false;
+                }
+                if trigger {
+                    self.enabled = self.envelope.dac_enabled();
+                    self.freq_timer = self.period();
+                    self.envelope.trigger();
+                    self.lfsr = 0x7FFF;
+                }
+            },
+            _ => panic!("The noise channel only has 4 registers"),
+        }
+    }
+
+    /**
+     * The divisor code picks 8, 16, 32, ... 112 which then gets shifted left
+     * by the clock shift
+     */
+    fn period(&self) -> u32 {
+        let divisor = match self.polynomial & 0x7 {
+            0 => 8,
+            code => code as u32 * 16,
+        };
+        return divisor << (self.polynomial >> 4);
+    }
+
+    pub fn cycle(&mut self) {
+        if self.freq_timer > 0 {
+            self.freq_timer -= 1;
+        }
+        if self.freq_timer == 0 {
+            self.freq_timer = self.period();
+
+            //Clock shifts of 14 and 15 stop the LFSR
+            if self.polynomial >> 4 < 14 {
+                let feedback = (self.lfsr ^ (self.lfsr >> 1)) & 0x1;
+                self.lfsr = (self.lfsr >> 1) | (feedback << 14);
+                if self.polynomial & 0x08 != 0 {
+                    self.lfsr = (self.lfsr & !0x40) | (feedback << 6);
+                }
+            }
+        }
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    pub fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn dac_enabled(&self) -> bool {
+        return self.envelope.dac_enabled();
+    }
+
+    pub fn state(&self) -> ChannelState {
+        return ChannelState {
+            channel: SoundChannel::Noise,
+            enabled: self.enabled,
+            dac_enabled: self.dac_enabled(),
+            muted: false,
+            frequency: self.polynomial as u16,
+            frequency_hz: CLK_CYCLES_PER_SECOND as f32 / self.period() as f32,
+            volume: self.envelope.volume,
+            envelope: Some(self.envelope.state()),
+            length: self.length.remaining(),
+            length_enabled: self.length.enabled,
+            duty: None,
+        };
+    }
+
+    pub fn output(&self) -> u8 {
+        if !self.enabled || self.lfsr & 0x1 != 0 {
+            return 0;
+        }
+        return self.envelope.volume;
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.enabled);
+        state.write_u8(self.polynomial);
+        state.write_u32(self.freq_timer);
+        state.write_u16(self.lfsr);
+        self.length.save_state(state);
+        self.envelope.save_state(state);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), Error> {
+        self.enabled = state.read_bool()?;
+        self.polynomial = state.read_u8()?;
+        self.freq_timer = state.read_u32()?;
+        self.lfsr = state.read_u16()?;
+        self.length.load_state(state)?;
+        self.envelope.load_state(state)?;
+        return Ok(());
+    }
+}
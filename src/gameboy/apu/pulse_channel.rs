@@ -0,0 +1,214 @@
+use crate::error::Error;
+use crate::save_state::{StateReader, StateWriter};
+use crate::gameboy::inspector::ChannelState;
+use super::SoundChannel;
+use super::units::{Envelope, LengthCounter, Sweep, SweepResult, MAX_FREQUENCY};
+
+//Which of the 8 steps of a period are high for each duty cycle
+const DUTY_PATTERNS: [[u8; 8]; 4] = [
+    [0, 0, 0, 0, 0, 0, 0, 1],   //12.5%
+    [1, 0, 0, 0, 0, 0, 0, 1],   //25%
+    [1, 0, 0, 0, 0, 1, 1, 1],   //50%
+    [0, 1, 1, 1, 1, 1, 1, 0],   //75%
+];
+
+/**
+ * Square wave channels 1 (NR10-NR14) and 2 (NR21-NR24). Only channel 1 has a
+ * frequency sweep
+ */
+pub struct PulseChannel {
+    pub enabled: bool,
+    duty: u8,
+    duty_position: u8,
+    frequency: u16,     //11 bits split across NRx3 and NRx4
+    freq_timer: u16,    //Clk cycles until we move to the next duty step
+    pub length: LengthCounter,
+    envelope: Envelope,
+    sweep: Option<Sweep>,
+}
+
+impl PulseChannel {
+    pub fn new(has_sweep: bool) -> Self {
+        Self {
+            enabled: false,
+            duty: 0,
+            duty_position: 0,
+            frequency: 0,
+            freq_timer: 0,
+            length: LengthCounter::new(64),
+            envelope: Envelope::new(),
+            sweep: if has_sweep { Some(Sweep::new()) } else { None },
+        }
+    }
+
+    /**
+     * Turning the apu off clears every register. On the DMG the length
+     * counter is left alone
+     */
+    pub fn power_off(&mut self) {
+        let mut length = self.length;
+        length.enabled = false;
+        *self = Self { length, ..Self::new(self.sweep.is_some()) };
+    }
+
+    /**
+     * Reading NRx0-NRx4. Bits that can't be read back get masked off by the apu
+     */
+    pub fn read_register(&self, register: u16) -> u8 {
+        match register {
+            0 => self.sweep.map_or(0, |sweep| sweep.read_register()),
+            1 => self.duty << 6,
+            2 => self.envelope.read_register(),
+            3 => 0,
+            4 => (self.length.enabled as u8) << 6,
+            _ => panic!("Pulse channels only have 5 registers"),
+        }
+    }
+
+    pub fn write_register(&mut self, register: u16, value: u8, next_step_clocks_length: bool) {
+        match register {
+            0 => {
+                if let Some(sweep) = &mut self.sweep {
+                    if sweep.write_register(value) {
+                        self.enabled = false;
+                    }
+                }
+            },
+            1 => {
+                self.duty = value >> 6;
+                self.length.load(value & 0x3F);
+            },
+            2 => {
+                self.envelope.write_register(value);
+                if !self.envelope.dac_enabled() {
+                    self.enabled = false;
+                }
+            },
+            3 => self.frequency = (self.frequency & 0x700) | value as u16,
+            4 => {
+                self.frequency = (self.frequency & 0xFF) | ((value as u16 & 0x7) << 8);
+                let trigger = value & 0x80 != 0;
+                if self.length.write_control(value & 0x40 != 0, trigger, next_step_clocks_length) {
+                    self.enabled = false;
+                }
+                if trigger {
+                    self.trigger();
+                }
+            },
+            _ => panic!("Pulse channels only have 5 registers"),
+        }
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.envelope.dac_enabled();
+        self.freq_timer = self.period();
+        self.envelope.trigger();
+        if let Some(sweep) = &mut self.sweep {
+            if sweep.trigger(self.frequency) {
+                self.enabled = false;
+            }
+        }
+    }
+
+    fn period(&self) -> u16 {
+        return (MAX_FREQUENCY + 1 - self.frequency) * 4;
+    }
+
+    pub fn cycle(&mut self) {
+        if self.freq_timer > 0 {
+            self.freq_timer -= 1;
+        }
+        if self.freq_timer == 0 {
+            self.freq_timer = self.period();
+            self.duty_position = (self.duty_position + 1) % 8;
+        }
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    pub fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn clock_sweep(&mut self) {
+        if let Some(sweep) = &mut self.sweep {
+            match sweep.clock() {
+                SweepResult::Unchanged => (),
+                SweepResult::NewFrequency(frequency) => self.frequency = frequency,
+                SweepResult::Overflowed => self.enabled = false,
+            }
+        }
+    }
+
+    /**
+     * The duty position only gets reset when the apu is turned on, not when
+     * the channel gets triggered
+     */
+    pub fn reset_duty_position(&mut self) {
+        self.duty_position = 0;
+    }
+
+    pub fn dac_enabled(&self) -> bool {
+        return self.envelope.dac_enabled();
+    }
+
+    pub fn state(&self, channel: SoundChannel) -> ChannelState {
+        return ChannelState {
+            channel,
+            enabled: self.enabled,
+            dac_enabled: self.dac_enabled(),
+            muted: false,
+            frequency: self.frequency,
+            frequency_hz: 131072.0 / (MAX_FREQUENCY + 1 - self.frequency) as f32,
+            volume: self.envelope.volume,
+            envelope: Some(self.envelope.state()),
+            length: self.length.remaining(),
+            length_enabled: self.length.enabled,
+            duty: Some(self.duty),
+        };
+    }
+
+    /**
+     * What the channel is feeding its DAC, from 0 to 15
+     */
+    pub fn output(&self) -> u8 {
+        if !self.enabled {
+            return 0;
+        }
+        return DUTY_PATTERNS[self.duty as usize][self.duty_position as usize] * self.envelope.volume;
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.enabled);
+        state.write_u8(self.duty);
+        state.write_u8(self.duty_position);
+        state.write_u16(self.frequency);
+        state.write_u16(self.freq_timer);
+        self.length.save_state(state);
+        self.envelope.save_state(state);
+        if let Some(sweep) = &self.sweep {
+            sweep.save_state(state);
+        }
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), Error> {
+        self.enabled = state.read_bool()?;
+        self.duty = state.read_u8()?;
+        self.duty_position = state.read_u8()?;
+        self.frequency = state.read_u16()?;
+        self.freq_timer = state.read_u16()?;
+        if self.duty > 3 || self.duty_position > 7 || self.frequency > MAX_FREQUENCY {
+            return Err(Error::CorruptSaveState);
+        }
+        self.length.load_state(state)?;
+        self.envelope.load_state(state)?;
+        if let Some(sweep) = &mut self.sweep {
+            sweep.load_state(state)?;
+        }
+        return Ok(());
+    }
+}
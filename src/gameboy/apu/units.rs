@@ -0,0 +1,319 @@
+use crate::error::Error;
+use crate::save_state::{StateReader, StateWriter};
+use crate::gameboy::inspector::EnvelopeState;
+
+pub const MAX_FREQUENCY: u16 = 2047;
+
+/**
+ * Turns a channel off once its length runs out. Gets clocked at 256Hz by the
+ * frame sequencer, but only counts down while it's enabled (bit 6 of NRx4)
+ */
+#[derive(Clone, Copy)]
+pub struct LengthCounter {
+    max: u16,           //64 for everything except the wave channel which is 256
+    counter: u16,
+    pub enabled: bool,
+}
+
+impl LengthCounter {
+    pub fn new(max: u16) -> Self {
+        Self {
+            max,
+            counter: 0,
+            enabled: false,
+        }
+    }
+
+    /**
+     * Writing the length part of NRx1. The counter counts up from it, so we
+     * keep how many clocks are left
+     */
+    pub fn load(&mut self, length: u8) {
+        self.counter = self.max - (length as u16 & (self.max - 1));
+    }
+
+    pub fn remaining(&self) -> u16 {
+        return self.counter;
+    }
+
+    /**
+     * Returns true if the length just ran out and the channel needs to be
+     * turned off
+     */
+    pub fn clock(&mut self) -> bool {
+        if self.enabled && self.counter > 0 {
+            self.counter -= 1;
+            return self.counter == 0;
+        }
+        return false;
+    }
+
+    /**
+     * Handling the length enable and trigger bits of an NRx4 write. If the
+     * next frame sequencer step doesn't clock the length, enabling it clocks
+     * it once extra. Returns true if that turned the channel off
+     */
+    pub fn write_control(&mut self, enable: bool, trigger: bool, next_step_clocks_length: bool) -> bool {
+        let was_enabled = self.enabled;
+        self.enabled = enable;
+
+        let mut channel_off = false;
+        if !next_step_clocks_length && !was_enabled && enable && self.counter > 0 {
+            self.counter -= 1;
+            channel_off = self.counter == 0 && !trigger;
+        }
+
+        if trigger && self.counter == 0 {
+            self.counter = self.max;
+            if enable && !next_step_clocks_length {
+                self.counter -= 1;
+            }
+        }
+        return channel_off;
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_u16(self.counter);
+        state.write_bool(self.enabled);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), Error> {
+        self.counter = state.read_u16()?;
+        if self.counter > self.max {
+            return Err(Error::CorruptSaveState);
+        }
+        self.enabled = state.read_bool()?;
+        return Ok(());
+    }
+}
+
+/**
+ * Volume envelope of the pulse and noise channels (NRx2). Gets clocked at 64Hz
+ * by the frame sequencer
+ */
+#[derive(Clone, Copy)]
+pub struct Envelope {
+    register: u8,       //What was last written to NRx2
+    pub volume: u8,
+    timer: u8,
+}
+
+impl Envelope {
+    pub fn new() -> Self {
+        Self {
+            register: 0,
+            volume: 0,
+            timer: 0,
+        }
+    }
+
+    pub fn read_register(&self) -> u8 {
+        return self.register;
+    }
+
+    pub fn write_register(&mut self, value: u8) {
+        self.register = value;
+    }
+
+    /**
+     * The DAC is on as long as the top 5 bits of NRx2 aren't all 0
+     */
+    pub fn dac_enabled(&self) -> bool {
+        return self.register & 0xF8 != 0;
+    }
+
+    fn period(&self) -> u8 {
+        return self.register & 0x7;
+    }
+
+    pub fn state(&self) -> EnvelopeState {
+        return EnvelopeState {
+            initial_volume: self.register >> 4,
+            increasing: self.register & 0x08 != 0,
+            period: self.period(),
+        };
+    }
+
+    pub fn trigger(&mut self) {
+        self.volume = self.register >> 4;
+        self.timer = self.period();
+    }
+
+    pub fn clock(&mut self) {
+        if self.period() == 0 {
+            return;
+        }
+
+        if self.timer > 0 {
+            self.timer -= 1;
+        }
+        if self.timer == 0 {
+            self.timer = self.period();
+            let increasing = self.register & 0x08 != 0;
+            if increasing && self.volume < 15 {
+                self.volume += 1;
+            } else if !increasing && self.volume > 0 {
+                self.volume -= 1;
+            }
+        }
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.register);
+        state.write_u8(self.volume);
+        state.write_u8(self.timer);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), Error> {
+        self.register = state.read_u8()?;
+        self.volume = state.read_u8()?;
+        self.timer = state.read_u8()?;
+        if self.volume > 15 {
+            return Err(Error::CorruptSaveState);
+        }
+        return Ok(());
+    }
+}
+
+/**
+ * What a sweep clock did to the channel. Overflowing past 2047 turns the
+ * channel off
+ */
+pub enum SweepResult {
+    Unchanged,
+    NewFrequency(u16),
+    Overflowed,
+}
+
+/**
+ * Frequency sweep of pulse channel 1 (NR10). Gets clocked at 128Hz by the
+ * frame sequencer and works off a shadow copy of the channel's frequency
+ */
+#[derive(Clone, Copy)]
+pub struct Sweep {
+    register: u8,           //What was last written to NR10
+    enabled: bool,
+    timer: u8,
+    shadow_frequency: u16,
+    negate_used: bool,      //A subtraction happened since the last trigger
+}
+
+impl Sweep {
+    pub fn new() -> Self {
+        Self {
+            register: 0,
+            enabled: false,
+            timer: 0,
+            shadow_frequency: 0,
+            negate_used: false,
+        }
+    }
+
+    pub fn read_register(&self) -> u8 {
+        return self.register;
+    }
+
+    /**
+     * Returns true if the channel has to be turned off. Clearing the negate
+     * bit after a subtraction was already done does that
+     */
+    pub fn write_register(&mut self, value: u8) -> bool {
+        self.register = value & 0x7F;
+        return self.negate_used && !self.negate();
+    }
+
+    fn period(&self) -> u8 {
+        return (self.register >> 4) & 0x7;
+    }
+
+    fn negate(&self) -> bool {
+        return self.register & 0x08 != 0;
+    }
+
+    fn shift(&self) -> u8 {
+        return self.register & 0x7;
+    }
+
+    /**
+     * A period of 0 gets treated as 8 by the timer
+     */
+    fn reload_timer(&mut self) {
+        self.timer = match self.period() {
+            0 => 8,
+            period => period,
+        };
+    }
+
+    /**
+     * Returns true if the overflow check turned the channel off
+     */
+    pub fn trigger(&mut self, frequency: u16) -> bool {
+        self.shadow_frequency = frequency;
+        self.negate_used = false;
+        self.reload_timer();
+        self.enabled = self.period() != 0 || self.shift() != 0;
+
+        if self.shift() != 0 {
+            return self.calculate_frequency() > MAX_FREQUENCY;
+        }
+        return false;
+    }
+
+    pub fn clock(&mut self) -> SweepResult {
+        if self.timer > 0 {
+            self.timer -= 1;
+        }
+        if self.timer != 0 {
+            return SweepResult::Unchanged;
+        }
+
+        self.reload_timer();
+        if !self.enabled || self.period() == 0 {
+            return SweepResult::Unchanged;
+        }
+
+        let new_frequency = self.calculate_frequency();
+        if new_frequency > MAX_FREQUENCY {
+            return SweepResult::Overflowed;
+        }
+        if self.shift() == 0 {
+            return SweepResult::Unchanged;
+        }
+
+        self.shadow_frequency = new_frequency;
+        //The new frequency goes through the overflow check a second time, but doesn't get used
+        if self.calculate_frequency() > MAX_FREQUENCY {
+            return SweepResult::Overflowed;
+        }
+        return SweepResult::NewFrequency(new_frequency);
+    }
+
+    fn calculate_frequency(&mut self) -> u16 {
+        let offset = self.shadow_frequency >> self.shift();
+        if self.negate() {
+            self.negate_used = true;
+            return self.shadow_frequency - offset;
+        }
+        return self.shadow_frequency + offset;
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.register);
+        state.write_bool(self.enabled);
+        state.write_u8(self.timer);
+        state.write_u16(self.shadow_frequency);
+        state.write_bool(self.negate_used);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), Error> {
+        self.register = state.read_u8()? & 0x7F;
+        self.enabled = state.read_bool()?;
+        self.timer = state.read_u8()?;
+        self.shadow_frequency = state.read_u16()?;
+        self.negate_used = state.read_bool()?;
+        if self.shadow_frequency > MAX_FREQUENCY {
+            return Err(Error::CorruptSaveState);
+        }
+        return Ok(());
+    }
+}
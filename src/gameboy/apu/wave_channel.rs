@@ -0,0 +1,211 @@
+use crate::error::Error;
+use crate::save_state::{StateReader, StateWriter};
+use crate::gameboy::inspector::ChannelState;
+use super::SoundChannel;
+use super::units::{LengthCounter, MAX_FREQUENCY};
+
+/**
+ * Channel 3 (NR30-NR34). Plays back the 32 4-bit samples stored in wave RAM
+ * (FF30-FF3F), high nibble first
+ */
+pub struct WaveChannel {
+    pub enabled: bool,
+    dac_enabled: bool,      //Bit 7 of NR30
+    volume_code: u8,        //Bits 5-6 of NR32
+    frequency: u16,
+    freq_timer: u16,
+    position: u8,           //Which of the 32 samples we're on
+    sample_buffer: u8,      //The last sample read out of wave RAM
+    just_read: bool,        //Wave RAM was read by the channel this clk cycle
+    pub length: LengthCounter,
+    wave_ram: [u8; 16],
+}
+
+impl WaveChannel {
+    pub fn new() -> Self {
+        Self {
+            enabled: false,
+            dac_enabled: false,
+            volume_code: 0,
+            frequency: 0,
+            freq_timer: 0,
+            position: 0,
+            sample_buffer: 0,
+            just_read: false,
+            length: LengthCounter::new(256),
+            wave_ram: [0; 16],
+        }
+    }
+
+    /**
+     * Turning the apu off clears every register. Wave RAM and, on the DMG,
+     * the length counter are left alone
+     */
+    pub fn power_off(&mut self) {
+        let mut length = self.length;
+        length.enabled = false;
+        *self = Self { length, wave_ram: self.wave_ram, ..Self::new() };
+    }
+
+    pub fn read_register(&self, register: u16) -> u8 {
+        match register {
+            0 => (self.dac_enabled as u8) << 7,
+            1 => 0,
+            2 => self.volume_code << 5,
+            3 => 0,
+            4 => (self.length.enabled as u8) << 6,
+            _ => panic!("The wave channel only has 5 registers"),
+        }
+    }
+
+    pub fn write_register(&mut self, register: u16, value: u8, next_step_clocks_length: bool) {
+        match register {
+            0 => {
+                self.dac_enabled = value & 0x80 != 0;
+                if !self.dac_enabled {
+                    self.enabled = false;
+                }
+            },
+            1 => self.length.load(value),
+            2 => self.volume_code = (value >> 5) & 0x3,
+            3 => self.frequency = (self.frequency & 0x700) | value as u16,
+            4 => {
+                self.frequency = (self.frequency & 0xFF) | ((value as u16 & 0x7) << 8);
+                let trigger = value & 0x80 != 0;
+                if self.length.write_control(value & 0x40 != 0, trigger, next_step_clocks_length) {
+                    self.enabled = false;
+                }
+                if trigger {
+                    self.enabled = self.dac_enabled;
+                    self.position = 0;
+                    self.freq_timer = self.period() + 6;   //There's a short delay before the first sample gets read
+                }
+            },
+            _ => panic!("The wave channel only has 5 registers"),
+        }
+    }
+
+    fn period(&self) -> u16 {
+        return (MAX_FREQUENCY + 1 - self.frequency) * 2;
+    }
+
+    pub fn cycle(&mut self) {
+        self.just_read = false;
+        if !self.enabled {
+            return;
+        }
+
+        if self.freq_timer > 0 {
+            self.freq_timer -= 1;
+        }
+        if self.freq_timer == 0 {
+            self.freq_timer = self.period();
+            self.position = (self.position + 1) % 32;
+            self.sample_buffer = self.wave_ram[self.position as usize / 2];
+            self.just_read = true;
+        }
+    }
+
+    /**
+     * While the channel is playing, the cpu can only get at the byte the
+     * channel is reading, and only on the exact cycle it reads it. Any other
+     * time it gets 0xFF
+     */
+    pub fn read_wave_ram(&self, offset: u16) -> u8 {
+        if self.enabled {
+            if self.just_read {
+                return self.wave_ram[self.position as usize / 2];
+            }
+            return 0xFF;
+        }
+        return self.wave_ram[offset as usize];
+    }
+
+    pub fn write_wave_ram(&mut self, offset: u16, value: u8) {
+        if self.enabled {
+            if self.just_read {
+                self.wave_ram[self.position as usize / 2] = value;
+            }
+            return;
+        }
+        self.wave_ram[offset as usize] = value;
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    /**
+     * Turning the apu on clears out the sample buffer
+     */
+    pub fn reset_sample_buffer(&mut self) {
+        self.sample_buffer = 0;
+    }
+
+    pub fn dac_enabled(&self) -> bool {
+        return self.dac_enabled;
+    }
+
+    pub fn state(&self) -> ChannelState {
+        return ChannelState {
+            channel: SoundChannel::Wave,
+            enabled: self.enabled,
+            dac_enabled: self.dac_enabled,
+            muted: false,
+            frequency: self.frequency,
+            frequency_hz: 65536.0 / (MAX_FREQUENCY + 1 - self.frequency) as f32,
+            volume: match self.volume_code {
+                0 => 0,
+                volume_code => 15 >> (volume_code - 1),
+            },
+            envelope: None,
+            length: self.length.remaining(),
+            length_enabled: self.length.enabled,
+            duty: None,
+        };
+    }
+
+    pub fn output(&self) -> u8 {
+        if !self.enabled {
+            return 0;
+        }
+
+        let sample = if self.position.is_multiple_of(2) { self.sample_buffer >> 4 } else { self.sample_buffer & 0xF };
+        return match self.volume_code {
+            0 => 0,
+            volume_code => sample >> (volume_code - 1),
+        };
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.enabled);
+        state.write_bool(self.dac_enabled);
+        state.write_u8(self.volume_code);
+        state.write_u16(self.frequency);
+        state.write_u16(self.freq_timer);
+        state.write_u8(self.position);
+        state.write_u8(self.sample_buffer);
+        state.write_bool(self.just_read);
+        self.length.save_state(state);
+        state.write_bytes(&self.wave_ram);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), Error> {
+        self.enabled = state.read_bool()?;
+        self.dac_enabled = state.read_bool()?;
+        self.volume_code = state.read_u8()?;
+        self.frequency = state.read_u16()?;
+        self.freq_timer = state.read_u16()?;
+        self.position = state.read_u8()?;
+        self.sample_buffer = state.read_u8()?;
+        self.just_read = state.read_bool()?;
+        if self.volume_code > 3 || self.frequency > MAX_FREQUENCY || self.position > 31 {
+            return Err(Error::CorruptSaveState);
+        }
+        self.length.load_state(state)?;
+        state.read_into(&mut self.wave_ram)?;
+        return Ok(());
+    }
+}
@@ -58,6 +58,25 @@ pub const JOYPAD_P1_REG: u16 = 0xFF00;
 pub const SERIAL_SB_REG: u16 = 0xFF01;
 pub const SERIAL_SC_REG: u16 = 0xFF02;
 
+pub const AUDIO_START: u16 = 0xFF10;
+pub const AUDIO_END: u16 = 0xFF3F;
+pub const NR10_REG: u16 = 0xFF10;
+pub const NR11_REG: u16 = 0xFF11;
+pub const NR12_REG: u16 = 0xFF12;
+pub const NR14_REG: u16 = 0xFF14;
+pub const NR21_REG: u16 = 0xFF16;
+pub const NR24_REG: u16 = 0xFF19;
+pub const NR30_REG: u16 = 0xFF1A;
+pub const NR31_REG: u16 = 0xFF1B;
+pub const NR34_REG: u16 = 0xFF1E;
+pub const NR41_REG: u16 = 0xFF20;
+pub const NR44_REG: u16 = 0xFF23;
+pub const NR50_REG: u16 = 0xFF24;
+pub const NR51_REG: u16 = 0xFF25;
+pub const NR52_REG: u16 = 0xFF26;
+pub const WAVE_RAM_START: u16 = 0xFF30;
+pub const WAVE_RAM_END: u16 = 0xFF3F;
+
 pub const MACHINE_CYCLE: u8 = 4;
 pub const PREFIX_OPCODE: u8 = 0xCB;
 pub const MAX_SCANLINE_CLK_TICKS: u16 = 456;
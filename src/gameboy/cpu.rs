@@ -4,9 +4,12 @@ use core::panic;
 use crate::gameboy::interrupt_handler::{self, Interrupt};
 use crate::gameboy::Memory;
 use crate::gameboy::opcodes::{OPCODE_MACHINE_CYCLES, PREFIX_OPCODE_MACHINE_CYCLES};
+use crate::disassembler::opcode_name;
 use crate::gameboy::binary_utils::{self, split_16bit_num, build_16bit_num};
 use self::cpu_state::{CpuState, Status};
 use crate::gameboy::constants::{MACHINE_CYCLE, PREFIX_OPCODE};
+use crate::error::Error;
+use crate::save_state::{StateReader, StateWriter};
 
 #[derive(Debug)]
 pub struct Cpu {
@@ -63,10 +66,6 @@ impl Cpu {
                 // if self.current_opcode == 0x76 {
                 //     println!("HALT");
                 // }
-
-                if self.current_opcode == 0x10 {
-                    println!("STOP");
-                }
     
                 if self.current_opcode == PREFIX_OPCODE {
                     self.cpu_state = CpuState::FetchPrefix;
@@ -83,7 +82,7 @@ impl Cpu {
                                 }
                             }
                             Status::Running => (),
-                            Status::Error => panic!("Error Executing opcode"),
+                            Status::Error => panic!("Error executing {} ({:#04X})", opcode_name(self.current_opcode, false), self.current_opcode),
                         }
                     }
                 }
@@ -98,7 +97,7 @@ impl Cpu {
                             self.cpu_state = CpuState::Fetch;
                         },
                         Status::Running => (),
-                        Status::Error => panic!("Error Executing opcode"),
+                        Status::Error => panic!("Error executing {} ({:#04X})", opcode_name(self.current_opcode, true), self.current_opcode),
                     }
                 }
             },
@@ -114,7 +113,7 @@ impl Cpu {
                         self.cpu_state = CpuState::Fetch;
                     },
                     Status::Running => (),
-                    Status::Error => panic!("Error Executing opcode"),
+                    Status::Error => panic!("Error executing {} ({:#04X})", opcode_name(self.current_opcode, is_prefix), self.current_opcode),
                 }
                 
                 let t_reg = temp_reg;
@@ -135,6 +134,63 @@ impl Cpu {
         }
     }
 
+    /**
+     * Whether the cpu just finished an instruction (or is sitting halted) and 
+     * hasn't started working on the next one yet
+     */
+    pub fn is_at_instruction_boundary(&self) -> bool {
+        return self.cpu_clk_cycles == 0 && matches!(self.cpu_state, CpuState::Fetch | CpuState::Halt);
+    }
+
+    /**
+     * Saving the registers and where the cpu is in the current instruction, 
+     * so a state saved mid instruction picks back up on the right machine cycle
+     */
+    pub fn save_state(&self, state: &mut StateWriter) {
+        for reg in [self.a, self.b, self.c, self.d, self.e, self.f, self.h, self.l] {
+            state.write_u8(reg);
+        }
+        state.write_u16(self.sp);
+        state.write_u16(self.pc);
+
+        match self.cpu_state {
+            CpuState::Fetch => state.write_u8(0),
+            CpuState::FetchPrefix => state.write_u8(1),
+            CpuState::Execute { machine_cycle, temp_reg, is_prefix } => {
+                state.write_u8(2);
+                state.write_u8(machine_cycle);
+                state.write_u16(temp_reg);
+                state.write_bool(is_prefix);
+            },
+            CpuState::Halt => state.write_u8(3),
+        }
+        state.write_u8(self.cpu_clk_cycles);
+        state.write_u8(self.current_opcode);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), Error> {
+        for reg in [&mut self.a, &mut self.b, &mut self.c, &mut self.d, &mut self.e, &mut self.f, &mut self.h, &mut self.l] {
+            *reg = state.read_u8()?;
+        }
+        self.sp = state.read_u16()?;
+        self.pc = state.read_u16()?;
+
+        self.cpu_state = match state.read_u8()? {
+            0 => CpuState::Fetch,
+            1 => CpuState::FetchPrefix,
+            2 => CpuState::Execute { 
+                machine_cycle: state.read_u8()?, 
+                temp_reg: state.read_u16()?, 
+                is_prefix: state.read_bool()?,
+            },
+            3 => CpuState::Halt,
+            _ => return Err(Error::CorruptSaveState),
+        };
+        self.cpu_clk_cycles = state.read_u8()?;
+        self.current_opcode = state.read_u8()?;
+        return Ok(());
+    }
+
     /**
      * Retrieving the next opcode from memory
      */
@@ -1,3 +1,6 @@
+use crate::error::Error;
+use crate::save_state::{StateReader, StateWriter};
+
 pub struct Dma {
     src_address_reg: u8,            //$FF46 in memory
     current_address_offset: u8,     //Will help keep track of what address we are currently reading from and to
@@ -64,4 +67,24 @@ impl Dma {
         self.cycles_since_start = 0;
         self.currently_transferring = true;
     }
+
+    /**
+     * Saving how far along the transfer is so it picks back up in the same spot
+     */
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.src_address_reg);
+        state.write_u8(self.current_address_offset);
+        state.write_u8(self.cycles_since_start);
+        state.write_u8(self.clk_ticks_before_write);
+        state.write_bool(self.currently_transferring);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), Error> {
+        self.src_address_reg = state.read_u8()?;
+        self.current_address_offset = state.read_u8()?;
+        self.cycles_since_start = state.read_u8()?;
+        self.clk_ticks_before_write = state.read_u8()?;
+        self.currently_transferring = state.read_bool()?;
+        return Ok(());
+    }
 }
\ No newline at end of file
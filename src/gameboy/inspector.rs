@@ -0,0 +1,208 @@
+use std::fmt;
+
+use crate::gameboy::apu::{Apu, SoundChannel};
+use crate::gameboy::cpu::Cpu;
+use crate::gameboy::ppu::Ppu;
+
+const PPU_MODE_NAMES: [&str; 4] = ["HBlank", "VBlank", "OAM scan", "Drawing"];
+const DUTY_NAMES: [&str; 4] = ["12.5%", "25%", "50%", "75%"];
+
+/**
+ * A snapshot of the ppu and apu, for seeing what a game is up to. Printing
+ * it gives a few lines of text that fit in a terminal
+ */
+#[derive(Debug, Clone)]
+pub struct DebugState {
+    pub ppu: PpuState,
+    pub apu: ApuState,
+}
+
+/**
+ * The cpu's registers, plus the interrupt master enable since there's no
+ * other way to see it
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Registers {
+    pub a: u8,
+    pub f: u8,
+    pub b: u8,
+    pub c: u8,
+    pub d: u8,
+    pub e: u8,
+    pub h: u8,
+    pub l: u8,
+    pub sp: u16,
+    pub pc: u16,
+    pub ime: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct PpuState {
+    pub lcd_on: bool,
+    pub mode: u8,       //Same as the bottom 2 bits of STAT
+    pub ly: u8,
+    pub lyc: u8,
+    pub lcdc: u8,
+    pub stat: u8,
+    pub scx: u8,
+    pub scy: u8,
+    pub wx: u8,
+    pub wy: u8,
+}
+
+#[derive(Debug, Clone)]
+pub struct ApuState {
+    pub powered_on: bool,
+    pub nr50: u8,
+    pub nr51: u8,
+    pub channels: [ChannelState; 4],    //Pulse 1, pulse 2, wave, noise
+}
+
+#[derive(Debug, Clone)]
+pub struct ChannelState {
+    pub channel: SoundChannel,
+    pub enabled: bool,
+    pub dac_enabled: bool,
+    pub muted: bool,                    //By us, either directly or by soloing another channel
+    pub frequency: u16,                 //The 11 bit value from NRx3/NRx4. For noise it's NR43
+    pub frequency_hz: f32,              //How often the waveform repeats. For noise, how often the LFSR shifts
+    pub volume: u8,                     //0-15. For the wave channel it's what the volume code scales 15 to
+    pub envelope: Option<EnvelopeState>,
+    pub length: u16,                    //Length clocks left
+    pub length_enabled: bool,
+    pub duty: Option<u8>,               //0-3 like NRx1, only the pulse channels have one
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct EnvelopeState {
+    pub initial_volume: u8,
+    pub increasing: bool,
+    pub period: u8,                     //0 means the envelope is stopped
+}
+
+impl Registers {
+    pub fn capture(cpu: &Cpu, ime: bool) -> Self {
+        Self { a: cpu.a, f: cpu.f, b: cpu.b, c: cpu.c, d: cpu.d, e: cpu.e, h: cpu.h, l: cpu.l, sp: cpu.sp, pc: cpu.pc, ime }
+    }
+
+    /**
+     * Putting these back into the cpu. The low 4 bits of F don't exist, so
+     * they stay 0
+     */
+    pub fn apply(&self, cpu: &mut Cpu) {
+        (cpu.a, cpu.f, cpu.b, cpu.c, cpu.d, cpu.e, cpu.h, cpu.l) = (self.a, self.f & 0xF0, self.b, self.c, self.d, self.e, self.h, self.l);
+        (cpu.sp, cpu.pc) = (self.sp, self.pc);
+    }
+
+    pub fn af(&self) -> u16 {
+        return u16::from_be_bytes([self.a, self.f]);
+    }
+
+    pub fn bc(&self) -> u16 {
+        return u16::from_be_bytes([self.b, self.c]);
+    }
+
+    pub fn de(&self) -> u16 {
+        return u16::from_be_bytes([self.d, self.e]);
+    }
+
+    pub fn hl(&self) -> u16 {
+        return u16::from_be_bytes([self.h, self.l]);
+    }
+}
+
+impl PpuState {
+    pub fn capture(ppu: &Ppu) -> Self {
+        Self {
+            lcd_on: ppu.is_active(),
+            mode: ppu.read_stat_reg() & 0x3,
+            ly: ppu.read_ly_reg(),
+            lyc: ppu.read_lyc_reg(),
+            lcdc: ppu.read_lcdc_reg(),
+            stat: ppu.read_stat_reg(),
+            scx: ppu.read_scx_reg(),
+            scy: ppu.read_scy_reg(),
+            wx: ppu.read_wx_reg(),
+            wy: ppu.read_wy_reg(),
+        }
+    }
+}
+
+impl ApuState {
+    pub fn capture(apu: &Apu) -> Self {
+        Self {
+            powered_on: apu.powered_on(),
+            nr50: apu.nr50(),
+            nr51: apu.nr51(),
+            channels: SoundChannel::ALL.map(|channel| apu.channel_state(channel)),
+        }
+    }
+}
+
+impl fmt::Display for DebugState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", self.ppu)?;
+        write!(f, "{}", self.apu)
+    }
+}
+
+impl fmt::Display for Registers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let flags: String = ['Z', 'N', 'H', 'C'].iter().enumerate()
+            .map(|(i, &flag)| if self.f & (0x80 >> i) != 0 { flag } else { '-' })
+            .collect();
+        write!(
+            f,
+            "AF {:04X}  BC {:04X}  DE {:04X}  HL {:04X}  SP {:04X}  PC {:04X}  {flags}  IME {}",
+            self.af(), self.bc(), self.de(), self.hl(), self.sp, self.pc, self.ime as u8,
+        )
+    }
+}
+
+impl fmt::Display for PpuState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mode = if self.lcd_on { PPU_MODE_NAMES[self.mode as usize] } else { "LCD off" };
+        write!(
+            f,
+            "PPU  {mode:<8}  LY {:3}  LYC {:3}  LCDC {:02X}  STAT {:02X}  SCX {:3}  SCY {:3}  WX {:3}  WY {:3}",
+            self.ly, self.lyc, self.lcdc, self.stat, self.scx, self.scy, self.wx, self.wy,
+        )
+    }
+}
+
+impl fmt::Display for ApuState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "APU  {}  NR50 {:02X}  NR51 {:02X}", if self.powered_on { "on " } else { "off" }, self.nr50, self.nr51)?;
+        for channel in &self.channels {
+            write!(f, "\n  {channel}")?;
+        }
+        return Ok(());
+    }
+}
+
+impl fmt::Display for ChannelState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:<7} {:<3}  DAC {:<3}  {:>9.1} Hz ({:03X})  vol {:2}",
+            self.channel.name(),
+            if self.enabled { "on" } else { "off" },
+            if self.dac_enabled { "on" } else { "off" },
+            self.frequency_hz,
+            self.frequency,
+            self.volume,
+        )?;
+        match self.envelope {
+            Some(envelope) => write!(f, "  env {:2}{}{}", envelope.initial_volume, if envelope.increasing { '+' } else { '-' }, envelope.period)?,
+            None => write!(f, "  env  -  ")?,
+        }
+        write!(f, "  len {:3}{}", self.length, if self.length_enabled { "*" } else { " " })?;
+        if let Some(duty) = self.duty {
+            write!(f, "  duty {}", DUTY_NAMES[duty as usize])?;
+        }
+        if self.muted {
+            write!(f, "  [muted]")?;
+        }
+        return Ok(());
+    }
+}
@@ -1,6 +1,8 @@
 use core::panic;
 
+use crate::error::Error;
 use crate::gameboy::binary_utils;
+use crate::save_state::{StateReader, StateWriter};
 
 const MACHINE_CYCLE: u8 = 4;
 
@@ -64,8 +66,11 @@ impl InterruptHandler {
         match self.machine_cycle {
             1 => {
                 self.handling_isr = true;
+                //Only the enabled interrupts count, otherwise a pending one we
+                //don't care about would jump in front of the one we do
+                let enabled_and_requested_interrupts = self.ie_reg & self.if_reg;
                 for bit_pos in 0..=4 {
-                    if binary_utils::get_bit(self.if_reg, bit_pos) != 0 {
+                    if binary_utils::get_bit(enabled_and_requested_interrupts, bit_pos) != 0 {
                         self.if_reg = binary_utils::reset_bit(self.if_reg, bit_pos); 
                         self.handling_interrupt = match bit_pos {
                             0 => Interrupt::VBlank,
@@ -140,4 +145,61 @@ impl InterruptHandler {
     pub fn read_if_reg(&self) -> u8 {
         self.if_reg | 0xE0
     }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.ime_flag);
+        state.write_u8(self.ie_reg);
+        state.write_u8(self.if_reg);
+        state.write_u8(match self.handling_interrupt {
+            Interrupt::VBlank => 0,
+            Interrupt::LcdStatus => 1,
+            Interrupt::TimerOverflow => 2,
+            Interrupt::SerialLink => 3,
+            Interrupt::Joypad => 4,
+            Interrupt::Idle => 5,
+        });
+        state.write_bool(self.handling_isr);
+        state.write_u8(self.cycles_since_ime_flag_set);
+        state.write_u8(self.machine_cycle);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), Error> {
+        self.ime_flag = state.read_bool()?;
+        self.ie_reg = state.read_u8()?;
+        self.if_reg = state.read_u8()?;
+        self.handling_interrupt = match state.read_u8()? {
+            0 => Interrupt::VBlank,
+            1 => Interrupt::LcdStatus,
+            2 => Interrupt::TimerOverflow,
+            3 => Interrupt::SerialLink,
+            4 => Interrupt::Joypad,
+            5 => Interrupt::Idle,
+            _ => return Err(Error::CorruptSaveState),
+        };
+        self.handling_isr = state.read_bool()?;
+        self.cycles_since_ime_flag_set = state.read_u8()?;
+        self.machine_cycle = state.read_u8()?;
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn disabled_interrupts_dont_jump_the_queue() {
+        let mut interrupt_handler = InterruptHandler::new();
+        interrupt_handler.ime_flag = true;
+        interrupt_handler.write_ie_reg(0x04);   //Only the timer
+        interrupt_handler.write_if_reg(0x05);   //VBlank is requested too but it's disabled
+
+        let mut pc = 0x150;
+        for _ in 0..5 {
+            interrupt_handler.cycle(&mut pc);
+        }
+        assert_eq!(pc, 0x0050);
+        assert_eq!(interrupt_handler.read_if_reg(), 0xE1);
+        assert!(!interrupt_handler.ime_flag);
+    }
 }
@@ -1,5 +1,22 @@
-use minifb::{Key, Window};
+use crate::error::Error;
 use crate::gameboy::binary_utils;
+use crate::save_state::{StateReader, StateWriter};
+
+/**
+ * Which buttons the player is currently holding down. Frontends fill this in
+ * however they read input and hand it to the Game Boy
+ */
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Buttons {
+    pub a: bool,
+    pub b: bool,
+    pub select: bool,
+    pub start: bool,
+    pub up: bool,
+    pub down: bool,
+    pub left: bool,
+    pub right: bool,
+}
 
 #[derive(Copy, Clone)]
 enum ButtonState {
@@ -69,13 +86,13 @@ impl Joypad {
 
     //Essentially just need to check the keys in questions every cycle. If they change state going from
     //High to low then they have been pressed
-    pub fn cycle(&mut self, window: &Window) -> bool {
+    pub fn cycle(&mut self, buttons: &Buttons) -> bool {
         let mut request_interrupt = false;
         let mut prev_button_state;
 
         //Capturing the old state and updating to new state
         prev_button_state = self.a_and_right;
-        self.a_and_right = match (window.is_key_down(Key::J) && self.select_buttons.is_on()) | (window.is_key_down(Key::D) && self.select_dpad.is_on()) {
+        self.a_and_right = match (buttons.a && self.select_buttons.is_on()) | (buttons.right && self.select_dpad.is_on()) {
             true => ButtonState::On,
             false => ButtonState::Off,
         };
@@ -90,7 +107,7 @@ impl Joypad {
         }
 
         prev_button_state = self.b_and_left;
-        self.b_and_left = match (window.is_key_down(Key::K) && self.select_buttons.is_on()) | (window.is_key_down(Key::A) && self.select_dpad.is_on()) {
+        self.b_and_left = match (buttons.b && self.select_buttons.is_on()) | (buttons.left && self.select_dpad.is_on()) {
             true => ButtonState::On,
             false => ButtonState::Off,
         };
@@ -105,7 +122,7 @@ impl Joypad {
         }
 
         prev_button_state = self.select_and_up;
-        self.select_and_up = match (window.is_key_down(Key::W) && self.select_dpad.is_on()) | (window.is_key_down(Key::Backspace) && self.select_buttons.is_on()) {
+        self.select_and_up = match (buttons.up && self.select_dpad.is_on()) | (buttons.select && self.select_buttons.is_on()) {
             true => ButtonState::On,
             false => ButtonState::Off
         };
@@ -120,7 +137,7 @@ impl Joypad {
         }
         
         prev_button_state = self.start_and_down;
-        self.start_and_down = match (window.is_key_down(Key::Space) && self.select_buttons.is_on()) | (window.is_key_down(Key::S) && self.select_dpad.is_on()) {
+        self.start_and_down = match (buttons.start && self.select_buttons.is_on()) | (buttons.down && self.select_dpad.is_on()) {
             true => ButtonState::On,
             false => ButtonState::Off,
         };
@@ -170,4 +187,28 @@ impl Joypad {
         self.unused_bit_6 = ButtonState::convert_from_num(binary_utils::get_bit(data_to_write, 6));
         self.unused_bit_7 = ButtonState::convert_from_num(binary_utils::get_bit(data_to_write, 7));
     }
+
+    /**
+     * Every button state is a single bit so they all get packed into one byte
+     */
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8((self.unused_bit_7.value() << 7) |
+                        (self.unused_bit_6.value() << 6) |
+                        (self.select_buttons.value() << 5) |
+                        (self.select_dpad.value() << 4) |
+                        (self.start_and_down.value() << 3) |
+                        (self.select_and_up.value() << 2) |
+                        (self.b_and_left.value() << 1) |
+                        (self.a_and_right.value()));
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), Error> {
+        let value = state.read_u8()?;
+        self.write_joypad_reg(value);
+        self.start_and_down = ButtonState::convert_from_num(binary_utils::get_bit(value, 3));
+        self.select_and_up = ButtonState::convert_from_num(binary_utils::get_bit(value, 2));
+        self.b_and_left = ButtonState::convert_from_num(binary_utils::get_bit(value, 1));
+        self.a_and_right = ButtonState::convert_from_num(binary_utils::get_bit(value, 0));
+        return Ok(());
+    }
 }
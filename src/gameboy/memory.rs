@@ -1,12 +1,17 @@
-use minifb::Window;
 use crate::gameboy::timer::Timer;
-use crate::gameboy::joypad::Joypad;
-use crate::gameboy::serial_transfer::SerialTransfer;
+use crate::gameboy::apu::{Apu, AudioSample};
+use crate::gameboy::joypad::{Joypad, Buttons};
+use crate::gameboy::serial_transfer::{SerialTransfer, SerialCallback, LinkCable};
 use crate::gameboy::dma::Dma;
 use crate::gameboy::ppu::{ Ppu, enums::PpuMode };
 use crate::gameboy::interrupt_handler::InterruptHandler;
+use crate::gameboy::watchpoint::Watchpoints;
 use crate::gameboy::constants::*;
 use crate::game_cartridge::GameCartridge;
+use crate::error::Error;
+use crate::save_state::{StateReader, StateWriter};
+
+const DOCTOR_LY: u8 = 0x90;     //What gameboy-doctor expects LY to always read as
 
 pub struct Memory {
     pub game_cartridge: GameCartridge,          //16KB -> 0000h – 3FFFh (Non-switchable ROM bank), 16KB -> 4000h – 7FFFh (Switchable ROM bank), 8KB  -> A000h – BFFFh (External RAM in cartridge)
@@ -17,12 +22,15 @@ pub struct Memory {
     joypad: Joypad,                             //     -> FF00h         (Joypad)
     serial: SerialTransfer,                     //     -> FF01h - FF02h (Serial Transfer)
     timer: Timer,                               //     -> FF04h - FF07h
+    pub apu: Apu,                               //     -> FF10h - FF3Fh (Sound registers and wave RAM)
     pub ppu: Ppu,                               //Pixel Processing Unit. Houses most of the graphics related memory
     dma: Dma,                                   //     -> FF46h OAM DMA source address register
     io: [u8; 0x80],                             //     -> FF00h – FF7Fh (I/O ports)
     pub interrupt_handler: InterruptHandler,    //Will contain IE, IF, and IME registers (0xFFFF, 0xFF0F)
     hram: [u8; 0x7F],                           //     -> FF80h – FFFEh (HRAM)
     dma_read_or_write: bool,
+    ly_stubbed: bool,                           //LY always reads as DOCTOR_LY, for comparing traces
+    pub(crate) watchpoints: Option<Box<Watchpoints>>,  //None unless there's something to watch, so it costs nothing otherwise
 }
 
 impl Memory {
@@ -36,16 +44,31 @@ impl Memory {
             joypad: Joypad::new(),
             serial: SerialTransfer::new(),
             timer: Timer::new(),
+            apu: Apu::new(),
             ppu: Ppu::new(),
             io: [0; 0x80],
             interrupt_handler: InterruptHandler::new(),
             dma: Dma::new(),
             hram: [0; 0x7F],
             dma_read_or_write: false,
+            ly_stubbed: false,
+            watchpoints: None,
         }
     }
 
     pub fn read_byte(&self, address: u16) -> u8 {
+        let data = self.peek_byte(address);
+        if let Some(watchpoints) = &self.watchpoints {
+            watchpoints.check_read(address, data);
+        }
+        return data;
+    }
+
+    /**
+     * Reading without setting off any watchpoints, for looking at memory from
+     * outside the game
+     */
+    pub fn peek_byte(&self, address: u16) -> u8 {
         //Can't read anything except HRAM and the DMA register
         if (self.dma.currently_transferring && address != DMA && (address < HRAM_START || address > HRAM_END)) && !self.dma_read_or_write {
             return 0xFF;
@@ -98,10 +121,12 @@ impl Memory {
                     TIMER_TIMA_REG => self.timer.read_tima(),
                     TIMER_TMA_REG => self.timer.read_tma(),
                     TIMER_TAC_REG => self.timer.read_tac(),
+                    AUDIO_START ..= AUDIO_END => self.apu.read_register(address),
                     LCDC_REG => self.ppu.read_lcdc_reg(),
                     STAT_REG => self.ppu.read_stat_reg(),
                     SCY_REG => self.ppu.read_scy_reg(),
                     SCX_REG => self.ppu.read_scx_reg(),
+                    LY_REG if self.ly_stubbed => DOCTOR_LY,
                     LY_REG => self.ppu.read_ly_reg(),
                     LYC_REG => self.ppu.read_lyc_reg(),
                     BGP_REG => self.ppu.read_bgp_reg(),
@@ -120,13 +145,29 @@ impl Memory {
     }
 
     pub fn write_byte(&mut self, address: u16, data_to_write: u8) {
+        if self.watchpoints.is_none() {
+            return self.poke_byte(address, data_to_write);
+        }
+
+        let old = self.peek_byte(address);
+        self.poke_byte(address, data_to_write);
+        let read_back = self.peek_byte(address);
+        if let Some(watchpoints) = &self.watchpoints {
+            watchpoints.check_write(address, old, data_to_write, read_back);
+        }
+    }
+
+    /**
+     * Writing without setting off any watchpoints
+     */
+    pub fn poke_byte(&mut self, address: u16, data_to_write: u8) {
         //Can't write anything below OAM while DMA is going
         if (self.dma.currently_transferring && address != DMA && (address < HRAM_START || address > HRAM_END)) && !self.dma_read_or_write {
             return;
         }
 
         match address {
-            RAM_ENABLE_START ..= RAM_ENABLE_END => self.game_cartridge.write_0x0000_to_0x1fff(data_to_write),
+            RAM_ENABLE_START ..= RAM_ENABLE_END => self.game_cartridge.write_0x0000_to_0x1fff(data_to_write, address),
             ROM_BANK_NUM_START ..= ROM_BANK_NUM_END => self.game_cartridge.write_0x2000_to_0x3fff(data_to_write, address),
             RAM_BANK_NUM_START ..= RAM_BANK_NUM_END => self.game_cartridge.write_0x4000_to_0x5fff(data_to_write),
             BANKING_MODE_SEL_START ..= BANKING_MODE_SEL_END => self.game_cartridge.write_0x6000_to_0x7fff(data_to_write),
@@ -170,6 +211,7 @@ impl Memory {
                     TIMER_TIMA_REG => self.timer.write_2_tima(data_to_write),
                     TIMER_TMA_REG => self.timer.write_2_tma(data_to_write),
                     TIMER_TAC_REG => self.timer.write_2_tac(data_to_write),
+                    AUDIO_START ..= AUDIO_END => self.apu.write_register(address, data_to_write),
                     LCDC_REG => self.ppu.write_lcdc_reg(data_to_write),
                     STAT_REG => self.ppu.write_stat_reg(data_to_write),
                     SCY_REG => self.ppu.write_scy_reg(data_to_write),
@@ -201,14 +243,14 @@ impl Memory {
             Some((src_address, oam_offset)) => {
                 self.dma_read_or_write = true;
                 let oam_address = OAM_START + oam_offset as u16;
-                let src_address_data = self.read_byte(src_address); //This will get affected by VRAM access blocking
-                self.write_byte(oam_address, src_address_data);
+                let src_address_data = self.peek_byte(src_address); //This will get affected by VRAM access blocking
+                self.poke_byte(oam_address, src_address_data);
                 self.dma_read_or_write = false;
             },
         }
     }
 
-    pub fn gpu_cycle(&mut self, buffer: &mut Vec<u32>, buffer_index: &mut usize) {
+    pub fn gpu_cycle(&mut self, buffer: &mut [u32], buffer_index: &mut usize) {
         if let Some(pixel_color) = self.ppu.cycle() {
             buffer[*buffer_index] = match pixel_color {
                 super::ppu::enums::PaletteColors::White => 0xFFFFFF,
@@ -229,6 +271,10 @@ impl Memory {
         }
     }
 
+    pub fn cartridge_cycle(&mut self) {
+        self.game_cartridge.cycle();
+    }
+
     pub fn timer_cycle(&mut self) {
         self.timer.cycle();
         if self.timer.interrupted_requested {
@@ -237,6 +283,21 @@ impl Memory {
         }
     }
 
+    pub fn serial_cycle(&mut self) {
+        self.serial.cycle(self.timer.serial_div_bit());
+        if self.serial.interrupt_requested {
+            self.interrupt_handler.if_reg |= 0x08;
+            self.serial.interrupt_requested = false;
+        }
+    }
+
+    /**
+     * Returns a sample whenever the apu has one ready
+     */
+    pub fn apu_cycle(&mut self) -> Option<AudioSample> {
+        return self.apu.cycle(self.timer.apu_div_bit());
+    }
+
     /**
      * Again this is wildly ugly but I had to pull out the memory read and writes because they aren't avaliable
      * to the interrupt handler, but its also apart of the memory object so I can't pass it in. I'm going to have
@@ -256,9 +317,63 @@ impl Memory {
         }
     }
     
-    pub fn joypad_cycle(&mut self, window: &Window) {
-        if self.joypad.cycle(window) {
+    pub fn set_serial_callback(&mut self, on_transfer: Option<SerialCallback>) {
+        self.serial.set_callback(on_transfer);
+    }
+
+    pub fn set_linked(&mut self, linked: bool) {
+        self.serial.set_linked(linked);
+    }
+
+    pub fn set_ly_stubbed(&mut self, ly_stubbed: bool) {
+        self.ly_stubbed = ly_stubbed;
+    }
+
+    pub fn link_cycle(&mut self, link_cable: &mut dyn LinkCable) {
+        self.serial.link_cycle(link_cable);
+    }
+
+    pub fn joypad_cycle(&mut self, buttons: &Buttons) {
+        if self.joypad.cycle(buttons) {
             self.interrupt_handler.if_reg |= 0x10;
         }
     }
+
+    /**
+     * Saving every component that lives on the memory bus
+     */
+    pub fn save_state(&self, state: &mut StateWriter) {
+        self.game_cartridge.save_state(state);
+        state.write_bytes(&self.wram_0);
+        state.write_bytes(&self.wram_x);
+        state.write_bytes(&self.unused);
+        self.joypad.save_state(state);
+        self.serial.save_state(state);
+        self.timer.save_state(state);
+        self.apu.save_state(state);
+        self.ppu.save_state(state);
+        self.dma.save_state(state);
+        state.write_bytes(&self.io);
+        self.interrupt_handler.save_state(state);
+        state.write_bytes(&self.hram);
+        state.write_bool(self.dma_read_or_write);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), Error> {
+        self.game_cartridge.load_state(state)?;
+        state.read_into(&mut self.wram_0)?;
+        state.read_into(&mut self.wram_x)?;
+        state.read_into(&mut self.unused)?;
+        self.joypad.load_state(state)?;
+        self.serial.load_state(state)?;
+        self.timer.load_state(state)?;
+        self.apu.load_state(state)?;
+        self.ppu.load_state(state)?;
+        self.dma.load_state(state)?;
+        state.read_into(&mut self.io)?;
+        self.interrupt_handler.load_state(state)?;
+        state.read_into(&mut self.hram)?;
+        self.dma_read_or_write = state.read_bool()?;
+        return Ok(());
+    }
 }
\ No newline at end of file
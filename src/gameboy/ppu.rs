@@ -8,6 +8,8 @@ use self::registers::PpuRegisters;
 use self::enums::{PaletteColors, PpuMode, SpritePriority, SpriteScanlineVisibility, SpriteSize, State, TileDataArea};
 use self::tile_and_sprite::*;
 use crate::gameboy::constants::*;
+use crate::error::Error;
+use crate::save_state::{StateReader, StateWriter};
 pub struct Ppu {
     pub tile_data_0: [Tile; 128],       //$8000–$87FF
     pub tile_data_1: [Tile; 128],       //$8800–$8FFF
@@ -522,6 +524,78 @@ impl Ppu {
     pub fn write_wy_reg(&mut self, value: u8) {
         self.ppu_registers.wy = value;
     }
+
+    /**
+     * Saving vram, oam, the registers and everything about the scanline being
+     * drawn. That includes both pixel fifos since a state can be saved in the
+     * middle of mode 3
+     */
+    pub fn save_state(&self, state: &mut StateWriter) {
+        for tile in self.tile_data_0.iter().chain(&self.tile_data_1).chain(&self.tile_data_2) {
+            for row in tile.pixel_rows {
+                state.write_u8(row.lower_bits);
+                state.write_u8(row.upper_bits);
+            }
+        }
+        state.write_bytes(&self.tile_map_0);
+        state.write_bytes(&self.tile_map_1);
+        for sprite in &self.oam {
+            sprite.save_state(state);
+        }
+
+        self.ppu_registers.save_state(state);
+        state.write_u16(self.clk_ticks);
+        state.write_u8(self.visible_sprites.len() as u8);
+        for sprite in &self.visible_sprites {
+            sprite.save_state(state);
+        }
+        self.pixel_fetcher.save_state(state);
+        for fifo in [&self.sprite_fifo, &self.bg_window_fifo] {
+            state.write_u8(fifo.len() as u8);
+            for pixel in fifo {
+                pixel.save_state(state);
+            }
+        }
+        state.write_u8(self.initial_pixel_shift);
+        state.write_u8(self.penalty);
+        state.write_bool(self.vblank_interrupt_req);
+        state.write_bool(self.stat_interrupt_req);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), Error> {
+        for tile in self.tile_data_0.iter_mut().chain(&mut self.tile_data_1).chain(&mut self.tile_data_2) {
+            for row in tile.pixel_rows.iter_mut() {
+                row.lower_bits = state.read_u8()?;
+                row.upper_bits = state.read_u8()?;
+            }
+        }
+        state.read_into(&mut self.tile_map_0)?;
+        state.read_into(&mut self.tile_map_1)?;
+        for sprite in self.oam.iter_mut() {
+            sprite.load_state(state)?;
+        }
+
+        self.ppu_registers.load_state(state)?;
+        self.clk_ticks = state.read_u16()?;
+        self.visible_sprites.clear();
+        for _ in 0..state.read_u8()? {
+            let mut sprite = Sprite::new();
+            sprite.load_state(state)?;
+            self.visible_sprites.push(sprite);
+        }
+        self.pixel_fetcher.load_state(state)?;
+        for fifo in [&mut self.sprite_fifo, &mut self.bg_window_fifo] {
+            fifo.clear();
+            for _ in 0..state.read_u8()? {
+                fifo.push(Pixel::load_state(state)?);
+            }
+        }
+        self.initial_pixel_shift = state.read_u8()?;
+        self.penalty = state.read_u8()?;
+        self.vblank_interrupt_req = state.read_bool()?;
+        self.stat_interrupt_req = state.read_bool()?;
+        return Ok(());
+    }
 }
 
 
@@ -1,4 +1,6 @@
 use crate::gameboy::binary_utils;
+use crate::error::Error;
+use crate::save_state::{StateReader, StateWriter};
 
 use super::{enums::{Orientation, SpritePalette, SpritePriority, SpriteSize, State, TileMapArea}, registers::PpuRegisters, Sprite, Tile};
 
@@ -237,6 +239,21 @@ impl PixelFetcher {
         }
        return false;
     }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.x_coordinate);
+        state.write_u8(self.win_x_coordinate);
+        state.write_u8(self.win_y_coordinate);
+        state.write_bool(self.drawing_window);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), Error> {
+        self.x_coordinate = state.read_u8()?;
+        self.win_x_coordinate = state.read_u8()?;
+        self.win_y_coordinate = state.read_u8()?;
+        self.drawing_window = state.read_bool()?;
+        return Ok(());
+    }
 }
 
 #[derive(Clone, Copy)]
@@ -259,4 +276,44 @@ impl Pixel {
             is_sprite: true,
         }
     }
+
+    /**
+     * Background pixels don't have a palette or priority so those get 
+     * written as 0xFF
+     */
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.color_id);
+        state.write_u8(self.palette.map_or(0xFF, |palette| palette as u8));
+        state.write_u8(self.bg_priority.map_or(0xFF, |priority| priority as u8));
+        state.write_bool(self.is_sprite);
+    }
+
+    pub fn load_state(state: &mut StateReader) -> Result<Self, Error> {
+        let color_id = state.read_u8()?;
+        let palette = match state.read_u8()? {
+            0 => Some(SpritePalette::Obp0),
+            1 => Some(SpritePalette::Obp1),
+            2 => Some(SpritePalette::Obp2),
+            3 => Some(SpritePalette::Obp3),
+            4 => Some(SpritePalette::Obp4),
+            5 => Some(SpritePalette::Obp5),
+            6 => Some(SpritePalette::Obp6),
+            7 => Some(SpritePalette::Obp7),
+            0xFF => None,
+            _ => return Err(Error::CorruptSaveState),
+        };
+        let bg_priority = match state.read_u8()? {
+            0 => Some(SpritePriority::OverBg),
+            1 => Some(SpritePriority::UnderBg),
+            0xFF => None,
+            _ => return Err(Error::CorruptSaveState),
+        };
+
+        return Ok(Self {
+            color_id,
+            palette,
+            bg_priority,
+            is_sprite: state.read_bool()?,
+        });
+    }
 }
\ No newline at end of file
@@ -1,4 +1,6 @@
 use crate::gameboy::{ppu::enums::*, binary_utils};
+use crate::error::Error;
+use crate::save_state::{StateReader, StateWriter};
 
 #[derive(Debug)]
 pub struct PpuRegisters {
@@ -61,6 +63,51 @@ impl PpuRegisters {
             self.stat.lyc_ly_compare = State::Off;
         }
     }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.lcdc.read_reg_raw());
+        state.write_u8(self.ly);
+        state.write_u8(self.lyc);
+        state.write_u8(self.stat.read_reg_raw());
+        state.write_u8(self.scx);
+        state.write_u8(self.scy);
+        state.write_u8(self.wx);
+        state.write_u8(self.wy);
+        state.write_u8(self.bgp.read_reg_raw());
+        state.write_u8(self.obp0.read_reg_raw());
+        state.write_u8(self.obp1.read_reg_raw());
+        state.write_u8(self.x_scanline_coord);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), Error> {
+        self.lcdc.write_reg_raw(state.read_u8()?);
+        self.ly = state.read_u8()?;
+        self.lyc = state.read_u8()?;
+
+        //The compare and mode bits are read only so they have to be set by hand
+        let stat = state.read_u8()?;
+        self.stat.write_reg_from_u8(stat);
+        self.stat.lyc_ly_compare = match binary_utils::get_bit(stat, 2) {
+            0 => State::Off,
+            _ => State::On,
+        };
+        self.stat.ppu_mode = match stat & 0x3 {
+            0b00 => PpuMode::Hblank,
+            0b01 => PpuMode::Vblank,
+            0b10 => PpuMode::OamScan,
+            _ => PpuMode::DrawingPixels,
+        };
+
+        self.scx = state.read_u8()?;
+        self.scy = state.read_u8()?;
+        self.wx = state.read_u8()?;
+        self.wy = state.read_u8()?;
+        self.bgp.write_reg_from_u8(state.read_u8()?);
+        self.obp0.write_reg_from_u8(state.read_u8()?);
+        self.obp1.write_reg_from_u8(state.read_u8()?);
+        self.x_scanline_coord = state.read_u8()?;
+        return Ok(());
+    }
 }
 
 #[derive(Debug)]
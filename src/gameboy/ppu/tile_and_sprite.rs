@@ -1,5 +1,7 @@
 use crate::gameboy::ppu::enums::{SpritePriority, Orientation, SpritePalette, VramBank};
 use crate::gameboy::binary_utils;
+use crate::error::Error;
+use crate::save_state::{StateReader, StateWriter};
 
 /*
     Represents a 8x8 square of pixels. Here we have an array of PixelRows
@@ -148,4 +150,19 @@ impl Sprite {
             _ => panic!("How did you get a higher number than 7"),
         };
     }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.y_pos);
+        state.write_u8(self.x_pos);
+        state.write_u8(self.tile_index);
+        state.write_u8(self.attribute_flags_raw());
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), Error> {
+        self.y_pos = state.read_u8()?;
+        self.x_pos = state.read_u8()?;
+        self.tile_index = state.read_u8()?;
+        self.write_attribute_flags(state.read_u8()?);
+        return Ok(());
+    }
 }
\ No newline at end of file
@@ -1,4 +1,68 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::error::Error;
 use crate::gameboy::binary_utils;
+use crate::save_state::{StateReader, StateWriter};
+
+/**
+ * Gets handed every byte the Game Boy sends out over the serial port
+ */
+pub type SerialCallback = Box<dyn FnMut(u8)>;
+
+/**
+ * What goes over the link cable. Whole bytes go across instead of single
+ * bits: the master says what it's sending when the transfer starts, the slave
+ * answers with what's in its SB, and the master says when the last bit is
+ * across so the slave can finish at the same time
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkMessage {
+    Start(u8),
+    Reply(u8),
+    Done,
+}
+
+/**
+ * The other end of the link cable. Gets run alongside the Game Boy every clk
+ * cycle. It takes whatever our serial port sent out of outgoing, and puts
+ * anything the other side sent that's due by now into incoming
+ */
+pub trait LinkCable {
+    fn cycle(&mut self, outgoing: &mut Vec<LinkMessage>, incoming: &mut Vec<LinkMessage>);
+}
+
+/**
+ * Collects everything sent over the serial port into a string. Test roms
+ * like blargg's print their results this way. Clones share the same text,
+ * so one can be handed to the Game Boy and another kept to read from
+ */
+#[derive(Clone, Default)]
+pub struct SerialSink {
+    text: Rc<RefCell<String>>,
+}
+
+impl SerialSink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /**
+     * A callback for Gameboy::set_serial_callback that appends to this sink
+     */
+    pub fn callback(&self) -> SerialCallback {
+        let text = self.text.clone();
+        return Box::new(move |byte| text.borrow_mut().push(byte as char));
+    }
+
+    pub fn text(&self) -> String {
+        return self.text.borrow().clone();
+    }
+
+    pub fn contains(&self, pattern: &str) -> bool {
+        return self.text.borrow().contains(pattern);
+    }
+}
 
 enum ClockSpeed {
     NormalSpeed,
@@ -74,6 +138,15 @@ pub struct SerialTransfer {
     unused_bit_2: u8,
     clock_speed: ClockSpeed,        //CGB Feature
     clock_select: ClockSelect,
+    bits_shifted: u8,               //How far into the current transfer we are
+    prev_div_bit: bool,
+    pub interrupt_requested: bool,
+    on_transfer: Option<SerialCallback>,
+    linked: bool,                       //Only queue up link messages if somebody is going to take them
+    link_outgoing: Vec<LinkMessage>,
+    link_incoming: Vec<LinkMessage>,
+    link_reply: Option<u8>,             //What the slave had in SB, for when we're the master
+    link_received: Option<u8>,          //What the master is sending, for when we're the slave
 }
 
 impl SerialTransfer {
@@ -88,6 +161,15 @@ impl SerialTransfer {
             unused_bit_2: 0,
             clock_speed: ClockSpeed::NormalSpeed,
             clock_select: ClockSelect::Master,
+            bits_shifted: 0,
+            prev_div_bit: false,
+            interrupt_requested: false,
+            on_transfer: None,
+            linked: false,
+            link_outgoing: Vec::new(),
+            link_incoming: Vec::new(),
+            link_reply: None,
+            link_received: None,
         }
     }
     /**
@@ -115,7 +197,112 @@ impl SerialTransfer {
         self.sb = data_to_write;
     }
 
+    /**
+     * Carrying out one clk cycle. Off the internal clock a bit gets shifted
+     * out of the top of SB on every falling edge of div_bit, and what the
+     * other side sent gets shifted in at the bottom. With nobody on the other
+     * end that's always a 1. Off the external clock we wait for the master on
+     * the other end of the link cable, which never comes without one
+     */
+    pub fn cycle(&mut self, div_bit: bool) {
+        let falling_edge = self.prev_div_bit && !div_bit;
+        self.prev_div_bit = div_bit;
+
+        if !falling_edge {
+            return;
+        }
+        if let (TransferStatus::RequestedOrInProgress, ClockSelect::Master) = (&self.transfer_enable, &self.clock_select) {
+            self.shift_bit(true);
+        }
+    }
+
+    /**
+     * Once all 8 bits are across the transfer is done, which clears bit 7 of
+     * SC and requests the serial interrupt
+     */
+    fn shift_bit(&mut self, bit_in: bool) {
+        self.sb = (self.sb << 1) | bit_in as u8;
+        self.bits_shifted += 1;
+        if self.bits_shifted == 8 {
+            //If the slave never answered, it wasn't ready and we keep the 1s
+            if let Some(reply) = self.link_reply.take() {
+                self.sb = reply;
+            }
+            self.send_link_message(LinkMessage::Done);
+            self.finish_transfer();
+        }
+    }
+
+    fn finish_transfer(&mut self) {
+        self.bits_shifted = 0;
+        self.transfer_enable = TransferStatus::Idle;
+        self.interrupt_requested = true;
+    }
+
+    fn send_link_message(&mut self, message: LinkMessage) {
+        if self.linked {
+            self.link_outgoing.push(message);
+        }
+    }
+
+    pub fn set_linked(&mut self, linked: bool) {
+        self.linked = linked;
+        self.link_outgoing.clear();
+        self.link_reply = None;
+        self.link_received = None;
+    }
+
+    /**
+     * Swapping messages with the other end of the link cable. Should be
+     * called every clk cycle while it's plugged in
+     */
+    pub fn link_cycle(&mut self, link_cable: &mut dyn LinkCable) {
+        link_cable.cycle(&mut self.link_outgoing, &mut self.link_incoming);
+
+        let mut incoming = std::mem::take(&mut self.link_incoming);
+        for message in incoming.drain(..) {
+            self.receive_link_message(message);
+        }
+        self.link_incoming = incoming;
+    }
+
+    /**
+     * Only a slave that's waiting on a transfer answers the master, otherwise
+     * the master ends up with 0xFF. Masters ignore each other
+     */
+    fn receive_link_message(&mut self, message: LinkMessage) {
+        let waiting_as_slave = matches!((&self.transfer_enable, &self.clock_select), (TransferStatus::RequestedOrInProgress, ClockSelect::Slave));
+        match message {
+            LinkMessage::Start(byte) if waiting_as_slave => {
+                self.link_received = Some(byte);
+                self.send_link_message(LinkMessage::Reply(self.sb));
+            },
+            LinkMessage::Reply(byte) if !waiting_as_slave => self.link_reply = Some(byte),
+            LinkMessage::Done if waiting_as_slave => {
+                if let Some(byte) = self.link_received.take() {
+                    self.sb = byte;
+                    self.finish_transfer();
+                }
+            },
+            _ => (),
+        }
+    }
+
     pub fn write_sc_reg(&mut self, data_to_write: u8) {
+        self.unpack_sc_reg(data_to_write);
+        self.bits_shifted = 0;
+
+        //Starting a transfer off the internal clock sends out whatever is in SB
+        if let (TransferStatus::RequestedOrInProgress, ClockSelect::Master) = (&self.transfer_enable, &self.clock_select) {
+            if let Some(on_transfer) = &mut self.on_transfer {
+                on_transfer(self.sb);
+            }
+            self.link_reply = None;
+            self.send_link_message(LinkMessage::Start(self.sb));
+        }
+    }
+
+    fn unpack_sc_reg(&mut self, data_to_write: u8) {
         self.transfer_enable = TransferStatus::convert_from_num(binary_utils::get_bit(data_to_write, 7));
         self.unused_bit_6 = binary_utils::get_bit(data_to_write, 6);
         self.unused_bit_5 = binary_utils::get_bit(data_to_write, 5);
@@ -125,5 +312,76 @@ impl SerialTransfer {
         self.clock_speed = ClockSpeed::convert_from_num(binary_utils::get_bit(data_to_write, 1));
         self.clock_select = ClockSelect::convert_from_num(binary_utils::get_bit(data_to_write, 0));
     }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.sb);
+        state.write_u8(self.read_sc_reg());
+        state.write_u8(self.bits_shifted);
+        state.write_bool(self.prev_div_bit);
+    }
+
+    /**
+     * Loading doesn't count as starting a transfer, so the callback is left
+     * alone. Anything that was halfway across the link cable is forgotten
+     */
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), Error> {
+        self.sb = state.read_u8()?;
+        let sc = state.read_u8()?;
+        self.unpack_sc_reg(sc);
+        self.link_reply = None;
+        self.link_received = None;
+        self.bits_shifted = state.read_u8()?;
+        if self.bits_shifted >= 8 {
+            return Err(Error::CorruptSaveState);
+        }
+        self.prev_div_bit = state.read_bool()?;
+        return Ok(());
+    }
+
+    pub fn set_callback(&mut self, on_transfer: Option<SerialCallback>) {
+        self.on_transfer = on_transfer;
+    }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /**
+     * Running the serial port off a counter the way the timer's DIV would
+     * drive it. Returns how many clk cycles it took to request the interrupt
+     */
+    fn run_until_interrupt(serial: &mut SerialTransfer, max_clk_cycles: u32) -> Option<u32> {
+        for counter in 1..=max_clk_cycles {
+            serial.cycle((counter >> 8) & 0x1 == 1);
+            if serial.interrupt_requested {
+                return Some(counter);
+            }
+        }
+        return None;
+    }
+
+    #[test]
+    fn internal_clock_shifts_in_ones_without_a_partner() {
+        let mut serial = SerialTransfer::new();
+        serial.write_sb_reg(0x5A);
+        serial.write_sc_reg(0x81);
+
+        //8 bits at 512 clk cycles each
+        assert_eq!(run_until_interrupt(&mut serial, 10000), Some(8 * 512));
+        assert_eq!(serial.read_sb_reg(), 0xFF);
+        assert_eq!(serial.read_sc_reg() & 0x80, 0);
+    }
+
+    #[test]
+    fn external_clock_waits_for_a_partner() {
+        let mut serial = SerialTransfer::new();
+        serial.write_sb_reg(0x5A);
+        serial.write_sc_reg(0x80);
+
+        assert_eq!(run_until_interrupt(&mut serial, 10000), None);
+        assert_eq!(serial.read_sb_reg(), 0x5A);
+        assert_eq!(serial.read_sc_reg() & 0x80, 0x80);
+    }
+}
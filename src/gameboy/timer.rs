@@ -1,3 +1,6 @@
+use crate::error::Error;
+use crate::save_state::{StateReader, StateWriter};
+
 const MACHINE_CYCLE: u8 = 4;
 
 pub struct Timer {
@@ -151,6 +154,21 @@ impl Timer {
         return (self.div_reg >> 8) as u8;
     }
 
+    /**
+     * Bit 4 of DIV. The apu's frame sequencer steps whenever it goes from 1 to 0
+     */
+    pub fn apu_div_bit(&self) -> bool {
+        return (self.div_reg >> 12) & 0x1 == 1;
+    }
+
+    /**
+     * Bit 0 of DIV. The serial port's internal clock shifts a bit whenever
+     * it goes from 1 to 0, which is 8192 times a second
+     */
+    pub fn serial_div_bit(&self) -> bool {
+        return (self.div_reg >> 8) & 0x1 == 1;
+    }
+
     pub fn read_tima(&self) -> u8 {
         return self.tima_reg;
     }
@@ -162,4 +180,33 @@ impl Timer {
     pub fn read_tac(&self) -> u8 {
         return self.tac_reg;
     }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_u16(self.div_reg);
+        state.write_u8(self.tima_reg);
+        state.write_u8(self.tma_reg);
+        state.write_u8(self.tac_reg);
+        state.write_u8(self.prev_div_bit_value);
+        state.write_bool(self.tima_overflow);
+        state.write_bool(self.tac_en_falling_edge);
+        state.write_u8(self.ticks_since_overflow);
+        state.write_bool(self.interrupted_requested);
+        state.write_bool(self.tima_a_cycle_write_occurred);
+        state.write_u8(self.tima_write_value);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), Error> {
+        self.div_reg = state.read_u16()?;
+        self.tima_reg = state.read_u8()?;
+        self.tma_reg = state.read_u8()?;
+        self.tac_reg = state.read_u8()?;
+        self.prev_div_bit_value = state.read_u8()?;
+        self.tima_overflow = state.read_bool()?;
+        self.tac_en_falling_edge = state.read_bool()?;
+        self.ticks_since_overflow = state.read_u8()?;
+        self.interrupted_requested = state.read_bool()?;
+        self.tima_a_cycle_write_occurred = state.read_bool()?;
+        self.tima_write_value = state.read_u8()?;
+        return Ok(());
+    }
 }
\ No newline at end of file
@@ -0,0 +1,139 @@
+use std::cell::RefCell;
+use std::fmt;
+
+/**
+ * What a watchpoint cares about. Change only goes off for writes that
+ * actually leave something different behind
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    Change,
+}
+
+/**
+ * Watching every address from start to end, end included
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub start: u16,
+    pub end: u16,
+    pub kind: WatchKind,
+}
+
+impl Watchpoint {
+    fn contains(&self, address: u16) -> bool {
+        return (self.start..=self.end).contains(&address);
+    }
+}
+
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self.kind {
+            WatchKind::Read => "read",
+            WatchKind::Write => "write",
+            WatchKind::Change => "change",
+        };
+        match self.start == self.end {
+            true => write!(f, "{kind} {:04X}", self.start),
+            false => write!(f, "{kind} {:04X}-{:04X}", self.start, self.end),
+        }
+    }
+}
+
+/**
+ * One time a watchpoint went off. pc and opcode are for the instruction
+ * that did it. Pushes from jumping to an interrupt count against the
+ * instruction that got interrupted
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchHit {
+    pub watchpoint: Watchpoint,
+    pub address: u16,
+    pub pc: u16,
+    pub opcode: u8,
+    pub old: u8,        //What was there before. Reads leave old and new the same
+    pub new: u8,        //What got written, or what reads back afterwards for Change
+}
+
+impl fmt::Display for WatchHit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.watchpoint.kind {
+            WatchKind::Read => write!(f, "Read {:02X} from {:04X}", self.old, self.address)?,
+            WatchKind::Write | WatchKind::Change => write!(f, "Wrote {:04X}: {:02X} -> {:02X}", self.address, self.old, self.new)?,
+        }
+        write!(f, " at PC {:04X} (opcode {:02X}), watching {}", self.pc, self.opcode, self.watchpoint)
+    }
+}
+
+/**
+ * Everything memory needs to check accesses against. Hits go into a RefCell
+ * since reads only get &Memory
+ */
+pub(crate) struct Watchpoints {
+    watchpoints: Vec<Watchpoint>,
+    pc: u16,
+    opcode: u8,
+    hits: RefCell<Vec<WatchHit>>,
+}
+
+impl Watchpoints {
+    pub fn new() -> Self {
+        Self {
+            watchpoints: Vec::new(),
+            pc: 0,
+            opcode: 0,
+            hits: RefCell::new(Vec::new()),
+        }
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        return &self.watchpoints;
+    }
+
+    pub fn add(&mut self, watchpoint: Watchpoint) {
+        if !self.watchpoints.contains(&watchpoint) {
+            self.watchpoints.push(watchpoint);
+        }
+    }
+
+    pub fn remove(&mut self, watchpoint: Watchpoint) -> bool {
+        let len = self.watchpoints.len();
+        self.watchpoints.retain(|&other| other != watchpoint);
+        return self.watchpoints.len() != len;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.watchpoints.is_empty();
+    }
+
+    /**
+     * Whatever gets hit from here on was done by the instruction at pc
+     */
+    pub fn start_instruction(&mut self, pc: u16, opcode: u8) {
+        self.pc = pc;
+        self.opcode = opcode;
+    }
+
+    pub fn take_hits(&self) -> Vec<WatchHit> {
+        return self.hits.take();
+    }
+
+    pub fn check_read(&self, address: u16, data: u8) {
+        self.check(address, data, data, |kind| kind == WatchKind::Read);
+    }
+
+    pub fn check_write(&self, address: u16, old: u8, written: u8, read_back: u8) {
+        self.check(address, old, written, |kind| kind == WatchKind::Write);
+        self.check(address, old, read_back, |kind| kind == WatchKind::Change && old != read_back);
+    }
+
+    fn check(&self, address: u16, old: u8, new: u8, goes_off: impl Fn(WatchKind) -> bool) {
+        for &watchpoint in &self.watchpoints {
+            if watchpoint.contains(address) && goes_off(watchpoint.kind) {
+                self.hits.borrow_mut().push(WatchHit { watchpoint, address, pc: self.pc, opcode: self.opcode, old, new });
+            }
+        }
+    }
+}
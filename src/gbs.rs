@@ -0,0 +1,218 @@
+use crate::error::Error;
+use crate::game_cartridge::header::{self, CARTRIDGE_TYPE, HEADER_CHECKSUM, RAM_SIZE, ROM_SIZE, TITLE_START};
+
+const GBS_MAGIC: &[u8; 3] = b"GBS";
+const GBS_HEADER_LEN: usize = 0x70;
+const MIN_LOAD_ADDRESS: u16 = 0x400;    //Everything below is where our driver goes
+const DRIVER_START: u16 = 0x150;
+const TITLE_LEN: usize = 16;
+
+/**
+ * The header at the start of a GBS file. Addresses are where things end up
+ * in the Game Boy's memory map
+ */
+#[derive(Debug, Clone)]
+pub struct GbsHeader {
+    pub version: u8,
+    pub num_of_songs: u8,
+    pub first_song: u8,         //Counting from 1
+    pub load_address: u16,
+    pub init_address: u16,      //Gets called with the song number (counting from 0) in A
+    pub play_address: u16,      //Gets called at the vblank or timer rate
+    pub stack_pointer: u16,
+    pub timer_modulo: u8,
+    pub timer_control: u8,
+    pub title: String,
+    pub author: String,
+    pub copyright: String,
+}
+
+/**
+ * A GBS music rip. It's the sound code and data pulled out of a game, along
+ * with a header saying how to drive it
+ */
+pub struct GbsFile {
+    pub header: GbsHeader,
+    data: Vec<u8>,
+}
+
+impl GbsFile {
+    pub fn parse(bytes: &[u8]) -> Result<Self, Error> {
+        if bytes.len() <= GBS_HEADER_LEN || &bytes[0..3] != GBS_MAGIC {
+            return Err(Error::InvalidGbs("not a GBS file"));
+        }
+
+        let read_u16 = |offset: usize| u16::from_le_bytes([bytes[offset], bytes[offset + 1]]);
+        let read_string = |offset: usize| {
+            let field = &bytes[offset..offset + 0x20];
+            let len = field.iter().position(|byte| *byte == 0).unwrap_or(field.len());
+            String::from_utf8_lossy(&field[..len]).trim().to_string()
+        };
+
+        let header = GbsHeader {
+            version: bytes[0x03],
+            num_of_songs: bytes[0x04],
+            first_song: bytes[0x05],
+            load_address: read_u16(0x06),
+            init_address: read_u16(0x08),
+            play_address: read_u16(0x0A),
+            stack_pointer: read_u16(0x0C),
+            timer_modulo: bytes[0x0E],
+            timer_control: bytes[0x0F],
+            title: read_string(0x10),
+            author: read_string(0x30),
+            copyright: read_string(0x50),
+        };
+
+        if header.version != 1 {
+            return Err(Error::InvalidGbs("only version 1 GBS files are supported"));
+        }
+        if header.num_of_songs == 0 {
+            return Err(Error::InvalidGbs("there aren't any songs"));
+        }
+        if !(MIN_LOAD_ADDRESS..0x8000).contains(&header.load_address) {
+            return Err(Error::InvalidGbs("load address has to be between 0x400 and 0x7FFF"));
+        }
+
+        let data = bytes[GBS_HEADER_LEN..].to_vec();
+        if header.load_address as usize + data.len() > 0x800000 {
+            return Err(Error::InvalidGbs("too big to fit in a cartridge"));
+        }
+        return Ok(Self { header, data });
+    }
+
+    /**
+     * Bit 2 of the timer control says to call play off the timer interrupt
+     * instead of vblank
+     */
+    pub fn uses_timer(&self) -> bool {
+        return self.header.timer_control & 0x04 != 0;
+    }
+
+    /**
+     * Building a cartridge that plays song (counting from 0). The rip gets
+     * loaded where it expects to be, and a small driver in front of it calls
+     * init and then sleeps, calling play from the vblank or timer interrupt.
+     * If it fits in 32KB it's ROM+RAM, otherwise it's an MBC5+RAM so the rip
+     * can switch banks by writing to 0x2000. Either way rips get the 8KB of
+     * cartridge RAM at A000 - BFFF they're allowed to use
+     */
+    pub fn build_rom(&self, song: u8) -> Vec<u8> {
+        let header = &self.header;
+        let load_address = header.load_address as usize;
+        let rom_len = (load_address + self.data.len()).max(0x8000).next_power_of_two();
+        let mut rom = vec![0; rom_len];
+        rom[load_address..load_address + self.data.len()].copy_from_slice(&self.data);
+
+        //RST vectors jump to the same offset from the load address
+        for rst in (0x00..0x40).step_by(8) {
+            let [low, high] = (header.load_address + rst as u16).to_le_bytes();
+            rom[rst..rst + 3].copy_from_slice(&[0xC3, low, high]);
+        }
+
+        //CALL play, RETI
+        let [play_low, play_high] = header.play_address.to_le_bytes();
+        let call_play = [0xCD, play_low, play_high, 0xD9];
+        rom[0x40..0x44].copy_from_slice(&call_play);   //VBlank
+        rom[0x48] = 0xD9;                               //STAT
+        rom[0x50..0x54].copy_from_slice(&call_play);   //Timer
+        rom[0x58] = 0xD9;                               //Serial
+        rom[0x60] = 0xD9;                               //Joypad
+
+        //NOP, JP DRIVER_START
+        let [driver_low, driver_high] = DRIVER_START.to_le_bytes();
+        rom[0x100..0x104].copy_from_slice(&[0x00, 0xC3, driver_low, driver_high]);
+
+        let title = header.title.bytes().filter(|byte| byte.is_ascii_graphic() || *byte == b' ').take(TITLE_LEN).collect::<Vec<u8>>();
+        rom[TITLE_START..TITLE_START + title.len()].copy_from_slice(&title);
+        rom[CARTRIDGE_TYPE] = if rom_len == 0x8000 { 0x08 } else { 0x1A };
+        rom[ROM_SIZE] = (rom_len / 0x8000).trailing_zeros() as u8;
+        rom[RAM_SIZE] = 0x02;
+
+        let [sp_low, sp_high] = header.stack_pointer.to_le_bytes();
+        let [init_low, init_high] = header.init_address.to_le_bytes();
+        let interrupt_enable = if self.uses_timer() { 0x04 } else { 0x01 };
+        let driver = [
+            0xF3,                                   //DI
+            0x31, sp_low, sp_high,                  //LD SP, stack_pointer
+            0xAF,                                   //XOR A
+            0xE0, 0xFF,                             //LDH (IE), A
+            0x3E, header.timer_modulo,              //LD A, timer_modulo
+            0xE0, 0x06,                             //LDH (TMA), A
+            0x3E, header.timer_control,             //LD A, timer_control
+            0xE0, 0x07,                             //LDH (TAC), A
+            0x3E, 0x0A,                             //LD A, 0x0A
+            0xEA, 0x00, 0x00,                       //LD (0x0000), A to enable the MBC5's RAM
+            0x3E, song,                             //LD A, song
+            0xCD, init_low, init_high,              //CALL init
+            0x3E, interrupt_enable,                 //LD A, interrupt_enable
+            0xE0, 0xFF,                             //LDH (IE), A
+            0xAF,                                   //XOR A
+            0xE0, 0x0F,                             //LDH (IF), A
+            0xFB,                                   //EI
+            0x76,                                   //HALT
+            0x18, 0xFD,                             //JR back to the HALT
+        ];
+        rom[DRIVER_START as usize..DRIVER_START as usize + driver.len()].copy_from_slice(&driver);
+
+        rom[HEADER_CHECKSUM] = header::compute_header_checksum(&rom);
+        return rom;
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::game_cartridge::GameCartridge;
+
+    /**
+     * A GBS with load at 0x400, init at 0x400 and play at 0x410
+     */
+    pub fn test_gbs(code: &[u8], timer_control: u8) -> Vec<u8> {
+        let mut gbs = vec![0; GBS_HEADER_LEN];
+        gbs[0..3].copy_from_slice(GBS_MAGIC);
+        gbs[0x03] = 1;
+        gbs[0x04] = 3;
+        gbs[0x05] = 1;
+        gbs[0x06..0x08].copy_from_slice(&0x400u16.to_le_bytes());
+        gbs[0x08..0x0A].copy_from_slice(&0x400u16.to_le_bytes());
+        gbs[0x0A..0x0C].copy_from_slice(&0x410u16.to_le_bytes());
+        gbs[0x0C..0x0E].copy_from_slice(&0xFFFEu16.to_le_bytes());
+        gbs[0x0F] = timer_control;
+        gbs[0x10..0x14].copy_from_slice(b"Test");
+        gbs.extend_from_slice(code);
+        return gbs;
+    }
+
+    #[test]
+    fn parses_header() {
+        let gbs = GbsFile::parse(&test_gbs(&[0xC9], 0x04)).unwrap();
+        assert_eq!(gbs.header.num_of_songs, 3);
+        assert_eq!(gbs.header.play_address, 0x410);
+        assert_eq!(gbs.header.title, "Test");
+        assert!(gbs.uses_timer());
+
+        assert!(matches!(GbsFile::parse(b"NOTAGBS"), Err(Error::InvalidGbs(_))));
+        let mut bad_load_address = test_gbs(&[0xC9], 0);
+        bad_load_address[0x06..0x08].copy_from_slice(&0x100u16.to_le_bytes());
+        assert!(matches!(GbsFile::parse(&bad_load_address), Err(Error::InvalidGbs(_))));
+    }
+
+    #[test]
+    fn builds_a_valid_cartridge() {
+        let gbs = GbsFile::parse(&test_gbs(&[0xC9], 0)).unwrap();
+        let rom = gbs.build_rom(2);
+        assert_eq!(rom.len(), 0x8000);
+        assert_eq!(rom[CARTRIDGE_TYPE], 0x08);
+        assert_eq!(rom[0x400], 0xC9);
+        assert_eq!(&rom[0x40..0x44], &[0xCD, 0x10, 0x04, 0xD9]);
+        assert!(GameCartridge::load_from_bytes(&rom).is_ok());
+
+        //Too big for 32KB, so it needs banking
+        let gbs = GbsFile::parse(&test_gbs(&vec![0; 0x9000], 0)).unwrap();
+        let rom = gbs.build_rom(0);
+        assert_eq!(rom.len(), 0x10000);
+        assert_eq!(rom[CARTRIDGE_TYPE], 0x1A);
+        assert!(GameCartridge::load_from_bytes(&rom).is_ok());
+    }
+}
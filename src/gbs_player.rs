@@ -0,0 +1,107 @@
+use std::{cell::RefCell, fs, path::Path, rc::Rc};
+
+use bintboy::audio::AudioOutput;
+use bintboy::gameboy::{Gameboy, SoundChannel, WIDTH, HEIGHT, CYCLES_PER_FRAME, CLK_CYCLES_PER_SECOND};
+use bintboy::gbs::GbsFile;
+use minifb::{Key, KeyRepeat, Window};
+
+use crate::{create_wav, handle_channel_keys, initialize_window, open_audio_sink, record_frame, FramePacer};
+
+/**
+ * Entry point of the play-gbs subcommand. Songs count from 1 like in the GBS
+ * header. With an output file the song gets rendered to it as fast as
+ * possible, otherwise it plays through the speakers and Left/Right switch
+ * songs. The channel keys and I work the same as in the emulator
+ */
+pub fn play_gbs(gbs_file_path: &Path, song: Option<u8>, output: Option<&Path>, seconds: u32, no_audio: bool) {
+    let gbs = match fs::read(gbs_file_path).map_err(bintboy::Error::from).and_then(|bytes| GbsFile::parse(&bytes)) {
+        Ok(gbs) => gbs,
+        Err(e) => {
+            eprintln!("Unable to load {}: {e}", gbs_file_path.display());
+            std::process::exit(1);
+        },
+    };
+
+    let num_of_songs = gbs.header.num_of_songs;
+    let song = song.unwrap_or(gbs.header.first_song).clamp(1, num_of_songs);
+    for line in [&gbs.header.title, &gbs.header.author, &gbs.header.copyright] {
+        if !line.is_empty() {
+            println!("{line}");
+        }
+    }
+    println!("{num_of_songs} songs, calling play off {}", if gbs.uses_timer() { "the timer" } else { "vblank" });
+
+    match output {
+        Some(output) => render_song(&gbs, song, output, seconds),
+        None => play_songs(&gbs, song, no_audio),
+    }
+}
+
+/**
+ * A fresh Game Boy with the cartridge for song plugged in
+ */
+fn load_song(gbs: &GbsFile, song: u8) -> Gameboy {
+    let mut gameboy = Gameboy::new();
+    gameboy.load_rom(&gbs.build_rom(song - 1)).expect("GBS cartridges are always valid");
+    println!("Playing song {song}/{}", gbs.header.num_of_songs);
+    return gameboy;
+}
+
+fn render_song(gbs: &GbsFile, song: u8, output: &Path, seconds: u32) {
+    let mut gameboy = load_song(gbs, song);
+    let mut wav = Some(create_wav(output));
+    gameboy.set_audio_callback(Some(Box::new(move |left, right| record_frame(&mut wav, left, right))));
+
+    let frames = seconds as u64 * CLK_CYCLES_PER_SECOND as u64 / CYCLES_PER_FRAME as u64;
+    for _ in 0..frames {
+        gameboy.run_frame();
+    }
+}
+
+fn play_songs(gbs: &GbsFile, mut song: u8, no_audio: bool) {
+    let audio = Rc::new(RefCell::new(AudioOutput::new(open_audio_sink(no_audio))));
+    let mut window = initialize_window();
+    let mut pacer = FramePacer::new();
+    let mut gameboy = start_song(gbs, song, &audio, &mut window);
+    let mut inspect = false;
+
+    while window.is_open() && !window.is_key_down(Key::Escape) {
+        let mut next_song = song;
+        if window.is_key_pressed(Key::Right, KeyRepeat::No) && song < gbs.header.num_of_songs {
+            next_song += 1;
+        }
+        if window.is_key_pressed(Key::Left, KeyRepeat::No) && song > 1 {
+            next_song -= 1;
+        }
+        if next_song != song {
+            song = next_song;
+            let previous = std::mem::replace(&mut gameboy, start_song(gbs, song, &audio, &mut window));
+
+            //Muting and soloing stick around between songs
+            for channel in SoundChannel::ALL {
+                gameboy.set_channel_muted(channel, previous.is_channel_muted(channel));
+            }
+            gameboy.set_solo_channel(previous.solo_channel());
+        }
+
+        handle_channel_keys(&window, &mut gameboy);
+        if window.is_key_pressed(Key::I, KeyRepeat::No) {
+            inspect = !inspect;
+        }
+
+        gameboy.run_frame();
+        if inspect {
+            println!("{}", gameboy.debug_state());
+        }
+        window.update_with_buffer(gameboy.frame_buffer(), WIDTH, HEIGHT).unwrap();
+        pacer.wait(&audio.borrow(), true);
+    }
+}
+
+fn start_song(gbs: &GbsFile, song: u8, audio: &Rc<RefCell<AudioOutput>>, window: &mut Window) -> Gameboy {
+    let mut gameboy = load_song(gbs, song);
+    let audio = audio.clone();
+    gameboy.set_audio_callback(Some(Box::new(move |left, right| audio.borrow_mut().push_sample(left, right))));
+    window.set_title(&format!("{} - song {song}/{}", gbs.header.title, gbs.header.num_of_songs));
+    return gameboy;
+}
@@ -0,0 +1,493 @@
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use crate::debugger::{Breakpoint, Debugger, StopReason};
+use crate::error::Error;
+use crate::gameboy::{Gameboy, Registers, Watchpoint, WatchKind};
+
+const INTERRUPT_BYTE: u8 = 0x03;    //What gdb sends when Ctrl-C gets pressed while we're running
+const SIGINT: u8 = 2;
+const SIGTRAP: u8 = 5;
+
+//gdb's z80 register layout: AF BC DE HL SP PC IX IY AF' BC' DE' HL' IR, all
+//16 bits and little endian. The SM83 only has the first 6, the rest read as 0
+const REGISTER_COUNT: usize = 13;
+const SM83_REGISTER_COUNT: usize = 6;
+
+const SOFTWARE_BREAKPOINT: u8 = 0;
+const HARDWARE_BREAKPOINT: u8 = 1;
+const WRITE_WATCHPOINT: u8 = 2;
+const READ_WATCHPOINT: u8 = 3;
+const ACCESS_WATCHPOINT: u8 = 4;
+
+const BANKED_ROM_START: u16 = 0x4000;
+const BANKED_ROM_END: u32 = 0x8000;
+
+/**
+ * A GDB remote serial protocol server, so gdb (or anything else that talks
+ * to gdbserver) can debug the game. Breakpoints, stepping and watchpoints
+ * all go through a Debugger, so the game runs exactly like it would without
+ * gdb. Addresses above 0xFFFF are banked, with the ROM bank in the upper bits
+ * like GBDK puts them. Breakpoints and reading 0x4000 - 0x7FFF go by that
+ * bank. Anything else banked is an error, instead of going to whatever bank
+ * happens to be switched in
+ */
+pub struct GdbStub {
+    stream: Option<TcpStream>,      //None once gdb detaches or goes away
+    debugger: Debugger,
+    interrupt: Arc<AtomicBool>,
+    running: bool,                  //gdb said to continue and hasn't heard that we stopped yet
+    watchpoints: Vec<(u8, Watchpoint)>,     //Which Z packet type set each one. Access watchpoints are a read and a write
+}
+
+impl GdbStub {
+    /**
+     * Waiting for gdb to connect, like with target remote localhost:port
+     */
+    pub fn listen(port: u16) -> Result<Self, Error> {
+        let (stream, _) = TcpListener::bind(("127.0.0.1", port))?.accept()?;
+        stream.set_nodelay(true)?;
+        return Ok(Self::new(stream));
+    }
+
+    /**
+     * The game starts out stopped, for gdb to look at before anything runs
+     */
+    pub fn new(stream: TcpStream) -> Self {
+        let debugger = Debugger::new();
+        let interrupt = debugger.interrupt_flag();
+        Self {
+            stream: Some(stream),
+            debugger,
+            interrupt,
+            running: false,
+            watchpoints: Vec::new(),
+        }
+    }
+
+    pub fn is_connected(&self) -> bool {
+        return self.stream.is_some();
+    }
+
+    /**
+     * Running a frame if gdb has us running, otherwise waiting for it to say
+     * to. Returns false once gdb kills the game
+     */
+    pub fn run_frame(&mut self, gameboy: &mut Gameboy) -> bool {
+        if self.stream.is_none() {
+            gameboy.run_frame();
+            return true;
+        }
+
+        let result = self.serve(gameboy);
+        return match result {
+            Ok(keep_going) => keep_going,
+            Err(e) => {
+                eprintln!("gdb disconnected: {e}");
+                self.detach(gameboy);
+                true
+            },
+        };
+    }
+
+    fn serve(&mut self, gameboy: &mut Gameboy) -> io::Result<bool> {
+        if self.running {
+            self.check_for_interrupt()?;
+            let Some(reason) = self.debugger.run_frame(gameboy) else { return Ok(true) };
+            self.running = false;
+            self.send_packet(&stop_reply(&reason, &self.watchpoints))?;
+        }
+
+        loop {
+            let Some(packet) = self.read_packet()? else { continue };     //Already stopped, so Ctrl-C has nothing to do
+            let (command, args) = packet.split_at(packet.len().min(1));
+            let reply = match command {
+                "?" => format!("S{SIGTRAP:02x}"),
+                "g" => read_registers(gameboy),
+                "G" => write_registers(gameboy, args),
+                "p" => read_register(gameboy, args),
+                "P" => write_register(gameboy, args),
+                "m" => read_memory(gameboy, args),
+                "M" => write_memory(gameboy, args),
+                "Z" | "z" => self.set_point(gameboy, command == "Z", args),
+                "c" => {
+                    resume_at(gameboy, args);
+                    self.running = true;
+                    return Ok(true);
+                },
+                "s" => {
+                    resume_at(gameboy, args);
+                    let reason = self.debugger.step_into(gameboy);
+                    stop_reply(&reason, &self.watchpoints)
+                },
+                "k" => return Ok(false),
+                "D" => {
+                    self.send_packet("OK")?;
+                    self.detach(gameboy);
+                    return Ok(true);
+                },
+                "H" => "OK".to_string(),
+                "q" if args.starts_with("Supported") => "PacketSize=1000".to_string(),
+                "q" if args == "Attached" => "1".to_string(),
+                _ => String::new(),     //An empty reply tells gdb we don't do that
+            };
+            self.send_packet(&reply)?;
+        }
+    }
+
+    /**
+     * Taking out everything gdb put in, and letting the game run on its own
+     */
+    fn detach(&mut self, gameboy: &mut Gameboy) {
+        for (_, watchpoint) in self.watchpoints.drain(..) {
+            gameboy.remove_watchpoint(watchpoint);
+        }
+        self.stream = None;
+        self.running = false;
+    }
+
+    /**
+     * Z and z packets, which look like type,address,kind. Kind is the length
+     * for watchpoints and gets ignored for breakpoints
+     */
+    fn set_point(&mut self, gameboy: &mut Gameboy, insert: bool, args: &str) -> String {
+        let mut fields = args.split(',');
+        let (Some(Ok(point_type)), Some(Ok(address)), Some(Ok(len))) = (
+            fields.next().map(str::parse::<u8>),
+            fields.next().map(|address| u32::from_str_radix(address, 16)),
+            fields.next().map(|len| u16::from_str_radix(len, 16)),
+        ) else {
+            return "E01".to_string();
+        };
+
+        let start = address as u16;
+        let end = start.saturating_add(len.max(1) - 1);
+        let watchpoints = match point_type {
+            SOFTWARE_BREAKPOINT | HARDWARE_BREAKPOINT => {
+                let bank = (address > 0xFFFF).then_some((address >> 16) as usize);
+                let breakpoint = Breakpoint { address: start, bank };
+                if insert {
+                    self.debugger.add_breakpoint(breakpoint);
+                } else {
+                    self.debugger.remove_breakpoint(breakpoint);
+                }
+                return "OK".to_string();
+            },
+            WRITE_WATCHPOINT => vec![WatchKind::Write],
+            READ_WATCHPOINT => vec![WatchKind::Read],
+            ACCESS_WATCHPOINT => vec![WatchKind::Read, WatchKind::Write],
+            _ => return String::new(),
+        };
+
+        //Watchpoints can't tell banks apart
+        if address > 0xFFFF {
+            return "E01".to_string();
+        }
+
+        for kind in watchpoints {
+            let watchpoint = Watchpoint { start, end, kind };
+            if insert {
+                gameboy.add_watchpoint(watchpoint);
+                self.watchpoints.push((point_type, watchpoint));
+                continue;
+            }
+
+            //Only taking it out of the Game Boy once no other Z packet needs it
+            if let Some(index) = self.watchpoints.iter().position(|&set| set == (point_type, watchpoint)) {
+                self.watchpoints.remove(index);
+            }
+            if self.watchpoints.iter().all(|&(_, other)| other != watchpoint) {
+                gameboy.remove_watchpoint(watchpoint);
+            }
+        }
+        return "OK".to_string();
+    }
+
+    /**
+     * Looking for Ctrl-C from gdb without waiting on it
+     */
+    fn check_for_interrupt(&mut self) -> io::Result<()> {
+        let stream = self.stream.as_mut().expect("only served while connected");
+        stream.set_nonblocking(true)?;
+        let mut buffer = [0; 64];
+        let read = stream.read(&mut buffer);
+        stream.set_nonblocking(false)?;
+
+        match read {
+            Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(len) if buffer[..len].contains(&INTERRUPT_BYTE) => self.interrupt.store(true, Ordering::Relaxed),
+            Ok(_) => (),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => (),
+            Err(e) => return Err(e),
+        }
+        return Ok(());
+    }
+
+    /**
+     * The next $packet#checksum, which gets acked with + (or - if the
+     * checksum is off, so gdb sends it again). Anything between packets is
+     * acks from gdb, except Ctrl-C which comes back as None
+     */
+    fn read_packet(&mut self) -> io::Result<Option<String>> {
+        let stream = self.stream.as_mut().expect("only served while connected");
+        loop {
+            match read_byte(stream)? {
+                b'$' => (),
+                INTERRUPT_BYTE => return Ok(None),
+                _ => continue,
+            }
+
+            let mut packet = Vec::new();
+            loop {
+                match read_byte(stream)? {
+                    b'#' => break,
+                    byte => packet.push(byte),
+                }
+            }
+            let checksum = [read_byte(stream)?, read_byte(stream)?];
+            let expected = std::str::from_utf8(&checksum).ok().and_then(|checksum| u8::from_str_radix(checksum, 16).ok());
+            if expected != Some(checksum_of(&packet)) {
+                stream.write_all(b"-")?;
+                continue;
+            }
+
+            stream.write_all(b"+")?;
+            return Ok(Some(String::from_utf8_lossy(&packet).into_owned()));
+        }
+    }
+
+    fn send_packet(&mut self, data: &str) -> io::Result<()> {
+        let stream = self.stream.as_mut().expect("only served while connected");
+        write!(stream, "${data}#{:02x}", checksum_of(data.as_bytes()))?;
+        return stream.flush();
+    }
+}
+
+fn read_byte(stream: &mut TcpStream) -> io::Result<u8> {
+    let mut byte = [0];
+    stream.read_exact(&mut byte)?;
+    return Ok(byte[0]);
+}
+
+fn checksum_of(data: &[u8]) -> u8 {
+    return data.iter().fold(0, |sum, &byte| sum.wrapping_add(byte));
+}
+
+/**
+ * Telling gdb why we stopped. Watchpoints say which address set them off
+ */
+fn stop_reply(reason: &StopReason, watchpoints: &[(u8, Watchpoint)]) -> String {
+    return match reason {
+        StopReason::Interrupted => format!("S{SIGINT:02x}"),
+        StopReason::Breakpoint(_) | StopReason::Stepped => format!("S{SIGTRAP:02x}"),
+        StopReason::Watchpoint(hits) => {
+            let hit = hits[0];
+            let point_type = watchpoints.iter().find(|&&(_, watchpoint)| watchpoint == hit.watchpoint).map(|&(point_type, _)| point_type);
+            let name = match (point_type, hit.watchpoint.kind) {
+                (Some(ACCESS_WATCHPOINT), _) => "awatch",
+                (_, WatchKind::Read) => "rwatch",
+                _ => "watch",
+            };
+            format!("T{SIGTRAP:02x}{name}:{:04x};", hit.address)
+        },
+    };
+}
+
+fn sm83_registers(registers: &Registers) -> [u16; SM83_REGISTER_COUNT] {
+    return [registers.af(), registers.bc(), registers.de(), registers.hl(), registers.sp, registers.pc];
+}
+
+fn set_sm83_register(registers: &mut Registers, index: usize, value: u16) {
+    let [high, low] = value.to_be_bytes();
+    match index {
+        0 => (registers.a, registers.f) = (high, low),
+        1 => (registers.b, registers.c) = (high, low),
+        2 => (registers.d, registers.e) = (high, low),
+        3 => (registers.h, registers.l) = (high, low),
+        4 => registers.sp = value,
+        5 => registers.pc = value,
+        _ => (),    //The z80 registers we don't have
+    }
+}
+
+fn read_registers(gameboy: &Gameboy) -> String {
+    let mut values = sm83_registers(&gameboy.registers()).to_vec();
+    values.resize(REGISTER_COUNT, 0);
+    return values.iter().map(|value| to_hex(&value.to_le_bytes())).collect();
+}
+
+fn write_registers(gameboy: &mut Gameboy, args: &str) -> String {
+    let Some(bytes) = from_hex(args) else { return "E01".to_string() };
+    let mut registers = gameboy.registers();
+    for (index, value) in bytes.chunks_exact(2).take(SM83_REGISTER_COUNT).enumerate() {
+        set_sm83_register(&mut registers, index, u16::from_le_bytes([value[0], value[1]]));
+    }
+    gameboy.set_registers(registers);
+    return "OK".to_string();
+}
+
+fn read_register(gameboy: &Gameboy, args: &str) -> String {
+    return match usize::from_str_radix(args, 16) {
+        Ok(index) if index < SM83_REGISTER_COUNT => to_hex(&sm83_registers(&gameboy.registers())[index].to_le_bytes()),
+        Ok(index) if index < REGISTER_COUNT => "0000".to_string(),
+        _ => "E01".to_string(),
+    };
+}
+
+fn write_register(gameboy: &mut Gameboy, args: &str) -> String {
+    let Some((index, value)) = args.split_once('=') else { return "E01".to_string() };
+    let (Ok(index), Some(value)) = (usize::from_str_radix(index, 16), from_hex(value)) else { return "E01".to_string() };
+    if index >= REGISTER_COUNT || value.len() != 2 {
+        return "E01".to_string();
+    }
+
+    let mut registers = gameboy.registers();
+    set_sm83_register(&mut registers, index, u16::from_le_bytes([value[0], value[1]]));
+    gameboy.set_registers(registers);
+    return "OK".to_string();
+}
+
+/**
+ * m packets, address,length. Banked addresses have to be in 0x4000 - 0x7FFF
+ * and get read straight out of that ROM bank
+ */
+fn read_memory(gameboy: &Gameboy, args: &str) -> String {
+    let Some((address, len)) = parse_range(args) else { return "E01".to_string() };
+    let start = address as u16;
+    if address <= 0xFFFF {
+        let bytes: Vec<u8> = (0..len).map(|i| gameboy.read_byte(start.wrapping_add(i))).collect();
+        return to_hex(&bytes);
+    }
+
+    if start < BANKED_ROM_START || start as u32 + len as u32 > BANKED_ROM_END {
+        return "E01".to_string();
+    }
+    let bank = (address >> 16) as usize;
+    return match (0..len).map(|i| gameboy.read_rom_bank(bank, start + i)).collect::<Option<Vec<u8>>>() {
+        Some(bytes) => to_hex(&bytes),
+        None => "E01".to_string(),
+    };
+}
+
+/**
+ * M packets, address,length:bytes. ROM can't be written to, so there's
+ * nothing a banked address could mean
+ */
+fn write_memory(gameboy: &mut Gameboy, args: &str) -> String {
+    let Some((range, data)) = args.split_once(':') else { return "E01".to_string() };
+    let (Some((address, len)), Some(bytes)) = (parse_range(range), from_hex(data)) else { return "E01".to_string() };
+    if address > 0xFFFF || bytes.len() != len as usize {
+        return "E01".to_string();
+    }
+
+    for (i, &byte) in bytes.iter().enumerate() {
+        gameboy.write_byte((address as u16).wrapping_add(i as u16), byte);
+    }
+    return "OK".to_string();
+}
+
+/**
+ * c and s packets can say where to pick back up from
+ */
+fn resume_at(gameboy: &mut Gameboy, args: &str) {
+    if let Ok(address) = u32::from_str_radix(args, 16) {
+        let mut registers = gameboy.registers();
+        registers.pc = address as u16;
+        gameboy.set_registers(registers);
+    }
+}
+
+fn parse_range(args: &str) -> Option<(u32, u16)> {
+    let (address, len) = args.split_once(',')?;
+    let address = u32::from_str_radix(address, 16).ok()?;
+    let len = u16::from_str_radix(len, 16).ok()?;
+    return Some((address, len));
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    return bytes.iter().map(|byte| format!("{byte:02x}")).collect();
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return None;
+    }
+    return (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok()).collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use crate::game_cartridge::header::{self, test_rom, CARTRIDGE_TYPE, HEADER_CHECKSUM, ROM_SIZE};
+
+    /**
+     * Plays gdb's side of the conversation: sends each packet and collects
+     * the reply to it. The stream gets handed back so it stays open until the
+     * stub is done with it
+     */
+    fn run_gdb(mut stream: TcpStream, packets: &[&str]) -> (Vec<String>, TcpStream) {
+        let mut replies = Vec::new();
+        for packet in packets {
+            write!(stream, "${packet}#{:02x}", checksum_of(packet.as_bytes())).unwrap();
+            if *packet == "k" {
+                break;
+            }
+
+            let mut reply = Vec::new();
+            loop {
+                match read_byte(&mut stream).unwrap() {
+                    b'$' => (),
+                    _ => continue,
+                }
+                loop {
+                    match read_byte(&mut stream).unwrap() {
+                        b'#' => break,
+                        byte => reply.push(byte),
+                    }
+                }
+                read_byte(&mut stream).unwrap();
+                read_byte(&mut stream).unwrap();
+                break;
+            }
+            stream.write_all(b"+").unwrap();
+            replies.push(String::from_utf8(reply).unwrap());
+        }
+        return (replies, stream);
+    }
+
+    #[test]
+    fn debugs_over_the_remote_protocol() {
+        //LD A, 0x42; LD (0xC000), A; NOP; JR to itself
+        let mut rom = test_rom(&[0x3E, 0x42, 0xEA, 0x00, 0xC0, 0x00, 0x00, 0x00, 0x18, 0xFE]);
+        //An MBC5 with 4 banks, with 0xAB at the start of bank 2
+        rom.resize(0x10000, 0);
+        rom[CARTRIDGE_TYPE] = 0x19;
+        rom[ROM_SIZE] = 0x01;
+        rom[0x8000] = 0xAB;
+        rom[HEADER_CHECKSUM] = header::compute_header_checksum(&rom);
+        let mut gameboy = Gameboy::new();
+        gameboy.load_rom(&rom).unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let gdb = thread::spawn(move || run_gdb(TcpStream::connect(address).unwrap(), &[
+            "?", "s", "p5", "Z2,c000,1", "c", "z2,c000,1", "Z0,157,1", "c", "m0156,3", "Mc001,2:beef", "mc000,3", "P5=0001", "g",
+            "m24000,1", "m4000,1", "m44000,1", "m27fff,2", "M24000,1:00", "Z2,2c000,1", "k",
+        ]));
+        let mut stub = GdbStub::new(listener.accept().unwrap().0);
+        while stub.run_frame(&mut gameboy) {}
+
+        let (replies, _stream) = gdb.join().unwrap();
+        assert_eq!(replies[..3], ["S05", "S05", "0101"]);
+        assert_eq!(replies[3..6], ["OK", "T05watch:c000;", "OK"]);
+        assert_eq!(replies[6..8], ["OK", "S05"]);
+        assert_eq!(replies[8..11], ["000018", "OK", "42beef"]);
+        assert_eq!(replies[11..13], ["OK", "b0421300d8004d01feff00010000000000000000000000000000"]);
+        assert_eq!(replies[13..16], ["ab", "00", "E01"]);
+        assert_eq!(replies[16..], ["E01", "E01", "E01"]);
+        assert_eq!(gameboy.registers().pc, 0x100);
+    }
+}
@@ -0,0 +1,157 @@
+use std::fs;
+use colored::Colorize;
+
+use bintboy::game_cartridge::{self, header::{self, CartridgeHeader, CgbSupport, Destination}};
+
+/**
+ * Everything `bintboy info` reports about a ROM
+ */
+struct RomInfo {
+    header: CartridgeHeader,
+    file_size: usize,
+    mbc: Option<&'static str>,    //None if we don't support the mapper
+    computed_header_checksum: u8,
+    computed_global_checksum: u16,
+    valid_logo: bool,
+}
+
+/**
+ * Entry point of the info subcommand. Prints out the cartridge header of the
+ * ROM and checks it the same way the boot rom would
+ */
+pub fn print_rom_info(rom_file_path: &str, json: bool) {
+    let rom = match fs::read(rom_file_path) {
+        Ok(rom) => rom,
+        Err(e) => {
+            eprintln!("Error reading {rom_file_path}: {e}");
+            std::process::exit(1);
+        },
+    };
+
+    let header = match CartridgeHeader::parse(&rom) {
+        Ok(header) => header,
+        Err(e) => {
+            eprintln!("Error reading the cartridge header of {rom_file_path}: {e}");
+            std::process::exit(1);
+        },
+    };
+
+    let info = RomInfo {
+        mbc: game_cartridge::select_mbc(&header, &rom).map(|mbc| mbc.name()),
+        file_size: rom.len(),
+        computed_header_checksum: header::compute_header_checksum(&rom),
+        computed_global_checksum: header::compute_global_checksum(&rom),
+        valid_logo: header::has_valid_logo(&rom),
+        header,
+    };
+
+    if json {
+        println!("{}", info_to_json(&info));
+    } else {
+        print_info_text(&info);
+    }
+}
+
+fn print_info_text(info: &RomInfo) {
+    let header = &info.header;
+    println!("Title:             {}", header.title);
+    if let Some(manufacturer_code) = &header.manufacturer_code {
+        println!("Manufacturer code: {manufacturer_code}");
+    }
+    println!("Licensee code:     {}", licensee_code(header));
+    println!("CGB support:       {}", cgb_support(header.cgb_support));
+    println!("SGB support:       {}", if header.sgb_support { "Yes" } else { "No" });
+    println!("Cartridge type:    {} (0x{:02X})", header.cartridge_type, header.cartridge_type.code);
+    println!("MBC:               {}", info.mbc.unwrap_or("Unsupported"));
+    println!("ROM size:          {} KiB ({} banks)", header.rom_size.num_of_banks() as usize * 16, header.rom_size.num_of_banks());
+    println!("File size:         {} KiB", info.file_size / 1024);
+    println!("RAM size:          {} KiB ({} banks)", header.ram_size.num_of_banks() as usize * 8, header.ram_size.num_of_banks());
+    println!("Destination:       {}", destination(header.destination));
+    println!("Version:           {}", header.version);
+    println!("Nintendo logo:     {}", status(info.valid_logo));
+    println!("Header checksum:   0x{:02X} (computed 0x{:02X}) {}", header.header_checksum, info.computed_header_checksum,
+                                                                     status(header.header_checksum == info.computed_header_checksum));
+    println!("Global checksum:   0x{:04X} (computed 0x{:04X}) {}", header.global_checksum, info.computed_global_checksum,
+                                                                     status(header.global_checksum == info.computed_global_checksum));
+}
+
+fn status(ok: bool) -> colored::ColoredString {
+    if ok {
+        return "OK".green();
+    }
+    return "MISMATCH".red();
+}
+
+/**
+ * Building the JSON by hand since it's the only place we'd need serde
+ */
+fn info_to_json(info: &RomInfo) -> String {
+    let header = &info.header;
+    let fields = [
+        ("title", json_string(&header.title)),
+        ("manufacturer_code", header.manufacturer_code.as_deref().map_or("null".to_string(), json_string)),
+        ("licensee_code", json_string(&licensee_code(header))),
+        ("cgb_support", json_string(cgb_support(header.cgb_support))),
+        ("sgb_support", header.sgb_support.to_string()),
+        ("cartridge_type", json_string(&header.cartridge_type.to_string())),
+        ("cartridge_type_code", header.cartridge_type.code.to_string()),
+        ("mbc", info.mbc.map_or("null".to_string(), json_string)),
+        ("rom_size", (header.rom_size.num_of_banks() as usize * 0x4000).to_string()),
+        ("rom_banks", header.rom_size.num_of_banks().to_string()),
+        ("file_size", info.file_size.to_string()),
+        ("ram_size", (header.ram_size.num_of_banks() as usize * 0x2000).to_string()),
+        ("ram_banks", header.ram_size.num_of_banks().to_string()),
+        ("destination", json_string(&destination(header.destination))),
+        ("version", header.version.to_string()),
+        ("logo_valid", info.valid_logo.to_string()),
+        ("header_checksum", header.header_checksum.to_string()),
+        ("computed_header_checksum", info.computed_header_checksum.to_string()),
+        ("header_checksum_valid", (header.header_checksum == info.computed_header_checksum).to_string()),
+        ("global_checksum", header.global_checksum.to_string()),
+        ("computed_global_checksum", info.computed_global_checksum.to_string()),
+        ("global_checksum_valid", (header.global_checksum == info.computed_global_checksum).to_string()),
+    ];
+
+    let body = fields.iter()
+                     .map(|(key, value)| format!("  \"{key}\": {value}"))
+                     .collect::<Vec<String>>()
+                     .join(",\n");
+    return format!("{{\n{body}\n}}");
+}
+
+fn json_string(value: &str) -> String {
+    let mut escaped = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if c.is_control() => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    return escaped;
+}
+
+fn licensee_code(header: &CartridgeHeader) -> String {
+    return match &header.new_licensee_code {
+        Some(code) => code.clone(),
+        None => format!("{:02X}", header.old_licensee_code),
+    };
+}
+
+fn cgb_support(cgb_support: CgbSupport) -> &'static str {
+    return match cgb_support {
+        CgbSupport::None => "None",
+        CgbSupport::Enhanced => "Enhanced",
+        CgbSupport::Required => "Required",
+    };
+}
+
+fn destination(destination: Destination) -> String {
+    return match destination {
+        Destination::Japan => "Japan".to_string(),
+        Destination::Overseas => "Overseas".to_string(),
+        Destination::Unknown(code) => format!("Unknown (0x{code:02X})"),
+    };
+}
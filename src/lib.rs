@@ -0,0 +1,22 @@
+pub mod gameboy;
+pub mod game_cartridge;
+pub mod error;
+pub mod save_state;
+pub mod rewind;
+pub mod audio;
+pub mod wav;
+pub mod gbs;
+pub mod link_cable;
+pub mod linked_pair;
+pub mod printer;
+pub mod disassembler;
+pub mod debugger;
+pub mod gdb_stub;
+
+pub use crate::gameboy::{Gameboy, Buttons};
+pub use crate::error::Error;
+
+pub enum TestStatus {
+    Failed,
+    Pass
+}
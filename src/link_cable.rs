@@ -0,0 +1,328 @@
+use std::collections::VecDeque;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+
+use crate::error::Error;
+use crate::gameboy::{LinkCable, LinkMessage};
+
+const LINK_MAGIC: &[u8; 8] = b"BINTLINK";
+const LINK_VERSION: u8 = 1;
+
+//How far apart the two Game Boys are allowed to drift in clk cycles. Everything
+//sent over the cable shows up exactly this much later on the other side, which
+//is well within the 4096 clk cycles a transfer takes
+const QUANTUM_CLK_CYCLES: u16 = 1024;
+
+const START_TAG: u8 = 0;
+const REPLY_TAG: u8 = 1;
+const DONE_TAG: u8 = 2;
+
+trait Stream: Read + Write {}
+impl<T: Read + Write> Stream for T {}
+
+/**
+ * A link cable to a Game Boy in another process, over TCP or a Unix domain
+ * socket. Both sides run in lockstep: after every QUANTUM_CLK_CYCLES they
+ * swap what they sent during it, and neither goes on until it hears from the
+ * other. Messages carry when in the quantum they were sent, so they arrive
+ * at the same emulated time no matter how slow the socket is
+ */
+pub struct SocketLink {
+    stream: Option<Box<dyn Stream>>,        //None once the other side hangs up
+    clk_cycles: u16,                        //How far into the current quantum we are
+    sending: Vec<u8>,
+    arriving: VecDeque<(u16, LinkMessage)>,
+}
+
+impl SocketLink {
+    /**
+     * Waiting for the other Game Boy to connect. Addresses with a / in them
+     * are Unix domain sockets, anything else is a TCP address like
+     * 127.0.0.1:8765
+     */
+    pub fn listen(address: &str) -> Result<Self, Error> {
+        #[cfg(unix)]
+        if address.contains('/') {
+            let (listener, lock) = bind_unix_socket(address)?;
+            let (stream, _) = listener.accept()?;
+            //Nobody else can connect from here on, so the socket file can go
+            std::fs::remove_file(address)?;
+            std::fs::remove_file(lock_path(address))?;
+            drop(lock);
+            return Self::new(stream);
+        }
+
+        let (stream, _) = TcpListener::bind(address)?.accept()?;
+        stream.set_nodelay(true)?;
+        return Self::new(stream);
+    }
+
+    pub fn connect(address: &str) -> Result<Self, Error> {
+        #[cfg(unix)]
+        if address.contains('/') {
+            return Self::new(UnixStream::connect(address)?);
+        }
+
+        let stream = TcpStream::connect(address)?;
+        stream.set_nodelay(true)?;
+        return Self::new(stream);
+    }
+
+    /**
+     * Making sure there's another bintboy on the other end of the stream
+     */
+    pub fn new(mut stream: impl Read + Write + 'static) -> Result<Self, Error> {
+        stream.write_all(LINK_MAGIC)?;
+        stream.write_all(&[LINK_VERSION])?;
+        stream.flush()?;
+
+        let mut handshake = [0; 9];
+        stream.read_exact(&mut handshake)?;
+        if &handshake[0..8] != LINK_MAGIC || handshake[8] != LINK_VERSION {
+            return Err(Error::LinkHandshake);
+        }
+
+        Ok(Self {
+            stream: Some(Box::new(stream)),
+            clk_cycles: 0,
+            sending: Vec::new(),
+            arriving: VecDeque::new(),
+        })
+    }
+
+    pub fn is_connected(&self) -> bool {
+        return self.stream.is_some();
+    }
+
+    /**
+     * Sending everything from the quantum that just ended and waiting for the
+     * other side to do the same. Each message is when it was sent, a tag and
+     * the byte that went with it
+     */
+    fn sync(&mut self) -> Result<(), Error> {
+        let stream = self.stream.as_mut().expect("only synced while connected");
+        stream.write_all(&(self.sending.len() as u16).to_le_bytes())?;
+        stream.write_all(&self.sending)?;
+        stream.flush()?;
+        self.sending.clear();
+
+        let mut len = [0; 2];
+        stream.read_exact(&mut len)?;
+        let mut received = vec![0; u16::from_le_bytes(len) as usize];
+        stream.read_exact(&mut received)?;
+
+        for message in received.chunks(4) {
+            let [low, high, tag, byte] = message.try_into().map_err(|_| Error::LinkProtocol)?;
+            let message = match tag {
+                START_TAG => LinkMessage::Start(byte),
+                REPLY_TAG => LinkMessage::Reply(byte),
+                DONE_TAG => LinkMessage::Done,
+                _ => return Err(Error::LinkProtocol),
+            };
+            self.arriving.push_back((u16::from_le_bytes([low, high]), message));
+        }
+        return Ok(());
+    }
+}
+
+/**
+ * Binding doesn't work while a socket file is still there, which happens if
+ * the last listener never got a connection. Listeners hold a lock on
+ * <address>.lock until they're connected, so if we can take it any socket
+ * file there is left over and gets removed. Connecting to find out would get
+ * picked up as the link partner of a listener that's still there. The lock
+ * lasts until the returned file is dropped
+ */
+#[cfg(unix)]
+fn bind_unix_socket(address: &str) -> Result<(UnixListener, std::fs::File), Error> {
+    let lock = std::fs::File::create(lock_path(address))?;
+    if lock.try_lock().is_err() {
+        return Err(std::io::Error::new(std::io::ErrorKind::AddrInUse, format!("another bintboy is already listening on {address}")).into());
+    }
+
+    if let Err(e) = std::fs::remove_file(address) {
+        if e.kind() != std::io::ErrorKind::NotFound {
+            return Err(e.into());
+        }
+    }
+    return Ok((UnixListener::bind(address)?, lock));
+}
+
+#[cfg(unix)]
+fn lock_path(address: &str) -> String {
+    return format!("{address}.lock");
+}
+
+impl LinkCable for SocketLink {
+    fn cycle(&mut self, outgoing: &mut Vec<LinkMessage>, incoming: &mut Vec<LinkMessage>) {
+        if self.stream.is_none() {
+            outgoing.clear();
+            return;
+        }
+
+        let [low, high] = self.clk_cycles.to_le_bytes();
+        for message in outgoing.drain(..) {
+            let (tag, byte) = match message {
+                LinkMessage::Start(byte) => (START_TAG, byte),
+                LinkMessage::Reply(byte) => (REPLY_TAG, byte),
+                LinkMessage::Done => (DONE_TAG, 0),
+            };
+            self.sending.extend_from_slice(&[low, high, tag, byte]);
+        }
+
+        while let Some(&(sent_at, message)) = self.arriving.front() {
+            if sent_at > self.clk_cycles {
+                break;
+            }
+            incoming.push(message);
+            self.arriving.pop_front();
+        }
+
+        self.clk_cycles += 1;
+        if self.clk_cycles == QUANTUM_CLK_CYCLES {
+            self.clk_cycles = 0;
+            if let Err(e) = self.sync() {
+                eprintln!("Link cable disconnected: {e}");
+                self.stream = None;
+                self.arriving.clear();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+    use crate::Gameboy;
+    use crate::game_cartridge::header::test_rom;
+
+    /**
+     * A cartridge that puts byte in SB, starts a transfer with sc and then
+     * copies SB to 0xC000 once the serial interrupt flag goes up
+     */
+    fn transfer_rom(byte: u8, sc: u8) -> Vec<u8> {
+        return test_rom(&[
+            0x3E, byte, 0xE0, 0x01,         //LD A, byte; LDH (SB), A
+            0x3E, sc, 0xE0, 0x02,           //LD A, sc; LDH (SC), A
+            0xF0, 0x0F, 0xE6, 0x08,         //LDH A, (IF); AND 0x08
+            0x28, 0xFA,                     //JR Z, back to the LDH
+            0xF0, 0x01, 0xEA, 0x00, 0xC0,   //LDH A, (SB); LD (0xC000), A
+            0x18, 0xFE,                     //JR to itself
+        ]);
+    }
+
+    /**
+     * Running a Game Boy on the other end of stream. Returns what it got sent
+     */
+    fn run_linked(stream: TcpStream, rom: Vec<u8>) -> u8 {
+        let mut gameboy = Gameboy::new();
+        gameboy.load_rom(&rom).unwrap();
+        gameboy.set_link_cable(Some(Box::new(SocketLink::new(stream).unwrap())));
+        for _ in 0..10 {
+            gameboy.run_frame();
+        }
+        return gameboy.read_byte(0xC000);
+    }
+
+    #[test]
+    fn swaps_bytes_between_processes() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let slave = thread::spawn(move || run_linked(TcpStream::connect(address).unwrap(), transfer_rom(0x99, 0x80)));
+        let (stream, _) = listener.accept().unwrap();
+        let master_got = run_linked(stream, transfer_rom(0x42, 0x81));
+
+        assert_eq!(master_got, 0x99);
+        assert_eq!(slave.join().unwrap(), 0x42);
+    }
+
+    #[test]
+    fn rejects_things_that_arent_bintboy() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let other_side = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(b"HTTP/1.1 ").unwrap();
+            //Hanging up before our handshake arrives would make sending it fail instead
+            let mut handshake = [0; 9];
+            stream.read_exact(&mut handshake).unwrap();
+        });
+        assert!(matches!(SocketLink::connect(&address.to_string()), Err(Error::LinkHandshake)));
+        other_side.join().unwrap();
+    }
+
+    #[test]
+    fn rejects_garbled_messages() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let other_side = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(LINK_MAGIC).unwrap();
+            stream.write_all(&[LINK_VERSION]).unwrap();
+            stream.write_all(&[4, 0, 0, 0, 7, 0x42]).unwrap();     //A message with a tag that doesn't exist
+            let mut sent = [0; 11];
+            stream.read_exact(&mut sent).unwrap();
+        });
+        let mut link = SocketLink::connect(&address.to_string()).unwrap();
+        assert!(matches!(link.sync(), Err(Error::LinkProtocol)));
+        other_side.join().unwrap();
+    }
+
+    /**
+     * Connecting to a Unix socket as soon as something is listening on it
+     */
+    #[cfg(unix)]
+    fn connect_once_listening(address: &str) -> thread::JoinHandle<()> {
+        let address = address.to_string();
+        return thread::spawn(move || {
+            while SocketLink::connect(&address).is_err() {
+                thread::sleep(std::time::Duration::from_millis(10));
+            }
+        });
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn listens_on_the_same_unix_socket_again() {
+        let path = std::env::temp_dir().join(format!("bintboy-link-{}.sock", std::process::id()));
+        let address = path.to_str().unwrap();
+        for _ in 0..2 {
+            let other_side = connect_once_listening(address);
+            assert!(SocketLink::listen(address).unwrap().is_connected());
+            other_side.join().unwrap();
+            assert!(!path.exists());
+        }
+
+        //One left behind by a listener that never got a connection
+        drop(UnixListener::bind(&path).unwrap());
+        let other_side = connect_once_listening(address);
+        assert!(SocketLink::listen(address).unwrap().is_connected());
+        other_side.join().unwrap();
+        assert!(!path.exists());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn leaves_other_listeners_alone() {
+        let path = std::env::temp_dir().join(format!("bintboy-link-busy-{}.sock", std::process::id()));
+        let address = path.to_str().unwrap().to_string();
+        let first_listener = {
+            let address = address.clone();
+            thread::spawn(move || SocketLink::listen(&address).unwrap().is_connected())
+        };
+        while !path.exists() {
+            thread::sleep(std::time::Duration::from_millis(10));
+        }
+
+        //Its socket isn't left over, so it keeps it and gets whoever connects next
+        let Err(Error::Io(e)) = SocketLink::listen(&address) else { panic!("the socket is already taken") };
+        assert_eq!(e.kind(), std::io::ErrorKind::AddrInUse);
+        assert!(SocketLink::connect(&address).unwrap().is_connected());
+        assert!(first_listener.join().unwrap());
+        assert!(!path.exists() && !std::path::Path::new(&lock_path(&address)).exists());
+    }
+}
@@ -0,0 +1,168 @@
+use std::{cell::RefCell, rc::Rc};
+
+use crate::gameboy::{Gameboy, LinkCable, LinkMessage, CYCLES_PER_FRAME};
+
+/**
+ * Both ends of a DirectLink. Everything sent is stamped with the clk cycle
+ * it was sent on, so it gets delivered exactly one clk cycle later no matter
+ * which Game Boy runs first
+ */
+#[derive(Default)]
+struct Wire {
+    clk_cycles: [u64; 2],
+    in_flight: [Vec<(u64, LinkMessage)>; 2],    //Indexed by who it's going to
+}
+
+/**
+ * One end of a link cable that goes straight into another Game Boy in the
+ * same process
+ */
+struct DirectLink {
+    wire: Rc<RefCell<Wire>>,
+    end: usize,
+}
+
+impl LinkCable for DirectLink {
+    fn cycle(&mut self, outgoing: &mut Vec<LinkMessage>, incoming: &mut Vec<LinkMessage>) {
+        let mut wire = self.wire.borrow_mut();
+        let now = wire.clk_cycles[self.end];
+
+        let in_flight = &mut wire.in_flight[self.end];
+        in_flight.retain(|&(sent_at, message)| {
+            if sent_at < now {
+                incoming.push(message);
+                return false;
+            }
+            true
+        });
+
+        let other_end = 1 - self.end;
+        wire.in_flight[other_end].extend(outgoing.drain(..).map(|message| (now, message)));
+        wire.clk_cycles[self.end] += 1;
+    }
+}
+
+/**
+ * Two Game Boys with a link cable between them, run one clk cycle at a time
+ * each so neither ever gets ahead. Nothing depends on the host, so running
+ * the same inputs always gives the same result
+ */
+pub struct LinkedPair {
+    first: Gameboy,
+    second: Gameboy,
+}
+
+impl LinkedPair {
+    pub fn new(mut first: Gameboy, mut second: Gameboy) -> Self {
+        let wire = Rc::new(RefCell::new(Wire::default()));
+        first.set_link_cable(Some(Box::new(DirectLink { wire: wire.clone(), end: 0 })));
+        second.set_link_cable(Some(Box::new(DirectLink { wire, end: 1 })));
+        Self { first, second }
+    }
+
+    pub fn first(&self) -> &Gameboy {
+        return &self.first;
+    }
+
+    pub fn first_mut(&mut self) -> &mut Gameboy {
+        return &mut self.first;
+    }
+
+    pub fn second(&self) -> &Gameboy {
+        return &self.second;
+    }
+
+    pub fn second_mut(&mut self) -> &mut Gameboy {
+        return &mut self.second;
+    }
+
+    /**
+     * Runs both Game Boys until the first one has drawn a whole frame. The
+     * second one's frames don't line up with it, so it just gets the same
+     * number of clk cycles
+     */
+    pub fn run_frame(&mut self) {
+        self.first.frame_completed = false;
+        let mut clk_cycles = 0;
+        while !self.first.frame_completed {
+            self.first.cycle();
+            self.second.cycle();
+
+            clk_cycles += 1;
+            if clk_cycles >= CYCLES_PER_FRAME && !self.first.is_lcd_on() {
+                break;
+            }
+        }
+        self.first.finish_frame();
+        self.second.finish_frame();
+    }
+
+    /**
+     * Unplugging the link cable and getting the Game Boys back
+     */
+    pub fn split(mut self) -> (Gameboy, Gameboy) {
+        self.first.set_link_cable(None);
+        self.second.set_link_cable(None);
+        return (self.first, self.second);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Buttons;
+
+    //Where Tetris keeps what screen it's on and who's running the link cable
+    const TETRIS_GAME_STATE: u16 = 0xFFE1;
+    const TETRIS_LINK_ROLE: u16 = 0xFFCB;
+    const TITLE_SCREEN: u8 = 0x07;
+    const TWO_PLAYER_SETUP: u8 = 0x2B;
+    const MASTER: u8 = 0x29;
+    const SLAVE: u8 = 0x55;
+
+    /**
+     * A Tetris sitting on the title screen. Only one gets run through the
+     * copyright screen and the other is loaded from it, which is twice as fast
+     */
+    fn tetris_pair() -> LinkedPair {
+        let mut first = Gameboy::new();
+        first.initialize("test_roms/games/Tetris(World)(Rev1).gb").unwrap();
+        let mut frames = 0;
+        while first.read_byte(TETRIS_GAME_STATE) != TITLE_SCREEN {
+            first.set_buttons(Buttons { start: frames % 10 == 0, ..Buttons::default() });
+            first.run_frame();
+            frames += 1;
+        }
+        first.set_buttons(Buttons::default());
+
+        let mut second = Gameboy::new();
+        second.initialize("test_roms/games/Tetris(World)(Rev1).gb").unwrap();
+        second.load_state(&first.save_state()).unwrap();
+        return LinkedPair::new(first, second);
+    }
+
+    fn press(pair: &mut LinkedPair, buttons: Buttons) {
+        pair.first_mut().set_buttons(buttons);
+        for _ in 0..5 {
+            pair.run_frame();
+        }
+        pair.first_mut().set_buttons(Buttons::default());
+        for _ in 0..20 {
+            pair.run_frame();
+        }
+    }
+
+    #[test]
+    fn tetris_two_player_handshake() {
+        let mut pair = tetris_pair();
+
+        //Picking 2 PLAYER on the first one makes it the master
+        press(&mut pair, Buttons { right: true, ..Buttons::default() });
+        press(&mut pair, Buttons { start: true, ..Buttons::default() });
+
+        assert_eq!(pair.first().read_byte(TETRIS_LINK_ROLE), MASTER);
+        assert_eq!(pair.second().read_byte(TETRIS_LINK_ROLE), SLAVE);
+        assert_eq!(pair.first().read_byte(TETRIS_GAME_STATE), TWO_PLAYER_SETUP);
+        assert_eq!(pair.second().read_byte(TETRIS_GAME_STATE), TWO_PLAYER_SETUP);
+    }
+}
@@ -1,13 +1,175 @@
-mod gameboy;
-mod game_cartridge;
+mod info;
+mod gbs_player;
+mod disasm;
+mod repl;
+mod trace_diff;
 
-use crate::gameboy::Gameboy;
-use clap::Parser;
+use bintboy::gameboy::{Gameboy, Buttons, SoundChannel, WIDTH, HEIGHT, CYCLES_PER_FRAME, CLK_CYCLES_PER_SECOND, AUDIO_SAMPLE_RATE};
+use bintboy::audio::{AudioOutput, AudioSink, NullSink};
+#[cfg(feature = "audio")]
+use bintboy::audio::CpalSink;
+use bintboy::rewind::RewindConfig;
+use bintboy::link_cable::SocketLink;
+use bintboy::printer::{Printer, DEFAULT_PRINTER_PALETTE};
+use bintboy::gdb_stub::GdbStub;
+use repl::Repl;
+use bintboy::wav::WavWriter;
+use bintboy::TestStatus;
+use clap::{Parser, Subcommand};
+use minifb::{Key, KeyRepeat, Scale, ScaleMode, Window, WindowOptions};
+use std::{cell::RefCell, fs, io::{self, Write}, path::{Path, PathBuf}, rc::Rc, thread, time::{Duration, Instant}};
+
+//F1 - F9 load save state slots 1 - 9. Holding shift saves to them instead
+const SAVE_STATE_SLOT_KEYS: [Key; 9] = [Key::F1, Key::F2, Key::F3, Key::F4, Key::F5, Key::F6, Key::F7, Key::F8, Key::F9];
+//1 - 4 mute pulse 1, pulse 2, wave and noise. Holding shift solos them instead
+const CHANNEL_KEYS: [Key; 4] = [Key::Key1, Key::Key2, Key::Key3, Key::Key4];
+const AUDIO_LATENCY_MS: usize = 50;     //How much audio we try to keep queued up ahead of the speakers
+#[cfg(test)]
+const BLARGG_TIMEOUT_SECONDS: u64 = 60;
+const STEM_NAMES: [&str; 4] = ["pulse1", "pulse2", "wave", "noise"];
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Cli {
-    path: String,
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[arg(required = true)]
+    path: Option<String>,
+
+    /// Drive the MBC3 real time clock from emulated cycles instead of the host clock
+    #[arg(long)]
+    rtc_cycles: bool,
+
+    /// How many rewind snapshots to keep. Hold R to rewind, 0 turns it off
+    #[arg(long, default_value_t = RewindConfig::default().depth)]
+    rewind_depth: usize,
+
+    /// Take a rewind snapshot every this many frames
+    #[arg(long, default_value_t = RewindConfig::default().interval, value_parser = clap::value_parser!(u32).range(1..))]
+    rewind_interval: u32,
+
+    /// Most memory in MiB the rewind snapshots can use
+    #[arg(long, default_value_t = RewindConfig::default().memory_budget / (1024 * 1024))]
+    rewind_memory: usize,
+
+    /// Don't open an audio device. Frames get paced by the host clock instead
+    #[arg(long)]
+    no_audio: bool,
+
+    /// Record the audio to a WAV file
+    #[arg(long, value_name = "FILE")]
+    record_audio: Option<PathBuf>,
+
+    /// Also record each sound channel to its own WAV file next to the recording, like out.pulse1.wav
+    #[arg(long, requires = "record_audio")]
+    record_stems: bool,
+
+    /// Run without a window or audio device, as fast as possible
+    #[arg(long, requires = "frames")]
+    headless: bool,
+
+    /// How many frames to run in headless mode
+    #[arg(long, requires = "headless")]
+    frames: Option<u32>,
+
+    /// Print whatever the game sends over the serial port, like the results of test roms
+    #[arg(long)]
+    serial_stdout: bool,
+
+    /// Write whatever the game sends over the serial port to a file
+    #[arg(long, value_name = "FILE")]
+    serial_file: Option<PathBuf>,
+
+    /// Print the PPU and APU state after every frame. I turns this on and off while running
+    #[arg(long)]
+    inspect: bool,
+
+    /// Log the registers before every instruction to FILE in gameboy-doctor's format. LY always reads as 0x90 while tracing
+    #[arg(long, value_name = "FILE")]
+    trace: Option<PathBuf>,
+
+    /// Wait for another bintboy to plug into our link cable, on a TCP address like 127.0.0.1:8765 or a Unix socket path
+    #[arg(long, value_name = "ADDRESS")]
+    link_listen: Option<String>,
+
+    /// Plug our link cable into another bintboy that's listening on ADDRESS
+    #[arg(long, value_name = "ADDRESS", conflicts_with = "link_listen")]
+    link_connect: Option<String>,
+
+    /// Plug a Game Boy Printer into the link port. Every sheet it prints gets saved to DIR as a PNG
+    #[arg(long, value_name = "DIR", conflicts_with_all = ["link_listen", "link_connect"])]
+    printer: Option<PathBuf>,
+
+    /// Colors for the printer's 4 shades from white to black, like FFFFFF,C0C0C0,606060,000000
+    #[arg(long, value_name = "COLORS", value_parser = parse_palette, requires = "printer")]
+    printer_palette: Option<[u32; 4]>,
+
+    /// Start at a debugger prompt. Ctrl-C gets back to it while the game runs
+    #[arg(long)]
+    debug: bool,
+
+    /// Wait for gdb to connect on localhost PORT, like with target remote localhost:PORT
+    #[arg(long, value_name = "PORT", conflicts_with = "debug")]
+    gdb: Option<u16>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Print the cartridge header of a ROM and check its checksums
+    Info {
+        path: String,
+
+        /// Print the header as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Disassemble a ROM bank or address range
+    Disasm {
+        path: String,
+
+        /// Which ROM bank to disassemble, or to use for addresses from 4000 up. Defaults to 0 on its own, 1 with a range
+        #[arg(long)]
+        bank: Option<usize>,
+
+        /// First address to disassemble, in hex
+        #[arg(long, value_parser = disasm::parse_address)]
+        start: Option<u16>,
+
+        /// Last address to disassemble, in hex
+        #[arg(long, value_parser = disasm::parse_address)]
+        end: Option<u16>,
+    },
+
+    /// Play a GBS music file. Left and Right switch songs
+    PlayGbs {
+        path: PathBuf,
+
+        /// Which song to play, counting from 1. Defaults to the one the file says to start on
+        #[arg(long, value_parser = clap::value_parser!(u8).range(1..))]
+        track: Option<u8>,
+
+        /// Render the song to a WAV file instead of playing it
+        #[arg(long, value_name = "FILE")]
+        output: Option<PathBuf>,
+
+        /// How many seconds of the song to render
+        #[arg(long, default_value_t = 180, requires = "output")]
+        seconds: u32,
+
+        /// Don't open an audio device
+        #[arg(long, conflicts_with = "output")]
+        no_audio: bool,
+    },
+
+    /// Find the first line where our --trace log differs from a reference log, like gameboy-doctor's
+    TraceDiff {
+        ours: PathBuf,
+
+        reference: PathBuf,
+    },
 }
 
 /**
@@ -21,7 +183,6 @@ struct Cli {
  * -Remember to remove all the unused linting (#![allow(dead_code)])
  * -NOT IMPLEMENTING THE MBC ENTIRELY
  * -Not making mbc1m its own struct. Because right now we have to do a comparison any time you write 
- * -MBC3's timer isn't really implemented
  */
 
 /**
@@ -29,27 +190,461 @@ struct Cli {
  */
 fn main() {
     let args = Cli::parse();
-    start_emulator(&args.path);
+    match args.command {
+        Some(Command::Info { path, json }) => info::print_rom_info(&path, json),
+        Some(Command::Disasm { path, bank, start, end }) => disasm::print_disassembly(&path, bank, start, end),
+        Some(Command::PlayGbs { path, track, output, seconds, no_audio }) => gbs_player::play_gbs(&path, track, output.as_deref(), seconds, no_audio),
+        Some(Command::TraceDiff { ours, reference }) => trace_diff::print_trace_diff(&ours, &reference),
+        None => start_emulator(args.path.as_deref().expect("clap requires a path"), &args),
+    }
 }
 
 /* This is the entry point for the Game Boy emulator */
-fn start_emulator(rom_file_path: &str) {
+fn start_emulator(rom_file_path: &str, args: &Cli) {
     let mut gameboy = Gameboy::new();
-    gameboy.initialize(rom_file_path);
-    gameboy.run();
+    if let Err(e) = gameboy.initialize(rom_file_path) {
+        eprintln!("Unable to load {rom_file_path}: {e}");
+        std::process::exit(1);
+    }
+    if args.rtc_cycles {
+        gameboy.use_emulated_rtc();
+    }
+    if args.rewind_depth > 0 {
+        gameboy.enable_rewind(RewindConfig {
+            interval: args.rewind_interval,
+            depth: args.rewind_depth,
+            memory_budget: args.rewind_memory * 1024 * 1024,
+        });
+    }
+
+    connect_serial(&mut gameboy, args);
+    connect_link(&mut gameboy, args);
+    connect_printer(&mut gameboy, args);
+    connect_trace(&mut gameboy, args);
+
+    let mut debugger = open_debugger(args);
+    if let Some(Debugger::Prompt(repl)) = &mut debugger {
+        println!("Type help for a list of debugger commands");
+        if !repl.prompt(&mut gameboy) {
+            gameboy.flush_save();
+            return;
+        }
+    }
+
+    if args.headless {
+        connect_audio(&mut gameboy, None, args);
+        for _ in 0..args.frames.expect("clap requires frames in headless mode") {
+            if !run_frame(&mut gameboy, &mut debugger) {
+                break;
+            }
+            if args.inspect {
+                println!("{}", gameboy.debug_state());
+            }
+        }
+        gameboy.flush_save();
+        return;
+    }
+
+    let audio = Rc::new(RefCell::new(AudioOutput::new(open_audio_sink(args.no_audio))));
+    connect_audio(&mut gameboy, Some(audio.clone()), args);
+
+    let mut window = initialize_window();
+    let mut pacer = FramePacer::new();
+    let mut toggle_2x_speed = false;
+    let mut inspect = args.inspect;
+
+    while window.is_open() && !window.is_key_down(Key::Escape) {
+        if window.is_key_pressed(Key::F, KeyRepeat::No) {
+            toggle_2x_speed = !toggle_2x_speed;
+            println!("Toggle2x is: {}", toggle_2x_speed);
+            audio.borrow_mut().set_speed(if toggle_2x_speed { 2 } else { 1 });
+        }
+
+        if window.is_key_pressed(Key::I, KeyRepeat::No) {
+            inspect = !inspect;
+        }
+        handle_channel_keys(&window, &mut gameboy);
+
+        for (slot, key) in SAVE_STATE_SLOT_KEYS.iter().enumerate() {
+            if window.is_key_pressed(*key, KeyRepeat::No) {
+                let shift_held = window.is_key_down(Key::LeftShift) || window.is_key_down(Key::RightShift);
+                handle_save_state_slot(&mut gameboy, rom_file_path, slot + 1, shift_held);
+            }
+        }
+
+        //Stepping backwards a snapshot each frame while R is held
+        let rewinding = window.is_key_down(Key::R);
+        if rewinding {
+            gameboy.rewind_frame();
+        } else {
+            gameboy.set_buttons(read_buttons(&window));
+            if !run_frame(&mut gameboy, &mut debugger) || (toggle_2x_speed && !run_frame(&mut gameboy, &mut debugger)) {
+                break;
+            }
+        }
+        if inspect {
+            println!("{}", gameboy.debug_state());
+        }
+        window.update_with_buffer(gameboy.frame_buffer(), WIDTH, HEIGHT).unwrap();
+        pacer.wait(&audio.borrow(), !rewinding);
+    }
+
+    gameboy.flush_save();
+}
+
+/**
+ * Whatever the game is getting debugged with
+ */
+enum Debugger {
+    Prompt(Repl),
+    Gdb(GdbStub),
+}
+
+fn open_debugger(args: &Cli) -> Option<Debugger> {
+    if args.debug {
+        return Some(Debugger::Prompt(Repl::new()));
+    }
+    let port = args.gdb?;
+    println!("Waiting for gdb on localhost:{port}");
+    return match GdbStub::listen(port) {
+        Ok(stub) => Some(Debugger::Gdb(stub)),
+        Err(e) => {
+            eprintln!("Unable to wait for gdb on port {port}: {e}");
+            std::process::exit(1);
+        },
+    };
+}
+
+/**
+ * Running a frame, through the debugger if there is one. Returns false if
+ * the user quit from the debugger
+ */
+fn run_frame(gameboy: &mut Gameboy, debugger: &mut Option<Debugger>) -> bool {
+    return match debugger {
+        Some(Debugger::Prompt(repl)) => repl.run_frame(gameboy),
+        Some(Debugger::Gdb(stub)) => stub.run_frame(gameboy),
+        None => {
+            gameboy.run_frame();
+            true
+        },
+    };
+}
+
+/**
+ * Save states for a slot live next to the rom as <rom>.ss<slot>
+ */
+fn handle_save_state_slot(gameboy: &mut Gameboy, rom_file_path: &str, slot: usize, save: bool) {
+    let state_path = PathBuf::from(rom_file_path).with_extension(format!("ss{slot}"));
+
+    if save {
+        match fs::write(&state_path, gameboy.save_state()) {
+            Ok(()) => println!("Saved state to slot {slot}"),
+            Err(e) => eprintln!("Unable to write save state {}: {e}", state_path.display()),
+        }
+    } else {
+        match fs::read(&state_path).map_err(bintboy::Error::from).and_then(|state| gameboy.load_state(&state)) {
+            Ok(()) => println!("Loaded state from slot {slot}"),
+            Err(e) => eprintln!("Unable to load save state {}: {e}", state_path.display()),
+        }
+    }
+}
+
+/**
+ * Muting a channel again unmutes it, and soloing the soloed channel goes back
+ * to playing everything that isn't muted
+ */
+fn handle_channel_keys(window: &Window, gameboy: &mut Gameboy) {
+    let shift_held = window.is_key_down(Key::LeftShift) || window.is_key_down(Key::RightShift);
+    for (channel, key) in SoundChannel::ALL.into_iter().zip(CHANNEL_KEYS) {
+        if !window.is_key_pressed(key, KeyRepeat::No) {
+            continue;
+        }
+
+        if shift_held {
+            let solo = if gameboy.solo_channel() == Some(channel) { None } else { Some(channel) };
+            gameboy.set_solo_channel(solo);
+            match solo {
+                Some(channel) => println!("Soloing {}", channel.name()),
+                None => println!("Playing all unmuted channels"),
+            }
+        } else {
+            let muted = !gameboy.is_channel_muted(channel);
+            gameboy.set_channel_muted(channel, muted);
+            println!("{} is {}", channel.name(), if muted { "muted" } else { "unmuted" });
+        }
+    }
+}
+
+/**
+ * Passing along every byte sent over the serial port as soon as it's sent, so
+ * nothing gets lost if the game crashes partway through
+ */
+fn connect_serial(gameboy: &mut Gameboy, args: &Cli) {
+    let mut file = args.serial_file.as_ref().map(|path| match fs::File::create(path) {
+        Ok(file) => file,
+        Err(e) => {
+            eprintln!("Unable to create {}: {e}", path.display());
+            std::process::exit(1);
+        },
+    });
+    if file.is_none() && !args.serial_stdout {
+        return;
+    }
+
+    let serial_stdout = args.serial_stdout;
+    gameboy.set_serial_callback(Some(Box::new(move |byte| {
+        if serial_stdout {
+            let mut stdout = io::stdout();
+            let _ = stdout.write_all(&[byte]).and_then(|_| stdout.flush());
+        }
+        if let Some(Err(e)) = file.as_mut().map(|file| file.write_all(&[byte])) {
+            eprintln!("Unable to write serial output: {e}");
+            file = None;
+        }
+    })));
+}
+
+/**
+ * Writing a trace line for every instruction. The file gets flushed when the
+ * Game Boy goes away
+ */
+fn connect_trace(gameboy: &mut Gameboy, args: &Cli) {
+    let Some(path) = &args.trace else { return };
+    let mut file = match fs::File::create(path) {
+        Ok(file) => Some(io::BufWriter::new(file)),
+        Err(e) => {
+            eprintln!("Unable to create {}: {e}", path.display());
+            std::process::exit(1);
+        },
+    };
+
+    gameboy.set_trace_callback(Some(Box::new(move |line| {
+        if let Some(Err(e)) = file.as_mut().map(|file| writeln!(file, "{line}")) {
+            eprintln!("Unable to write trace: {e}");
+            file = None;
+        }
+    })));
+}
+
+/**
+ * Plugging the link cable into another bintboy. Listening blocks until the
+ * other side shows up
+ */
+fn connect_link(gameboy: &mut Gameboy, args: &Cli) {
+    let link = match (&args.link_listen, &args.link_connect) {
+        (Some(address), _) => {
+            println!("Waiting for the other Game Boy on {address}");
+            SocketLink::listen(address)
+        },
+        (None, Some(address)) => SocketLink::connect(address),
+        (None, None) => return,
+    };
+
+    match link {
+        Ok(link) => {
+            println!("Link cable connected");
+            gameboy.set_link_cable(Some(Box::new(link)));
+        },
+        Err(e) => {
+            eprintln!("Unable to connect the link cable: {e}");
+            std::process::exit(1);
+        },
+    }
+}
+
+/**
+ * Sheets are saved as print-<n>.png, starting after the highest one that's
+ * already in the directory so earlier prints don't get overwritten
+ */
+fn connect_printer(gameboy: &mut Gameboy, args: &Cli) {
+    let Some(dir) = args.printer.clone() else { return };
+    if let Err(e) = fs::create_dir_all(&dir) {
+        eprintln!("Unable to create {}: {e}", dir.display());
+        std::process::exit(1);
+    }
+
+    let palette = args.printer_palette.unwrap_or(DEFAULT_PRINTER_PALETTE);
+    let mut sheet = fs::read_dir(&dir).into_iter().flatten().flatten()
+        .filter_map(|entry| entry.file_name().to_str()?.strip_prefix("print-")?.strip_suffix(".png")?.parse::<u32>().ok())
+        .max()
+        .unwrap_or(0);
+    gameboy.set_link_cable(Some(Box::new(Printer::new(Box::new(move |printout| {
+        sheet += 1;
+        let path = dir.join(format!("print-{sheet}.png"));
+        match printout.write_png(&path, &palette) {
+            Ok(()) => println!("Printed {}", path.display()),
+            Err(e) => eprintln!("Unable to save {}: {e}", path.display()),
+        }
+    })))));
+}
+
+fn parse_palette(colors: &str) -> Result<[u32; 4], String> {
+    let colors = colors.split(',')
+        .map(|color| u32::from_str_radix(color.trim().trim_start_matches('#'), 16).map_err(|e| format!("{color}: {e}")))
+        .collect::<Result<Vec<u32>, String>>()?;
+    return colors.try_into().map_err(|colors: Vec<u32>| format!("expected 4 colors, got {}", colors.len()));
+}
+
+/**
+ * Hooking the Game Boy's audio up to the speakers (if there are any) and to
+ * the recordings (if we're making any)
+ */
+fn connect_audio(gameboy: &mut Gameboy, audio: Option<Rc<RefCell<AudioOutput>>>, args: &Cli) {
+    let mut mix_wav = args.record_audio.as_deref().map(create_wav);
+    gameboy.set_audio_callback(Some(Box::new(move |left, right| {
+        if let Some(audio) = &audio {
+            audio.borrow_mut().push_sample(left, right);
+        }
+        record_frame(&mut mix_wav, left, right);
+    })));
+
+    if let (Some(path), true) = (&args.record_audio, args.record_stems) {
+        let mut stem_wavs = STEM_NAMES.map(|name| Some(create_wav(&path.with_extension(format!("{name}.wav")))));
+        gameboy.set_channel_audio_callback(Some(Box::new(move |channels| {
+            for (wav, (left, right)) in stem_wavs.iter_mut().zip(channels) {
+                record_frame(wav, left, right);
+            }
+        })));
+    }
+}
+
+fn create_wav(path: &Path) -> WavWriter {
+    return match WavWriter::create(path, AUDIO_SAMPLE_RATE) {
+        Ok(wav) => wav,
+        Err(e) => {
+            eprintln!("Unable to create {}: {e}", path.display());
+            std::process::exit(1);
+        },
+    };
+}
+
+/**
+ * If writing to a recording fails we say so once and stop recording to it
+ */
+fn record_frame(wav: &mut Option<WavWriter>, left: f32, right: f32) {
+    if let Some(Err(e)) = wav.as_mut().map(|wav| wav.write_frame(left, right)) {
+        eprintln!("Unable to write audio recording: {e}");
+        *wav = None;
+    }
+}
+
+/**
+ * Plays through the host's audio device if we were built with it, otherwise
+ * the samples go nowhere
+ */
+fn open_audio_sink(no_audio: bool) -> Box<dyn AudioSink> {
+    if no_audio {
+        return Box::new(NullSink::default());
+    }
+
+    #[cfg(feature = "audio")]
+    match CpalSink::new() {
+        Ok(sink) => return Box::new(sink),
+        Err(e) => eprintln!("{e}, continuing without sound"),
+    }
+    #[cfg(not(feature = "audio"))]
+    eprintln!("Warning: bintboy was built without the audio feature, so there's no sound. Rebuild with --features audio to hear it");
+    return Box::new(NullSink::default());
+}
+
+/**
+ * Keeps the emulator running at the Game Boy's real speed of about 59.73
+ * frames a second. While audio is playing we go off how full its queue is,
+ * so we never get ahead of or fall behind the speakers. Otherwise we go off
+ * the host clock
+ */
+struct FramePacer {
+    next_frame: Instant,
+}
+
+impl FramePacer {
+    fn new() -> Self {
+        Self { next_frame: Instant::now() }
+    }
+
+    fn wait(&mut self, audio: &AudioOutput, audio_produced: bool) {
+        if audio_produced && audio.queued_frames().is_some() {
+            let target = audio.sample_rate() as usize * AUDIO_LATENCY_MS / 1000;
+            while audio.queued_frames().is_some_and(|queued| queued > target) {
+                thread::sleep(Duration::from_millis(1));
+            }
+            self.next_frame = Instant::now();
+            return;
+        }
+
+        self.next_frame += Duration::from_secs_f64(CYCLES_PER_FRAME as f64 / CLK_CYCLES_PER_SECOND as f64);
+        let now = Instant::now();
+        if self.next_frame > now {
+            thread::sleep(self.next_frame - now);
+        } else {
+            self.next_frame = now;     //Too far behind to catch up
+        }
+    }
+}
+
+fn initialize_window() -> Window {
+    let mut window = Window::new(
+        "Noise Test - Press ESC to exit",
+        WIDTH,
+        HEIGHT,
+        WindowOptions {
+            resize: false,
+            title: true,
+            scale: Scale::X4,
+            scale_mode: ScaleMode::Stretch,
+            ..WindowOptions::default()
+        },
+    )
+        .expect("Unable to create the window");
+
+    //The FramePacer takes care of this
+    window.limit_update_rate(None);
+
+    return window;
+}
+
+/**
+ * Mapping the keyboard to the Game Boy buttons. J = A, K = B, 
+ * Backspace = Select, Space = Start and WASD for the d-pad
+ */
+fn read_buttons(window: &Window) -> Buttons {
+    return Buttons {
+        a: window.is_key_down(Key::J),
+        b: window.is_key_down(Key::K),
+        select: window.is_key_down(Key::Backspace),
+        start: window.is_key_down(Key::Space),
+        up: window.is_key_down(Key::W),
+        down: window.is_key_down(Key::S),
+        left: window.is_key_down(Key::A),
+        right: window.is_key_down(Key::D),
+    };
 }
 
 /* This is the entry point for the Game Boy emulator */
 #[allow(unused)]
 fn test_start_emulator(rom_file_path: &str) -> TestStatus {
     let mut gameboy = Gameboy::new();
-    gameboy.initialize(rom_file_path);
+    if let Err(e) = gameboy.initialize_without_save(rom_file_path) {
+        println!("Unable to load {rom_file_path}: {e}");
+        return TestStatus::Failed;
+    }
     gameboy.test_run()
 }
 
-enum TestStatus {
-    Failed,
-    Pass
+/**
+ * Blargg's test roms report how they did over the serial port or in SRAM. The longest
+ * one takes under a minute of Game Boy time
+ */
+#[cfg(test)]
+fn test_blargg_rom(rom_file_path: &str) -> TestStatus {
+    let mut gameboy = Gameboy::new();
+    if let Err(e) = gameboy.initialize_without_save(rom_file_path) {
+        println!("Unable to load {rom_file_path}: {e}");
+        return TestStatus::Failed;
+    }
+    let (status, output) = gameboy.blargg_test_run(BLARGG_TIMEOUT_SECONDS * CLK_CYCLES_PER_SECOND as u64);
+    println!("{}", output.trim_end());
+    status
 }
 
 #[cfg(test)]
@@ -57,45 +652,70 @@ mod tests {
     use std::fs;
     use colored::Colorize;
 
-    use crate::{test_start_emulator, TestStatus};
+    use crate::{test_blargg_rom, test_start_emulator, TestStatus};
 
     /*
-        This will run all the blargg test ROMs individually, which are each 32KB in size. This test
-        is helpful when your Game Boy emulator can only support ROMs up to 32KB.
+        This will run all the blargg test ROMs individually, which are each 32KB in size. They print
+        their results over the serial port or in cartridge SRAM, so we go off whether that says
+        Passed or Failed.
     */
-    // #[test]
-    // fn run_individual_blargg_roms() {
-    //     let path = "test_roms/individual/";
-    //     let paths = fs::read_dir(path).unwrap();
-    //     //let mut tests = vec![];
-    //     for path in paths {
-    //         let path = path.unwrap().path();
-    //         if path.is_file() && path.extension().unwrap() == "gb" {
-    //             println!("Reading {}", &path.display().to_string());
-    //             start_emulator(&path.display().to_string());
-    //             //tests.push((path.file_name().unwrap().to_str().unwrap().to_owned(), result))
-    //         }
-    //     }
-    
-    //     // let mut num_of_failures = 0;
-    //     // for (test, status) in tests {
-    //     //     if status == "Failed" {
-    //     //         num_of_failures += 1;
-    //     //     }
-    //     //     println!("{}: {}", test, status);
-    //     // }
-    
-    //     // if num_of_failures == 0 {
-    //     //     println!("\n*** ALL TESTS PASSED ***")
-    //     // } else {
-    //     //     if num_of_failures == 1 {
-    //     //         println!("\n*** {num_of_failures} TEST FAILURE ***");
-    //     //     } else {
-    //     //         println!("\n*** {num_of_failures} TESTS FAILURES ***");
-    //     //     }
-    //     // }
-        
-    // }
+    //The DMG boot rom isn't a test, and interrupt_time needs a CGB
+    const BLARGG_SKIPPED_ROMS: [&str; 2] = ["DMG_ROM.gb", "interrupt_time.gb"];
+    //Still run and reported, but don't fail the test. modify_timing needs the read and write of
+    //instructions like INC (HL) on their own m-cycles, which the cpu doesn't do yet
+    const BLARGG_EXPECTED_FAILURES: [&str; 1] = ["03-modify_timing.gb"];
+
+    #[test]
+    fn run_individual_blargg_roms() {
+        let test_roms_path_list = vec![("test_roms/individual", "CPU INSTRS TEST"),
+                            ("test_roms/timing_tests", "TIMING TEST"),
+                            ];
+
+        let mut num_of_failures = 0;
+        let mut num_of_expected_failures = 0;
+        for (test_rom_folder_path, test_name) in test_roms_path_list {
+            let msg = format!("\n{}", test_name);
+            println!("{}", msg.bright_cyan());
+            println!("===============================");
+
+            let mut test_roms = fs::read_dir(test_rom_folder_path).unwrap()
+                .map(|rom_path| rom_path.unwrap().path())
+                .filter(|rom| rom.is_file() && rom.extension().unwrap() == "gb")
+                .filter(|rom| !BLARGG_SKIPPED_ROMS.contains(&rom.file_name().unwrap().to_str().unwrap()))
+                .collect::<Vec<_>>();
+            test_roms.sort();
+            for rom in test_roms {
+                let rom_name = rom.file_name().unwrap().to_str().unwrap().to_owned();
+                match test_blargg_rom(&rom.display().to_string()) {
+                    TestStatus::Failed if BLARGG_EXPECTED_FAILURES.contains(&rom_name.as_str()) => {
+                        let msg = format!("{}: Failed (expected)", rom_name);
+                        println!("{}", msg.yellow());
+                        num_of_expected_failures += 1;
+                    },
+                    TestStatus::Failed => {
+                        let msg = format!("{}: Failed", rom_name);
+                        println!("{}", msg.red());
+                        num_of_failures += 1;
+                    },
+                    TestStatus::Pass => {
+                        let msg = format!("{}: Passed", rom_name);
+                        println!("{}", msg.green());
+                    },
+                }
+            }
+        }
+
+        if num_of_failures == 0 && num_of_expected_failures > 0 {
+            println!("\n*** ALL TESTS PASSED EXCEPT {num_of_expected_failures} EXPECTED FAILURE(S) ***")
+        } else if num_of_failures == 0 {
+            println!("\n*** ALL TESTS PASSED ***")
+        } else if num_of_failures == 1 {
+            println!("\n*** {num_of_failures} TEST FAILURE ***");
+        } else {
+            println!("\n*** {num_of_failures} TESTS FAILURES ***");
+        }
+        assert!(num_of_failures == 0);
+    }
 
     #[test]
     fn run_individual_mooneye_roms() {
@@ -104,7 +724,9 @@ mod tests {
                             ("test_roms/acceptance/oam_dma", "OAM_DMA TEST"), 
                             ("test_roms/acceptance/timer", "TIMER TEST"), 
                             ("test_roms/acceptance/interrupts", "INTERRUPT TEST"),
+                            ("test_roms/acceptance/serial", "SERIAL TEST"),
                             ("test_roms/emulator-only/mbc1", "MBC1 TEST"),
+                            ("test_roms/emulator-only/mbc2", "MBC2 TEST"),
                             ("test_roms/emulator-only/mbc5", "MBC5 TEST"),
                             ];
         
@@ -0,0 +1,441 @@
+use std::{fs::File, io::{self, BufWriter}, path::Path};
+
+use crate::error::Error;
+use crate::gameboy::{LinkCable, LinkMessage, CLK_CYCLES_PER_SECOND};
+
+pub const PRINTER_WIDTH: usize = 160;
+//0RGB like the frame buffer, from white to black
+pub const DEFAULT_PRINTER_PALETTE: [u32; 4] = [0xFFFFFF, 0xC0C0C0, 0x606060, 0x000000];
+
+const MAGIC: [u8; 2] = [0x88, 0x33];
+const INIT_COMMAND: u8 = 0x01;
+const PRINT_COMMAND: u8 = 0x02;
+const DATA_COMMAND: u8 = 0x04;
+const STATUS_COMMAND: u8 = 0x0F;
+const ALIVE: u8 = 0x81;                         //What the printer answers with on the byte before the status
+
+const STATUS_CHECKSUM_ERROR: u8 = 0x01;
+const STATUS_BUSY: u8 = 0x02;
+const STATUS_IMAGE_FULL: u8 = 0x04;
+const STATUS_UNPROCESSED: u8 = 0x08;
+
+const TILES_PER_ROW: usize = PRINTER_WIDTH / 8;
+const BYTES_PER_TILE_ROW: usize = TILES_PER_ROW * 16;
+const MAX_IMAGE_BYTES: usize = 9 * 2 * BYTES_PER_TILE_ROW;   //The printer only has room for 9 data packets
+const LINES_PER_MARGIN: usize = 8;              //Each margin step feeds a tile's worth of blank paper
+const PRINT_CLK_CYCLES_PER_LINE: u32 = CLK_CYCLES_PER_SECOND / 64;
+const DEFAULT_PRINT_PALETTE: u8 = 0xE4;         //Some games send 0 and expect the usual one
+
+/**
+ * Gets called with every finished sheet of paper
+ */
+pub type PrintCallback = Box<dyn FnMut(Printout)>;
+
+/**
+ * Where in a packet the next byte goes. A packet is the magic bytes, a
+ * command, a compression flag, a 16 bit length, that much data and a 16 bit
+ * checksum. Then the Game Boy sends 2 more bytes so we can answer with 0x81
+ * and our status
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum PacketStage {
+    Magic(usize),
+    Command,
+    Compression,
+    Length(usize),
+    Data,
+    Checksum(usize),
+    Alive,
+    Status,
+}
+
+/**
+ * A Game Boy Printer hanging off the link cable. The Game Boy runs the clock
+ * and we just answer every byte. Images get built up from data packets and
+ * go onto the paper when a print packet comes in. Once a print feeds paper
+ * out after it, that sheet gets handed to the callback
+ */
+pub struct Printer {
+    stage: PacketStage,
+    command: u8,
+    compressed: bool,
+    length: u16,
+    data: Vec<u8>,
+    checksum: u16,                  //What we added up, the one that came with the packet goes in received_checksum
+    received_checksum: u16,
+    status: u8,
+    image: Vec<u8>,                 //Uncompressed tile data that hasn't been printed yet
+    paper: Vec<u8>,                 //Shades 0-3 for the sheet we're printing on, PRINTER_WIDTH per line
+    busy_clk_cycles: u32,           //How much longer the print head is going
+    on_print: PrintCallback,
+}
+
+/**
+ * A finished sheet of paper. Each pixel is a shade from 0 (white) to 3
+ * (black), going row by row
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Printout {
+    pub shades: Vec<u8>,
+}
+
+impl Printer {
+    pub fn new(on_print: PrintCallback) -> Self {
+        Self {
+            stage: PacketStage::Magic(0),
+            command: 0,
+            compressed: false,
+            length: 0,
+            data: Vec::new(),
+            checksum: 0,
+            received_checksum: 0,
+            status: 0,
+            image: Vec::new(),
+            paper: Vec::new(),
+            busy_clk_cycles: 0,
+            on_print,
+        }
+    }
+
+    /**
+     * One byte over the serial port. Returns what the printer shifts back
+     * out at the same time, which it had ready before seeing this byte
+     */
+    pub fn transfer(&mut self, byte: u8) -> u8 {
+        let reply = match self.stage {
+            PacketStage::Alive => ALIVE,
+            PacketStage::Status => self.status,
+            _ => 0x00,
+        };
+
+        self.stage = match self.stage {
+            PacketStage::Magic(i) if byte != MAGIC[i] => PacketStage::Magic(0),
+            PacketStage::Magic(0) => PacketStage::Magic(1),
+            PacketStage::Magic(_) => {
+                self.checksum = 0;
+                self.data.clear();
+                PacketStage::Command
+            },
+            PacketStage::Command => {
+                self.command = byte;
+                self.add_to_checksum(byte);
+                PacketStage::Compression
+            },
+            PacketStage::Compression => {
+                self.compressed = byte & 0x1 == 1;
+                self.add_to_checksum(byte);
+                PacketStage::Length(0)
+            },
+            PacketStage::Length(0) => {
+                self.length = byte as u16;
+                self.add_to_checksum(byte);
+                PacketStage::Length(1)
+            },
+            PacketStage::Length(_) => {
+                self.length |= (byte as u16) << 8;
+                self.add_to_checksum(byte);
+                if self.length == 0 { PacketStage::Checksum(0) } else { PacketStage::Data }
+            },
+            PacketStage::Data => {
+                self.data.push(byte);
+                self.add_to_checksum(byte);
+                if self.data.len() == self.length as usize { PacketStage::Checksum(0) } else { PacketStage::Data }
+            },
+            PacketStage::Checksum(0) => {
+                self.received_checksum = byte as u16;
+                PacketStage::Checksum(1)
+            },
+            PacketStage::Checksum(_) => {
+                self.received_checksum |= (byte as u16) << 8;
+                self.run_command();
+                PacketStage::Alive
+            },
+            PacketStage::Alive => PacketStage::Status,
+            PacketStage::Status => PacketStage::Magic(0),
+        };
+        return reply;
+    }
+
+    fn add_to_checksum(&mut self, byte: u8) {
+        self.checksum = self.checksum.wrapping_add(byte as u16);
+    }
+
+    fn run_command(&mut self) {
+        if self.checksum != self.received_checksum {
+            self.status |= STATUS_CHECKSUM_ERROR;
+            return;
+        }
+        self.status &= !STATUS_CHECKSUM_ERROR;
+
+        match self.command {
+            INIT_COMMAND => {
+                self.image.clear();
+                self.status = 0;
+            },
+            DATA_COMMAND => {
+                if self.compressed {
+                    decompress(&self.data, &mut self.image);
+                } else {
+                    self.image.extend_from_slice(&self.data);
+                }
+                self.image.truncate(MAX_IMAGE_BYTES);
+                if !self.image.is_empty() {
+                    self.status |= STATUS_UNPROCESSED;
+                }
+            },
+            PRINT_COMMAND if self.data.len() == 4 => self.print(self.data[0], self.data[1], self.data[2]),
+            STATUS_COMMAND => (),   //Only wants the status, which always gets sent
+            _ => (),
+        }
+    }
+
+    /**
+     * Putting the image on paper. The top nibble of margins is how much paper
+     * to feed before it and the bottom nibble how much after. Feeding paper
+     * after the image finishes the sheet. The exposure byte doesn't change
+     * anything here
+     */
+    fn print(&mut self, sheets: u8, margins: u8, palette: u8) {
+        let palette = if palette == 0 { DEFAULT_PRINT_PALETTE } else { palette };
+        let margin_before = (margins >> 4) as usize * LINES_PER_MARGIN;
+        let margin_after = (margins & 0xF) as usize * LINES_PER_MARGIN;
+
+        self.paper.resize(self.paper.len() + margin_before * PRINTER_WIDTH, 0);
+        let mut lines = 0;
+        if sheets > 0 {
+            for tile_row in self.image.chunks_exact(BYTES_PER_TILE_ROW) {
+                for line in 0..8 {
+                    for tile in tile_row.chunks_exact(16) {
+                        let low = tile[line * 2];
+                        let high = tile[line * 2 + 1];
+                        for bit in (0..8).rev() {
+                            let color_id = ((high >> bit) & 0x1) << 1 | ((low >> bit) & 0x1);
+                            self.paper.push((palette >> (color_id * 2)) & 0x3);
+                        }
+                    }
+                    lines += 1;
+                }
+            }
+        }
+        self.paper.resize(self.paper.len() + margin_after * PRINTER_WIDTH, 0);
+
+        self.image.clear();
+        self.status = STATUS_BUSY | STATUS_IMAGE_FULL;
+        self.busy_clk_cycles = (lines + margin_before + margin_after) as u32 * PRINT_CLK_CYCLES_PER_LINE;
+        if margin_after > 0 {
+            self.finish_sheet();
+        }
+    }
+
+    /**
+     * Tearing off whatever has been printed so far
+     */
+    pub fn finish_sheet(&mut self) {
+        if !self.paper.is_empty() {
+            (self.on_print)(Printout { shades: std::mem::take(&mut self.paper) });
+        }
+    }
+}
+
+impl LinkCable for Printer {
+    fn cycle(&mut self, outgoing: &mut Vec<LinkMessage>, incoming: &mut Vec<LinkMessage>) {
+        for message in outgoing.drain(..) {
+            if let LinkMessage::Start(byte) = message {
+                let reply = self.transfer(byte);
+                incoming.push(LinkMessage::Reply(reply));
+            }
+        }
+
+        if self.busy_clk_cycles > 0 {
+            self.busy_clk_cycles -= 1;
+            if self.busy_clk_cycles == 0 {
+                self.status &= !STATUS_BUSY;
+            }
+        }
+    }
+}
+
+impl Drop for Printer {
+    fn drop(&mut self) {
+        self.finish_sheet();
+    }
+}
+
+/**
+ * Data packets can be run length encoded. A control byte with the top bit
+ * set means the next byte repeats (control & 0x7F) + 2 times, otherwise the
+ * next control + 1 bytes are copied as is
+ */
+fn decompress(data: &[u8], out: &mut Vec<u8>) {
+    let mut i = 0;
+    while i < data.len() {
+        let control = data[i];
+        i += 1;
+        if control & 0x80 != 0 {
+            let Some(&byte) = data.get(i) else { break };
+            out.resize(out.len() + (control & 0x7F) as usize + 2, byte);
+            i += 1;
+        } else {
+            let end = (i + control as usize + 1).min(data.len());
+            out.extend_from_slice(&data[i..end]);
+            i = end;
+        }
+    }
+}
+
+impl Printout {
+    pub fn height(&self) -> usize {
+        return self.shades.len() / PRINTER_WIDTH;
+    }
+
+    /**
+     * Saving the sheet as an RGB PNG, with palette picking the color for each
+     * shade
+     */
+    pub fn write_png(&self, path: &Path, palette: &[u32; 4]) -> Result<(), Error> {
+        let mut encoder = png::Encoder::new(BufWriter::new(File::create(path)?), PRINTER_WIDTH as u32, self.height() as u32);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+
+        let pixels: Vec<u8> = self.shades.iter()
+            .flat_map(|&shade| {
+                let [_, red, green, blue] = palette[shade as usize].to_be_bytes();
+                [red, green, blue]
+            })
+            .collect();
+        encoder.write_header()
+            .and_then(|mut writer| writer.write_image_data(&pixels))
+            .map_err(|e| Error::Io(io::Error::other(e)))?;
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::*;
+    use crate::Gameboy;
+    use crate::game_cartridge::header::test_rom;
+
+    fn packet(command: u8, compressed: bool, data: &[u8]) -> Vec<u8> {
+        let mut body = vec![command, compressed as u8, data.len() as u8, (data.len() >> 8) as u8];
+        body.extend_from_slice(data);
+        let checksum = body.iter().fold(0u16, |sum, &byte| sum.wrapping_add(byte as u16));
+
+        let mut packet = MAGIC.to_vec();
+        packet.extend(body);
+        packet.extend_from_slice(&checksum.to_le_bytes());
+        packet.extend_from_slice(&[0, 0]);
+        return packet;
+    }
+
+    /**
+     * Sending a whole packet and getting back the two bytes the printer
+     * answered with at the end
+     */
+    fn send(printer: &mut Printer, packet: &[u8]) -> (u8, u8) {
+        let replies: Vec<u8> = packet.iter().map(|&byte| printer.transfer(byte)).collect();
+        assert!(replies[..replies.len() - 2].iter().all(|&reply| reply == 0));
+        return (replies[replies.len() - 2], replies[replies.len() - 1]);
+    }
+
+    fn printer() -> (Printer, Rc<RefCell<Vec<Printout>>>) {
+        let printouts = Rc::new(RefCell::new(Vec::new()));
+        let sink = printouts.clone();
+        return (Printer::new(Box::new(move |printout| sink.borrow_mut().push(printout))), printouts);
+    }
+
+    #[test]
+    fn answers_with_status() {
+        let (mut printer, _) = printer();
+        assert_eq!(send(&mut printer, &packet(INIT_COMMAND, false, &[])), (ALIVE, 0x00));
+        assert_eq!(send(&mut printer, &packet(DATA_COMMAND, false, &[0xFF; BYTES_PER_TILE_ROW * 2])), (ALIVE, STATUS_UNPROCESSED));
+
+        let mut bad_checksum = packet(STATUS_COMMAND, false, &[]);
+        bad_checksum[6] ^= 0xFF;
+        assert_eq!(send(&mut printer, &bad_checksum), (ALIVE, STATUS_UNPROCESSED | STATUS_CHECKSUM_ERROR));
+        assert_eq!(send(&mut printer, &packet(STATUS_COMMAND, false, &[])), (ALIVE, STATUS_UNPROCESSED));
+
+        assert_eq!(send(&mut printer, &packet(PRINT_COMMAND, false, &[1, 0x00, 0xE4, 0x40])), (ALIVE, STATUS_BUSY | STATUS_IMAGE_FULL));
+        for _ in 0..16 * PRINT_CLK_CYCLES_PER_LINE {
+            printer.cycle(&mut Vec::new(), &mut Vec::new());
+        }
+        assert_eq!(send(&mut printer, &packet(STATUS_COMMAND, false, &[])), (ALIVE, STATUS_IMAGE_FULL));
+    }
+
+    #[test]
+    fn decompresses_runs_and_literals() {
+        let mut out = Vec::new();
+        decompress(&[0x81, 0xAA, 0x01, 0x12, 0x34, 0x80, 0x00], &mut out);
+        assert_eq!(out, [0xAA, 0xAA, 0xAA, 0x12, 0x34, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn prints_with_palette_and_margins() {
+        let (mut printer, printouts) = printer();
+        send(&mut printer, &packet(INIT_COMMAND, false, &[]));
+
+        //A tile row where every pixel is color 3, as 3 runs of 0xFF
+        let compressed = [0xFF, 0xFF, 0xFF, 0xFF, 0x80 | (BYTES_PER_TILE_ROW - 2 * 129 - 2) as u8, 0xFF];
+        let mut tile_row = Vec::new();
+        decompress(&compressed, &mut tile_row);
+        assert_eq!(tile_row.len(), BYTES_PER_TILE_ROW);
+
+        send(&mut printer, &packet(DATA_COMMAND, true, &compressed));
+        send(&mut printer, &packet(DATA_COMMAND, false, &[]));
+        assert!(printouts.borrow().is_empty());
+
+        //Color 3 goes to light grey, one margin before and two after
+        send(&mut printer, &packet(PRINT_COMMAND, false, &[1, 0x12, 0b01_00_00_00, 0x40]));
+        let printouts = printouts.borrow();
+        assert_eq!(printouts.len(), 1);
+        let sheet = &printouts[0];
+        assert_eq!(sheet.height(), LINES_PER_MARGIN + 8 + 2 * LINES_PER_MARGIN);
+
+        let line = |y: usize| &sheet.shades[y * PRINTER_WIDTH..(y + 1) * PRINTER_WIDTH];
+        assert!(line(0).iter().all(|&shade| shade == 0));
+        assert!(line(LINES_PER_MARGIN).iter().all(|&shade| shade == 1));
+        assert!(line(LINES_PER_MARGIN + 7).iter().all(|&shade| shade == 1));
+        assert!(line(sheet.height() - 1).iter().all(|&shade| shade == 0));
+
+        let path = std::env::temp_dir().join(format!("bintboy-printout-{}.png", std::process::id()));
+        sheet.write_png(&path, &DEFAULT_PRINTER_PALETTE).unwrap();
+        let mut reader = png::Decoder::new(File::open(&path).unwrap()).read_info().unwrap();
+        let mut pixels = vec![0; reader.output_buffer_size()];
+        reader.next_frame(&mut pixels).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!((reader.info().width, reader.info().height), (PRINTER_WIDTH as u32, sheet.height() as u32));
+        assert_eq!(pixels[LINES_PER_MARGIN * PRINTER_WIDTH * 3..][..3], [0xC0, 0xC0, 0xC0]);
+    }
+
+    #[test]
+    fn gameboy_gets_answers_over_serial() {
+        let init = packet(INIT_COMMAND, false, &[]);
+
+        //Sends the packet at 0x200 a byte at a time and saves what came back from 0xC000 on
+        let mut rom = test_rom(&[
+            0x21, 0x00, 0x02,       //LD HL, 0x200
+            0x11, 0x00, 0xC0,       //LD DE, 0xC000
+            0x06, init.len() as u8, //LD B, packet length
+            0x2A, 0xE0, 0x01,       //LD A, (HL+); LDH (SB), A
+            0x3E, 0x81, 0xE0, 0x02, //LD A, 0x81; LDH (SC), A
+            0xF0, 0x02, 0xCB, 0x7F, //LDH A, (SC); BIT 7, A
+            0x20, 0xFA,             //JR NZ, back until the transfer is done
+            0xF0, 0x01, 0x12, 0x13, //LDH A, (SB); LD (DE), A; INC DE
+            0x05, 0x20, 0xEC,       //DEC B; JR NZ, to the next byte
+            0x18, 0xFE,             //JR to itself
+        ]);
+        rom[0x200..0x200 + init.len()].copy_from_slice(&init);
+
+        let (printer, _) = printer();
+        let mut gameboy = Gameboy::new();
+        gameboy.load_rom(&rom).unwrap();
+        gameboy.set_link_cable(Some(Box::new(printer)));
+        gameboy.run_frame();
+
+        let replies: Vec<u8> = (0..init.len() as u16).map(|i| gameboy.read_byte(0xC000 + i)).collect();
+        assert_eq!(replies[..init.len() - 2], [0; 8]);
+        assert_eq!(replies[init.len() - 2..], [ALIVE, 0x00]);
+    }
+}
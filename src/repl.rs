@@ -0,0 +1,262 @@
+use std::io::{self, BufRead, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use bintboy::debugger::{self, Breakpoint, Debugger, StopReason};
+use bintboy::Gameboy;
+use bintboy::gameboy::{Watchpoint, WatchKind};
+
+use crate::disasm::parse_address;
+
+const HEXDUMP_WIDTH: u16 = 16;
+const DEFAULT_HEXDUMP_LEN: u16 = 64;
+const LIST_BEFORE: usize = 3;
+const LIST_AFTER: usize = 8;
+
+const HELP: &str = "\
+Commands (addresses are hex, counts are decimal):
+  c, continue           Keep running until a breakpoint, watchpoint or Ctrl-C
+  s, step [count]       Run one instruction, going into calls
+  n, next               Run one instruction, running calls all the way through
+  f, finish             Run until the current function returns
+  b, break [bank:]addr  Stop before the instruction at addr. The bank only matters from 4000 to 7FFF
+  d, delete [bank:]addr Remove a breakpoint
+  bl, breakpoints       List the breakpoints
+  w, watch [r|w|c] start[-end]
+                        Stop after the game reads, writes or changes memory in the range. Changes if not given
+  dw, unwatch [r|w|c] start[-end]
+                        Remove a watchpoint
+  wl, watchpoints       List the watchpoints
+  r, regs               Show the cpu registers
+  x addr [count]        Hexdump count bytes of memory, 64 if not given
+  l, list [addr]        Disassemble around PC, or starting at addr
+  q, quit               Quit the emulator
+An empty line repeats the last command";
+
+/**
+ * The prompt for --debug. The emulator runs normally until a breakpoint gets
+ * hit or Ctrl-C is pressed, then we sit at the prompt until told to continue
+ */
+pub struct Repl {
+    debugger: Debugger,
+    interrupt: Arc<AtomicBool>,
+    last_command: String,
+}
+
+impl Repl {
+    pub fn new() -> Self {
+        let debugger = Debugger::new();
+        let interrupt = debugger.interrupt_flag();
+        let handler_interrupt = interrupt.clone();
+        if let Err(e) = ctrlc::set_handler(move || handler_interrupt.store(true, Ordering::Relaxed)) {
+            eprintln!("Unable to catch Ctrl-C, only breakpoints will stop the game: {e}");
+        }
+
+        Self { debugger, interrupt, last_command: String::new() }
+    }
+
+    /**
+     * Running a frame, dropping into the prompt if anything stops it. Returns
+     * false once the user wants to quit
+     */
+    pub fn run_frame(&mut self, gameboy: &mut Gameboy) -> bool {
+        return match self.debugger.run_frame(gameboy) {
+            Some(reason) => {
+                println!("{reason}");
+                self.prompt(gameboy)
+            },
+            None => true,
+        };
+    }
+
+    /**
+     * Taking commands until one of them says to continue (true) or quit (false)
+     */
+    pub fn prompt(&mut self, gameboy: &mut Gameboy) -> bool {
+        print_location(gameboy);
+        let stdin = io::stdin();
+        loop {
+            print!("(bintboy) ");
+            let _ = io::stdout().flush();
+
+            let mut line = String::new();
+            match stdin.lock().read_line(&mut line) {
+                Ok(0) | Err(_) => return false,     //Ctrl-D or stdin went away
+                Ok(_) => (),
+            }
+            let line = match line.trim() {
+                "" => self.last_command.clone(),
+                line => line.to_string(),
+            };
+            self.last_command = line.clone();
+
+            //Ctrl-C at the prompt shouldn't stop us right after continuing
+            self.interrupt.store(false, Ordering::Relaxed);
+
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let Some((&command, args)) = words.split_first() else { continue };
+            match self.run_command(gameboy, command, args) {
+                Ok(Some(keep_running)) => return keep_running,
+                Ok(None) => (),
+                Err(message) => println!("{message}"),
+            }
+        }
+    }
+
+    /**
+     * Some(true) to continue, Some(false) to quit and None to stay at the prompt
+     */
+    fn run_command(&mut self, gameboy: &mut Gameboy, command: &str, args: &[&str]) -> Result<Option<bool>, String> {
+        match command {
+            "c" | "continue" => return Ok(Some(true)),
+            "q" | "quit" => return Ok(Some(false)),
+            "s" | "step" => {
+                let count = match args.first() {
+                    Some(count) => count.parse::<u32>().map_err(|e| format!("{count}: {e}"))?,
+                    None => 1,
+                };
+                for _ in 0..count {
+                    let reason = self.debugger.step_into(gameboy);
+                    if reason != StopReason::Stepped {
+                        println!("{reason}");
+                        break;
+                    }
+                }
+                print_location(gameboy);
+            },
+            "n" | "next" => self.report_step(gameboy, Debugger::step_over),
+            "f" | "finish" => self.report_step(gameboy, Debugger::step_out),
+            "b" | "break" => {
+                let breakpoint = parse_breakpoint(args.first().ok_or("break needs an address")?)?;
+                self.debugger.add_breakpoint(breakpoint);
+                println!("Breakpoint at {breakpoint}");
+            },
+            "d" | "delete" => {
+                let breakpoint = parse_breakpoint(args.first().ok_or("delete needs an address")?)?;
+                if !self.debugger.remove_breakpoint(breakpoint) {
+                    return Err(format!("No breakpoint at {breakpoint}"));
+                }
+            },
+            "bl" | "breakpoints" => {
+                if self.debugger.breakpoints().is_empty() {
+                    println!("No breakpoints");
+                }
+                for breakpoint in self.debugger.breakpoints() {
+                    println!("{breakpoint}");
+                }
+            },
+            "w" | "watch" => {
+                let watchpoint = parse_watchpoint(args)?;
+                gameboy.add_watchpoint(watchpoint);
+                println!("Watching {watchpoint}");
+            },
+            "dw" | "unwatch" => {
+                let watchpoint = parse_watchpoint(args)?;
+                if !gameboy.remove_watchpoint(watchpoint) {
+                    return Err(format!("Not watching {watchpoint}"));
+                }
+            },
+            "wl" | "watchpoints" => {
+                if gameboy.watchpoints().is_empty() {
+                    println!("No watchpoints");
+                }
+                for watchpoint in gameboy.watchpoints() {
+                    println!("{watchpoint}");
+                }
+            },
+            "r" | "regs" => println!("{}  ROM bank {}", gameboy.registers(), gameboy.rom_bank()),
+            "x" => {
+                let address = parse_address(args.first().ok_or("x needs an address")?)?;
+                let len = match args.get(1) {
+                    Some(len) => len.parse::<u16>().map_err(|e| format!("{len}: {e}"))?,
+                    None => DEFAULT_HEXDUMP_LEN,
+                };
+                hexdump(gameboy, address, len);
+            },
+            "l" | "list" => {
+                let pc = gameboy.registers().pc;
+                let instructions = match args.first() {
+                    Some(address) => debugger::instructions_around(gameboy, parse_address(address)?, 0, LIST_AFTER),
+                    None => debugger::instructions_around(gameboy, pc, LIST_BEFORE, LIST_AFTER),
+                };
+                for instruction in instructions {
+                    let marker = if instruction.address == pc { "=>" } else { "  " };
+                    println!("{marker} {instruction}");
+                }
+            },
+            "h" | "help" => println!("{HELP}"),
+            _ => return Err(format!("Unknown command {command}, try help")),
+        }
+        return Ok(None);
+    }
+
+    fn report_step(&mut self, gameboy: &mut Gameboy, step: fn(&mut Debugger, &mut Gameboy) -> StopReason) {
+        let reason = step(&mut self.debugger, gameboy);
+        if reason != StopReason::Stepped {
+            println!("{reason}");
+        }
+        print_location(gameboy);
+    }
+}
+
+fn print_location(gameboy: &Gameboy) {
+    let pc = gameboy.registers().pc;
+    let bank = match pc {
+        0x0000..=0x3FFF => "00:".to_string(),
+        0x4000..=0x7FFF => format!("{:02X}:", gameboy.rom_bank()),
+        _ => String::new(),
+    };
+    println!("=> {bank}{}", debugger::instruction_at(gameboy, pc));
+}
+
+fn parse_breakpoint(text: &str) -> Result<Breakpoint, String> {
+    return match text.split_once(':') {
+        Some((bank, address)) => Ok(Breakpoint {
+            address: parse_address(address)?,
+            bank: Some(usize::from_str_radix(bank, 16).map_err(|e| format!("{bank}: {e}"))?),
+        }),
+        None => Ok(Breakpoint { address: parse_address(text)?, bank: None }),
+    };
+}
+
+fn parse_watchpoint(args: &[&str]) -> Result<Watchpoint, String> {
+    let (kind, range) = match args {
+        [range] => (WatchKind::Change, range),
+        [kind, range] => {
+            let kind = match *kind {
+                "r" | "read" => WatchKind::Read,
+                "w" | "write" => WatchKind::Write,
+                "c" | "change" => WatchKind::Change,
+                _ => return Err(format!("{kind}: watch for r, w or c")),
+            };
+            (kind, range)
+        },
+        _ => return Err("watch needs an address or start-end".to_string()),
+    };
+
+    let (start, end) = match range.split_once('-') {
+        Some((start, end)) => (parse_address(start)?, parse_address(end)?),
+        None => (parse_address(range)?, parse_address(range)?),
+    };
+    if start > end {
+        return Err(format!("{range}: the range ends before it starts"));
+    }
+    return Ok(Watchpoint { start, end, kind });
+}
+
+fn hexdump(gameboy: &Gameboy, address: u16, len: u16) {
+    let mut line_start = address;
+    let end = address as u32 + len as u32;
+    while (line_start as u32) < end {
+        let line_len = HEXDUMP_WIDTH.min((end - line_start as u32) as u16);
+        let bytes: Vec<u8> = (0..line_len).map(|i| gameboy.read_byte(line_start.wrapping_add(i))).collect();
+        let hex: Vec<String> = bytes.iter().map(|byte| format!("{byte:02X}")).collect();
+        let text: String = bytes.iter().map(|&byte| if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '.' }).collect();
+        println!("{line_start:04X}  {:<47}  {text}", hex.join(" "));
+
+        match line_start.checked_add(HEXDUMP_WIDTH) {
+            Some(next) => line_start = next,
+            None => break,
+        }
+    }
+}
@@ -0,0 +1,240 @@
+use std::collections::VecDeque;
+
+const ZERO_RUN_FLAG: u8 = 0x80;
+const MAX_RUN_LEN: usize = 0x80;
+
+/**
+ * How far back the Game Boy can rewind
+ */
+#[derive(Debug, Clone, Copy)]
+pub struct RewindConfig {
+    pub interval: u32,          //Take a snapshot every this many frames
+    pub depth: usize,           //How many snapshots to keep at most
+    pub memory_budget: usize,   //How many bytes the snapshots can take up at most
+}
+
+impl Default for RewindConfig {
+    fn default() -> Self {
+        Self {
+            interval: 2,
+            depth: 600,                     //20 seconds at 2 frames per snapshot
+            memory_budget: 64 * 1024 * 1024,
+        }
+    }
+}
+
+/**
+ * XOR of one snapshot against the next one, run length encoded. States can
+ * differ in length (the ppu fifos don't always hold the same amount), so we
+ * keep the length of the older one around
+ */
+struct Delta {
+    state_len: usize,
+    data: Vec<u8>,
+}
+
+/**
+ * Ring buffer of save states. Only the newest snapshot is kept whole, every
+ * older one is a delta against the one after it. Consecutive frames barely
+ * differ so the deltas are mostly zeros and compress really well
+ */
+pub struct RewindBuffer {
+    config: RewindConfig,
+    newest: Option<Vec<u8>>,
+    deltas: VecDeque<Delta>,    //Oldest first
+    used_bytes: usize,
+    frames_since_snapshot: u32,
+}
+
+impl RewindBuffer {
+    pub fn new(config: RewindConfig) -> Self {
+        Self {
+            config,
+            newest: None,
+            deltas: VecDeque::new(),
+            used_bytes: 0,
+            frames_since_snapshot: 0,
+        }
+    }
+
+    /**
+     * Should be called once per emulated frame. Returns true when it's time
+     * to take a snapshot
+     */
+    pub fn frame_completed(&mut self) -> bool {
+        self.frames_since_snapshot += 1;
+        if self.frames_since_snapshot >= self.config.interval {
+            self.frames_since_snapshot = 0;
+            return true;
+        }
+        return false;
+    }
+
+    pub fn push(&mut self, state: Vec<u8>) {
+        if let Some(newest) = self.newest.take() {
+            let delta = Delta {
+                state_len: newest.len(),
+                data: rle_encode(&xor(&newest, &state)),
+            };
+            self.used_bytes += delta.data.len();
+            self.used_bytes -= newest.len();
+            self.deltas.push_back(delta);
+        }
+
+        self.used_bytes += state.len();
+        self.newest = Some(state);
+
+        //Dropping the oldest snapshots until we're back under the limits
+        while self.len() > self.config.depth.max(1) || (self.used_bytes > self.config.memory_budget && !self.deltas.is_empty()) {
+            let oldest = self.deltas.pop_front().expect("There's always a delta while over the limits");
+            self.used_bytes -= oldest.data.len();
+        }
+    }
+
+    /**
+     * Taking the newest snapshot off the buffer. The one before it becomes
+     * the newest, so calling this repeatedly walks backwards in time
+     */
+    pub fn pop(&mut self) -> Option<Vec<u8>> {
+        let newest = self.newest.take()?;
+        self.used_bytes -= newest.len();
+
+        if let Some(delta) = self.deltas.pop_back() {
+            self.used_bytes -= delta.data.len();
+            let mut previous = xor(&newest, &rle_decode(&delta.data));
+            previous.truncate(delta.state_len);
+            self.used_bytes += previous.len();
+            self.newest = Some(previous);
+        }
+
+        //Starting the count over so we don't snapshot right after rewinding
+        self.frames_since_snapshot = 0;
+        return Some(newest);
+    }
+
+    /**
+     * How many snapshots we can still go back
+     */
+    pub fn len(&self) -> usize {
+        return match self.newest {
+            Some(_) => self.deltas.len() + 1,
+            None => 0,
+        };
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.newest.is_none();
+    }
+
+    pub fn used_bytes(&self) -> usize {
+        return self.used_bytes;
+    }
+}
+
+/**
+ * XORing two byte strings. The shorter one gets treated as if it was padded
+ * out with zeros
+ */
+fn xor(a: &[u8], b: &[u8]) -> Vec<u8> {
+    let mut result = vec![0; a.len().max(b.len())];
+    for (idx, byte) in result.iter_mut().enumerate() {
+        *byte = a.get(idx).copied().unwrap_or(0) ^ b.get(idx).copied().unwrap_or(0);
+    }
+    return result;
+}
+
+/**
+ * Each run starts with a control byte. If bit 7 is set it's a run of
+ * (bits 0-6) + 1 zeros, otherwise (bits 0-6) + 1 literal bytes follow it
+ */
+fn rle_encode(data: &[u8]) -> Vec<u8> {
+    let mut encoded = vec![];
+    let mut idx = 0;
+
+    while idx < data.len() {
+        let run_len = data[idx..].iter().take(MAX_RUN_LEN).take_while(|byte| **byte == 0).count();
+        if run_len > 0 {
+            encoded.push(ZERO_RUN_FLAG | (run_len - 1) as u8);
+            idx += run_len;
+        } else {
+            let literal_len = data[idx..].iter().take(MAX_RUN_LEN).take_while(|byte| **byte != 0).count();
+            encoded.push((literal_len - 1) as u8);
+            encoded.extend_from_slice(&data[idx..idx + literal_len]);
+            idx += literal_len;
+        }
+    }
+
+    return encoded;
+}
+
+fn rle_decode(encoded: &[u8]) -> Vec<u8> {
+    let mut data = vec![];
+    let mut idx = 0;
+
+    while idx < encoded.len() {
+        let control = encoded[idx];
+        let run_len = (control & !ZERO_RUN_FLAG) as usize + 1;
+        idx += 1;
+
+        if control & ZERO_RUN_FLAG != 0 {
+            data.resize(data.len() + run_len, 0);
+        } else {
+            data.extend_from_slice(&encoded[idx..idx + run_len]);
+            idx += run_len;
+        }
+    }
+
+    return data;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rle_round_trip() {
+        let mut data = vec![0; 1000];
+        data.extend((0..=255).cycle().take(700));
+        data.extend([0, 7, 0, 0, 9]);
+
+        let encoded = rle_encode(&data);
+        assert!(encoded.len() < data.len());
+        assert_eq!(rle_decode(&encoded), data);
+        assert!(rle_encode(&[]).is_empty());
+    }
+
+    #[test]
+    fn pops_states_newest_first() {
+        let mut rewind = RewindBuffer::new(RewindConfig { interval: 1, depth: 3, memory_budget: usize::MAX });
+        for state in [vec![1, 2, 3], vec![1, 2, 4, 5], vec![1, 9], vec![6, 6, 6]] {
+            rewind.push(state);
+        }
+
+        assert_eq!(rewind.len(), 3);
+        assert_eq!(rewind.pop(), Some(vec![6, 6, 6]));
+        assert_eq!(rewind.pop(), Some(vec![1, 9]));
+        assert_eq!(rewind.pop(), Some(vec![1, 2, 4, 5]));
+        assert_eq!(rewind.pop(), None);
+        assert_eq!(rewind.used_bytes(), 0);
+    }
+
+    #[test]
+    fn stays_under_memory_budget() {
+        let mut rewind = RewindBuffer::new(RewindConfig { interval: 2, depth: 100, memory_budget: 300 });
+        let state = |frame: u8| {
+            let mut state = vec![0; 200];
+            state[100] = frame;
+            state
+        };
+        for frame in 0..100u8 {
+            if rewind.frame_completed() {
+                rewind.push(state(frame));
+            }
+        }
+
+        assert!(rewind.used_bytes() <= 300);
+        assert!(rewind.len() > 10 && rewind.len() < 50);
+        assert_eq!(rewind.pop(), Some(state(99)));
+        assert_eq!(rewind.pop(), Some(state(97)));
+    }
+}
@@ -0,0 +1,139 @@
+use crate::error::Error;
+
+/**
+ * Builds up a save state. Everything gets written little endian in the order
+ * the components write it, so loading has to read it back in the same order
+ */
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl Default for StateWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StateWriter {
+    pub fn new() -> Self {
+        Self { data: vec![] }
+    }
+
+    pub fn write_u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn write_u16(&mut self, value: u16) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u32(&mut self, value: u32) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u64(&mut self, value: u64) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_bool(&mut self, value: bool) {
+        self.data.push(value as u8);
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.data.extend_from_slice(bytes);
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        return self.data;
+    }
+}
+
+/**
+ * Reads a save state back out. Running off the end of the data or reading
+ * a value that can't be valid means the state is corrupt
+ */
+pub struct StateReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, Error> {
+        return Ok(self.read_bytes(1)?[0]);
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, Error> {
+        return Ok(u16::from_le_bytes(self.read_array()?));
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, Error> {
+        return Ok(u32::from_le_bytes(self.read_array()?));
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, Error> {
+        return Ok(u64::from_le_bytes(self.read_array()?));
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool, Error> {
+        return match self.read_u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(Error::CorruptSaveState),
+        };
+    }
+
+    pub fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        let bytes = self.data.get(self.pos..self.pos + len).ok_or(Error::CorruptSaveState)?;
+        self.pos += len;
+        return Ok(bytes);
+    }
+
+    /**
+     * Filling a buffer that's already the right size
+     */
+    pub fn read_into(&mut self, buffer: &mut [u8]) -> Result<(), Error> {
+        buffer.copy_from_slice(self.read_bytes(buffer.len())?);
+        return Ok(());
+    }
+
+    pub fn read_array<const N: usize>(&mut self) -> Result<[u8; N], Error> {
+        let mut array = [0; N];
+        self.read_into(&mut array)?;
+        return Ok(array);
+    }
+
+    pub fn is_finished(&self) -> bool {
+        return self.pos == self.data.len();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_values() {
+        let mut writer = StateWriter::new();
+        writer.write_u8(0x12);
+        writer.write_u16(0x3456);
+        writer.write_u32(0x789ABCDE);
+        writer.write_u64(u64::MAX - 1);
+        writer.write_bool(true);
+        writer.write_bytes(&[1, 2, 3]);
+        let data = writer.into_bytes();
+
+        let mut reader = StateReader::new(&data);
+        assert_eq!(reader.read_u8().unwrap(), 0x12);
+        assert_eq!(reader.read_u16().unwrap(), 0x3456);
+        assert_eq!(reader.read_u32().unwrap(), 0x789ABCDE);
+        assert_eq!(reader.read_u64().unwrap(), u64::MAX - 1);
+        assert!(reader.read_bool().unwrap());
+        assert_eq!(reader.read_array::<3>().unwrap(), [1, 2, 3]);
+        assert!(reader.is_finished());
+        assert!(matches!(reader.read_u8(), Err(Error::CorruptSaveState)));
+    }
+}
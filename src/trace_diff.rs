@@ -0,0 +1,143 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+/**
+ * Entry point of the trace-diff subcommand. Goes through both traces a line
+ * at a time, since gameboy-doctor logs run to millions of lines, and stops at
+ * the first one that doesn't match. Exits with 1 if they differ
+ */
+pub fn print_trace_diff(ours_path: &Path, reference_path: &Path) {
+    let difference = match first_difference(read_lines(ours_path), read_lines(reference_path)) {
+        Comparison::Match { lines } => {
+            println!("Traces match, {lines} lines");
+            return;
+        },
+        Comparison::Differ(difference) => difference,
+    };
+
+    println!("First difference at line {}", difference.line_number);
+    if let Some(previous) = &difference.previous {
+        println!("  previous:  {}", previous.trim_end());
+    }
+    println!("  ours:      {}", difference.ours.as_deref().unwrap_or("<end of trace>").trim_end());
+    println!("  reference: {}", difference.reference.as_deref().unwrap_or("<end of trace>").trim_end());
+    if let (Some(our_line), Some(reference_line)) = (&difference.ours, &difference.reference) {
+        let fields = differing_fields(our_line, reference_line);
+        if !fields.is_empty() {
+            println!("  differing: {}", fields.join(", "));
+        }
+    }
+    std::process::exit(1);
+}
+
+#[derive(Debug, PartialEq)]
+enum Comparison {
+    Match { lines: usize },
+    Differ(Difference),
+}
+
+/**
+ * The first line two traces don't agree on. A side is None if its trace
+ * ended before the other one did
+ */
+#[derive(Debug, PartialEq)]
+struct Difference {
+    line_number: usize,
+    previous: Option<String>,       //The last line they agreed on
+    ours: Option<String>,
+    reference: Option<String>,
+}
+
+/**
+ * Lines count as the same if they only differ in trailing whitespace, so a
+ * reference log with CRLF line endings still matches
+ */
+fn first_difference(mut ours: impl Iterator<Item = String>, mut reference: impl Iterator<Item = String>) -> Comparison {
+    let mut previous = None;
+    let mut line_number = 0;
+
+    loop {
+        line_number += 1;
+        let (our_line, reference_line) = match (ours.next(), reference.next()) {
+            (None, None) => return Comparison::Match { lines: line_number - 1 },
+            lines => lines,
+        };
+        if our_line.as_deref().map(str::trim_end) == reference_line.as_deref().map(str::trim_end) {
+            previous = our_line;
+            continue;
+        }
+        return Comparison::Differ(Difference { line_number, previous, ours: our_line, reference: reference_line });
+    }
+}
+
+/**
+ * Which of the NAME:VALUE fields don't match. Fields only one side has
+ * count as differing too
+ */
+fn differing_fields<'a>(ours: &'a str, reference: &'a str) -> Vec<&'a str> {
+    let ours: Vec<(&str, &str)> = ours.split_whitespace().filter_map(|field| field.split_once(':')).collect();
+    let reference: Vec<(&str, &str)> = reference.split_whitespace().filter_map(|field| field.split_once(':')).collect();
+
+    let mut fields = Vec::new();
+    for &(name, value) in &ours {
+        if reference.iter().all(|&other| other != (name, value)) {
+            fields.push(name);
+        }
+    }
+    for &(name, _) in &reference {
+        if ours.iter().all(|&(other, _)| other != name) {
+            fields.push(name);
+        }
+    }
+    return fields;
+}
+
+fn read_lines(path: &Path) -> impl Iterator<Item = String> + '_ {
+    let trace = match File::open(path) {
+        Ok(file) => BufReader::new(file),
+        Err(e) => exit_with_error(path, e),
+    };
+    return trace.lines().map(move |line| line.unwrap_or_else(|e| exit_with_error(path, e)));
+}
+
+fn exit_with_error(path: &Path, e: std::io::Error) -> ! {
+    eprintln!("Unable to read {}: {e}", path.display());
+    std::process::exit(1);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compare(ours: &str, reference: &str) -> Comparison {
+        return first_difference(ours.as_bytes().lines().map(Result::unwrap), reference.as_bytes().lines().map(Result::unwrap));
+    }
+
+    #[test]
+    fn finds_the_differing_register() {
+        let ours = "A:01 F:B0 SP:FFFE PC:0100\nA:01 F:B0 SP:FFFE PC:0101\n";
+        let reference = "A:01 F:B0 SP:FFFE PC:0100\nA:01 F:80 SP:FFFE PC:0101\n";
+        let Comparison::Differ(difference) = compare(ours, reference) else { panic!("F differs") };
+        assert_eq!(difference.line_number, 2);
+        assert_eq!(difference.previous.as_deref(), Some("A:01 F:B0 SP:FFFE PC:0100"));
+        assert_eq!(differing_fields(difference.ours.as_deref().unwrap(), difference.reference.as_deref().unwrap()), ["F"]);
+
+        //A field only one side has counts too
+        assert_eq!(differing_fields("A:01 PC:0100", "A:01 PC:0100 IE:00"), ["IE"]);
+    }
+
+    #[test]
+    fn stops_where_the_shorter_trace_ends() {
+        let Comparison::Differ(difference) = compare("PC:0100\n", "PC:0100\nPC:0101\n") else { panic!("ours ended first") };
+        assert_eq!((difference.line_number, difference.ours, difference.reference.as_deref()), (2, None, Some("PC:0101")));
+
+        let Comparison::Differ(difference) = compare("PC:0100\nPC:0101\n", "PC:0100\n") else { panic!("the reference ended first") };
+        assert_eq!((difference.ours.as_deref(), difference.reference), (Some("PC:0101"), None));
+    }
+
+    #[test]
+    fn ignores_trailing_whitespace_and_crlf() {
+        assert_eq!(compare("A:01 PC:0100\nA:01 PC:0101\n", "A:01 PC:0100 \r\nA:01 PC:0101\t\r\n"), Comparison::Match { lines: 2 });
+    }
+}
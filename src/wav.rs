@@ -0,0 +1,142 @@
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::error::Error;
+
+const HEADER_LEN: u32 = 44;
+const BITS_PER_SAMPLE: u16 = 16;
+const BYTES_PER_FRAME: u32 = 2 * BITS_PER_SAMPLE as u32 / 8;
+const MAX_DATA_LEN: u32 = u32::MAX - (HEADER_LEN - 8);      //The RIFF size has to fit in 32 bits too
+
+/**
+ * Writes 16 bit PCM stereo WAV files. The sizes in the header can't be known
+ * until we're done, so they get filled in by finish
+ */
+pub struct WavWriter {
+    file: BufWriter<File>,
+    frames_written: u32,
+    finished: bool,
+    full: bool,                 //Hit the 4GB limit of the header's sizes
+}
+
+impl WavWriter {
+    pub fn create(path: &Path, sample_rate: u32) -> Result<Self, Error> {
+        let mut file = BufWriter::new(File::create(path)?);
+        let channels: u16 = 2;
+        let block_align = channels * BITS_PER_SAMPLE / 8;
+
+        file.write_all(b"RIFF")?;
+        file.write_all(&0u32.to_le_bytes())?;                  //Filled in by finish
+        file.write_all(b"WAVE")?;
+        file.write_all(b"fmt ")?;
+        file.write_all(&16u32.to_le_bytes())?;
+        file.write_all(&1u16.to_le_bytes())?;                  //PCM
+        file.write_all(&channels.to_le_bytes())?;
+        file.write_all(&sample_rate.to_le_bytes())?;
+        file.write_all(&(sample_rate * block_align as u32).to_le_bytes())?;
+        file.write_all(&block_align.to_le_bytes())?;
+        file.write_all(&BITS_PER_SAMPLE.to_le_bytes())?;
+        file.write_all(b"data")?;
+        file.write_all(&0u32.to_le_bytes())?;                  //Filled in by finish
+
+        Ok(Self {
+            file,
+            frames_written: 0,
+            finished: false,
+            full: false,
+        })
+    }
+
+    /**
+     * Samples get clamped to -1.0 to 1.0 before being converted. Once the
+     * file is as big as a WAV can get, everything after that gets dropped
+     */
+    pub fn write_frame(&mut self, left: f32, right: f32) -> Result<(), Error> {
+        if self.data_len() > MAX_DATA_LEN - BYTES_PER_FRAME {
+            if !self.full {
+                eprintln!("WAV file reached the 4GB limit, the rest of the recording is being dropped");
+                self.full = true;
+            }
+            return Ok(());
+        }
+
+        for sample in [left, right] {
+            let value = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+            self.file.write_all(&value.to_le_bytes())?;
+        }
+        self.frames_written += 1;
+        return Ok(());
+    }
+
+    /**
+     * Filling in the sizes in the header and flushing everything out. This
+     * also happens when the writer gets dropped, but then errors get lost
+     */
+    pub fn finish(&mut self) -> Result<(), Error> {
+        if self.finished {
+            return Ok(());
+        }
+        self.finished = true;
+
+        let data_len = self.data_len();
+        self.file.seek(SeekFrom::Start(4))?;
+        self.file.write_all(&(HEADER_LEN - 8 + data_len).to_le_bytes())?;
+        self.file.seek(SeekFrom::Start(40))?;
+        self.file.write_all(&data_len.to_le_bytes())?;
+        self.file.flush()?;
+        return Ok(());
+    }
+
+    fn data_len(&self) -> u32 {
+        return self.frames_written * BYTES_PER_FRAME;
+    }
+}
+
+impl Drop for WavWriter {
+    fn drop(&mut self) {
+        if let Err(e) = self.finish() {
+            eprintln!("Unable to finish writing WAV file: {e}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_header_and_samples() {
+        let path = std::env::temp_dir().join(format!("bintboy_wav_test_{}.wav", std::process::id()));
+        let mut wav = WavWriter::create(&path, 131072).unwrap();
+        wav.write_frame(1.0, -1.0).unwrap();
+        wav.write_frame(0.0, 2.0).unwrap();
+        wav.finish().unwrap();
+        drop(wav);
+
+        let data = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(data.len(), HEADER_LEN as usize + 8);
+        assert_eq!(&data[0..4], b"RIFF");
+        assert_eq!(u32::from_le_bytes(data[4..8].try_into().unwrap()), HEADER_LEN - 8 + 8);
+        assert_eq!(u32::from_le_bytes(data[24..28].try_into().unwrap()), 131072);
+        assert_eq!(u32::from_le_bytes(data[40..44].try_into().unwrap()), 8);
+        assert_eq!(&data[44..], &[0xFF, 0x7F, 0x01, 0x80, 0x00, 0x00, 0xFF, 0x7F]);
+    }
+
+    #[test]
+    fn stops_before_the_sizes_overflow() {
+        let path = std::env::temp_dir().join(format!("bintboy_wav_full_test_{}.wav", std::process::id()));
+        let mut wav = WavWriter::create(&path, 131072).unwrap();
+        wav.frames_written = MAX_DATA_LEN / BYTES_PER_FRAME - 1;
+        wav.write_frame(0.0, 0.0).unwrap();
+        wav.write_frame(0.0, 0.0).unwrap();
+        let frames_written = wav.frames_written;
+        wav.finished = true;
+        drop(wav);
+
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(frames_written, MAX_DATA_LEN / BYTES_PER_FRAME);
+        assert!((HEADER_LEN - 8).checked_add(frames_written * BYTES_PER_FRAME).is_some());
+    }
+}